@@ -1,10 +1,25 @@
 use anyhow::Result;
 use clap::Parser;
 use hamilton::{
-    configuration, driver::hamilton_driver_from_config, error::ErrorWrapper,
-    gamepad::start_gamepad_loop, ioc::IocContainer, lidar::Lidar, logging,
+    collision::{telemetry::start_collision_telemetry, CollisionDetector},
+    configuration,
+    driver::hamilton_driver_from_config,
+    error::ErrorWrapper,
+    gamepad::{feedback::start_feedback_publisher, start_gamepad_loop},
+    ioc::IocContainer,
+    lidar::{
+        control::start_lidar_control, publisher::start_scan_publisher,
+        recording::start_scan_recorder, Lidar,
+    },
+    localisation::start_localisation,
+    logging,
+    navigation::{
+        mission::start_mission_executor, planner::GlobalPlanner, start_navigation_loop,
+        NavigationController,
+    },
 };
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
+use tokio::sync::Mutex;
 use zenoh::prelude::r#async::*;
 
 #[derive(Parser, Debug)]
@@ -32,9 +47,11 @@ async fn main() -> Result<()> {
 
     let body_config = app_config.body.clone();
 
-    if let Some(lidar_config) = &app_config.lidar {
-        let _lidar_driver = Lidar::open(lidar_config.clone())?;
-    }
+    let lidar = if let Some(lidar_config) = &app_config.lidar {
+        Some(Lidar::open(lidar_config.clone())?)
+    } else {
+        None
+    };
 
     let driver = hamilton_driver_from_config(body_config).await?;
 
@@ -49,7 +66,55 @@ async fn main() -> Result<()> {
     let ioc_container = IocContainer::global_instance();
     ioc_container.register_arc(zenoh_session.clone());
 
-    start_gamepad_loop(zenoh_session, driver).await?;
+    let feedback = start_feedback_publisher(zenoh_session.clone());
+
+    if let (Some(lidar), Some(lidar_config)) = (&lidar, &app_config.lidar) {
+        start_scan_publisher(zenoh_session.clone(), lidar, lidar_config.publish.clone());
+        start_scan_recorder(zenoh_session.clone(), lidar, lidar_config.clone()).await?;
+        start_lidar_control(
+            zenoh_session.clone(),
+            lidar.clone(),
+            lidar_config.idle_timeout(),
+        )
+        .await?;
+    }
+
+    let mut collision_detector = CollisionDetector::from_config(lidar, &app_config.collision);
+    collision_detector.set_event_sender(start_collision_telemetry(
+        zenoh_session.clone(),
+        app_config.collision.telemetry.clone(),
+    ));
+    collision_detector.set_feedback(feedback.clone());
+    let mut navigation = NavigationController::new(
+        driver,
+        collision_detector.clone(),
+        app_config.navigation.clone(),
+    );
+    navigation.set_feedback(feedback.clone());
+    if let Some(planner) = GlobalPlanner::from_config(app_config.navigation.planner.clone())? {
+        navigation.set_planner(planner);
+    }
+    let navigation = Arc::new(Mutex::new(navigation));
+
+    let localiser = start_localisation(zenoh_session.clone(), &app_config.localisation).await?;
+    start_navigation_loop(zenoh_session.clone(), navigation.clone(), localiser.clone()).await?;
+    start_mission_executor(
+        zenoh_session.clone(),
+        navigation.clone(),
+        localiser,
+        app_config.navigation.mission.clone(),
+        app_config.navigation.docking.clone(),
+    )
+    .await?;
+
+    start_gamepad_loop(
+        zenoh_session,
+        navigation,
+        collision_detector,
+        feedback,
+        app_config.gamepad.clone(),
+    )
+    .await?;
 
     tokio::signal::ctrl_c().await?;
 
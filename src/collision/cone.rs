@@ -0,0 +1,91 @@
+use super::{CollisionCheck, CollisionChecker, CollisionReason};
+use crate::holonomic_controller::MoveCommand;
+use nalgebra as na;
+use serde::Deserialize;
+
+fn default_safe_distance() -> f32 {
+    0.3
+}
+
+fn default_half_angle_deg() -> f32 {
+    45.0
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ConeConfig {
+    /// Points closer than this in meters block translation
+    #[serde(default = "default_safe_distance")]
+    pub safe_distance: f32,
+    /// Half width of the cone around the direction of travel
+    #[serde(default = "default_half_angle_deg")]
+    pub half_angle_deg: f32,
+}
+
+impl Default for ConeConfig {
+    fn default() -> Self {
+        Self {
+            safe_distance: default_safe_distance(),
+            half_angle_deg: default_half_angle_deg(),
+        }
+    }
+}
+
+/// Blocks translation when anything is close in a cone around the direction of travel
+///
+/// This is the most primitive collider possible. Rotation is never blocked.
+pub struct ConeChecker {
+    config: ConeConfig,
+}
+
+impl ConeChecker {
+    pub fn new(config: ConeConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl CollisionChecker for ConeChecker {
+    fn check(&self, command: &MoveCommand, obstacles: &[na::Point2<f32>]) -> CollisionCheck {
+        let direction = na::Vector2::new(command.forward(), command.strafe());
+        if direction.norm() <= f32::EPSILON {
+            return CollisionCheck::clear(command);
+        }
+        let half_angle = self.config.half_angle_deg.to_radians();
+        let blocked = obstacles.iter().any(|point| {
+            point.coords.norm() <= self.config.safe_distance
+                && direction.angle(&point.coords) <= half_angle
+        });
+        if blocked {
+            CollisionCheck {
+                command: command.with_rotation_only(),
+                reason: CollisionReason::TranslationBlocked,
+            }
+        } else {
+            CollisionCheck::clear(command)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_translation_towards_close_points() {
+        let checker = ConeChecker::new(ConeConfig::default());
+        // ahead and to the left
+        let points = [na::Point2::new(0.2, 0.05), na::Point2::new(0.05, 0.25)];
+
+        let forward = checker.check(&MoveCommand::new(0.5, 0.0, 0.2), &points);
+        assert_eq!(forward.reason, CollisionReason::TranslationBlocked);
+        assert_eq!(forward.command, MoveCommand::new(0.0, 0.0, 0.2));
+        let left = checker.check(&MoveCommand::new(0.0, 0.5, 0.0), &points);
+        assert_eq!(left.reason, CollisionReason::TranslationBlocked);
+
+        let backwards = checker.check(&MoveCommand::new(-0.5, 0.0, 0.0), &points);
+        assert_eq!(backwards.reason, CollisionReason::Clear);
+        let right = checker.check(&MoveCommand::new(0.0, -0.5, 0.0), &points);
+        assert_eq!(right.reason, CollisionReason::Clear);
+        let turning = checker.check(&MoveCommand::new(0.0, 0.0, 0.5), &points);
+        assert_eq!(turning.reason, CollisionReason::Clear);
+    }
+}
@@ -0,0 +1,493 @@
+use super::{CollisionCheck, CollisionChecker, CollisionReason};
+use crate::{holonomic_controller::MoveCommand, navigation::Pose2d};
+use nalgebra as na;
+use serde::{de::Error, Deserialize, Deserializer};
+
+fn default_footprint() -> Footprint {
+    Footprint::Rectangle {
+        length: 0.4,
+        width: 0.4,
+    }
+}
+
+fn default_safety_margin() -> f32 {
+    0.05
+}
+
+fn default_horizon_s() -> f32 {
+    0.5
+}
+
+fn default_step_s() -> f32 {
+    0.1
+}
+
+fn default_max_linear_speed() -> f32 {
+    0.5
+}
+
+fn default_max_yaw_rate() -> f32 {
+    1.5
+}
+
+fn default_slow_down_distance() -> f32 {
+    0.6
+}
+
+fn default_stop_distance() -> f32 {
+    0.05
+}
+
+/// Resolution of the clearance search in meters
+const CLEARANCE_STEP: f32 = 0.01;
+
+/// Sweep step has to be above zero for the sweep to end
+fn positive_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if !(value.is_finite() && value > 0.0) {
+        return Err(D::Error::custom(format!(
+            "expected seconds above zero, got {}",
+            value
+        )));
+    }
+    Ok(value)
+}
+
+fn non_negative_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if !(value.is_finite() && value >= 0.0) {
+        return Err(D::Error::custom(format!(
+            "expected seconds of at least zero, got {}",
+            value
+        )));
+    }
+    Ok(value)
+}
+
+fn polygon_points<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(f32, f32)>, D::Error> {
+    let points = Vec::<(f32, f32)>::deserialize(deserializer)?;
+    if points.len() < 3 {
+        return Err(D::Error::custom(format!(
+            "footprint polygon needs at least 3 points, got {}",
+            points.len()
+        )));
+    }
+    Ok(points)
+}
+
+/// Outline of the robot in the robot frame
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Footprint {
+    /// Rectangle centered on the body origin in meters
+    Rectangle { length: f32, width: f32 },
+    /// Polygon vertices in meters in either winding order
+    Polygon {
+        #[serde(deserialize_with = "polygon_points")]
+        points: Vec<(f32, f32)>,
+    },
+}
+
+impl Footprint {
+    pub fn vertices(&self) -> Vec<na::Point2<f32>> {
+        match self {
+            Footprint::Rectangle { length, width } => {
+                let (x, y) = (length / 2.0, width / 2.0);
+                vec![
+                    na::Point2::new(x, y),
+                    na::Point2::new(-x, y),
+                    na::Point2::new(-x, -y),
+                    na::Point2::new(x, -y),
+                ]
+            }
+            Footprint::Polygon { points } => points
+                .iter()
+                .map(|(x, y)| na::Point2::new(*x, *y))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FootprintConfig {
+    #[serde(default = "default_footprint")]
+    pub footprint: Footprint,
+    /// Extra clearance around the footprint in meters
+    #[serde(default = "default_safety_margin")]
+    pub safety_margin: f32,
+    /// How far ahead the footprint is swept along the commanded motion
+    #[serde(
+        default = "default_horizon_s",
+        deserialize_with = "non_negative_seconds"
+    )]
+    pub horizon_s: f32,
+    #[serde(default = "default_step_s", deserialize_with = "positive_seconds")]
+    pub step_s: f32,
+    /// Speed in m/s at full forward or strafe command
+    #[serde(default = "default_max_linear_speed")]
+    pub max_linear_speed: f32,
+    /// Rotation speed in rad/s at full yaw command
+    #[serde(default = "default_max_yaw_rate")]
+    pub max_yaw_rate: f32,
+    /// Clearance along the commanded direction below which translation slows down
+    #[serde(default = "default_slow_down_distance")]
+    pub slow_down_distance: f32,
+    /// Clearance along the commanded direction at which translation stops
+    #[serde(default = "default_stop_distance")]
+    pub stop_distance: f32,
+}
+
+impl Default for FootprintConfig {
+    fn default() -> Self {
+        Self {
+            footprint: default_footprint(),
+            safety_margin: default_safety_margin(),
+            horizon_s: default_horizon_s(),
+            step_s: default_step_s(),
+            max_linear_speed: default_max_linear_speed(),
+            max_yaw_rate: default_max_yaw_rate(),
+            slow_down_distance: default_slow_down_distance(),
+            stop_distance: default_stop_distance(),
+        }
+    }
+}
+
+/// Sweeps the robot footprint along the command
+///
+/// Translation is slowed down with the clearance along the commanded direction,
+/// falling back to rotation only and then stopping when the sweep still collides.
+pub struct FootprintChecker {
+    config: FootprintConfig,
+}
+
+impl FootprintChecker {
+    pub fn new(config: FootprintConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl CollisionChecker for FootprintChecker {
+    fn check(&self, command: &MoveCommand, obstacles: &[na::Point2<f32>]) -> CollisionCheck {
+        let scale = speed_scale(
+            translation_clearance(obstacles, command, &self.config),
+            &self.config,
+        );
+        let scaled = MoveCommand::new(
+            command.forward() * scale,
+            command.strafe() * scale,
+            command.yaw(),
+        );
+        let translating = command.forward() != 0.0 || command.strafe() != 0.0;
+        if scale > 0.0 && !sweep_collides(obstacles, &scaled, &self.config) {
+            let reason = if scale < 1.0 && translating {
+                CollisionReason::SlowedDown { scale }
+            } else {
+                CollisionReason::Clear
+            };
+            return CollisionCheck {
+                command: scaled,
+                reason,
+            };
+        }
+        let rotation_only = command.with_rotation_only();
+        if !sweep_collides(obstacles, &rotation_only, &self.config) {
+            CollisionCheck {
+                command: rotation_only,
+                reason: CollisionReason::TranslationBlocked,
+            }
+        } else {
+            CollisionCheck::blocked()
+        }
+    }
+}
+
+/// Whether any point comes within the safety margin of the swept footprint
+///
+/// Points already within the margin only block motion that brings them closer
+/// so that the robot can always back away.
+fn sweep_collides(
+    points: &[na::Point2<f32>],
+    command: &MoveCommand,
+    config: &FootprintConfig,
+) -> bool {
+    if command.is_stopped() {
+        return false;
+    }
+    let footprint = config.footprint.vertices();
+    let step = Pose2d::new(
+        (
+            command.forward() * config.max_linear_speed * config.step_s,
+            command.strafe() * config.max_linear_speed * config.step_s,
+        ),
+        command.yaw() * config.max_yaw_rate * config.step_s,
+    );
+    let steps = (config.horizon_s / config.step_s).ceil().max(1.0) as usize;
+    let poses: Vec<_> = std::iter::successors(Some(step.clone()), |pose| Some(pose.compose(&step)))
+        .take(steps)
+        .collect();
+
+    // nothing further than this can be reached within the horizon,
+    // diagonal commands go further than either axis alone
+    let translation = na::Vector2::new(command.forward(), command.strafe()).norm();
+    let reach = footprint
+        .iter()
+        .map(|vertex| vertex.coords.norm())
+        .fold(0.0, f32::max)
+        + translation * config.max_linear_speed * config.horizon_s
+        + config.safety_margin;
+
+    points
+        .iter()
+        .filter(|point| point.coords.norm() < reach)
+        .any(|point| {
+            let initial = signed_distance(&footprint, point);
+            poses.iter().any(|pose| {
+                let distance = signed_distance(&footprint, &pose.inverse_transform_point(point));
+                distance < config.safety_margin && distance < initial
+            })
+        })
+}
+
+/// How far the footprint can translate along the command before a point gets within the safety margin
+///
+/// Only searched up to the slow down distance, infinite beyond that or when not translating.
+fn translation_clearance(
+    points: &[na::Point2<f32>],
+    command: &MoveCommand,
+    config: &FootprintConfig,
+) -> f32 {
+    let direction = na::Vector2::new(command.forward(), command.strafe());
+    if direction.norm() <= f32::EPSILON {
+        return f32::INFINITY;
+    }
+    let direction = direction.normalize();
+    let footprint = config.footprint.vertices();
+    let reach = footprint
+        .iter()
+        .map(|vertex| vertex.coords.norm())
+        .fold(0.0, f32::max)
+        + config.slow_down_distance
+        + config.safety_margin;
+    let nearby: Vec<_> = points
+        .iter()
+        .filter(|point| point.coords.norm() < reach)
+        .map(|point| (point, signed_distance(&footprint, point)))
+        .collect();
+
+    let steps = (config.slow_down_distance / CLEARANCE_STEP).ceil() as usize;
+    (0..=steps)
+        .map(|step| step as f32 * CLEARANCE_STEP)
+        .find(|travel| {
+            nearby.iter().any(|(point, initial)| {
+                let moved = na::Point2::from(point.coords - direction * *travel);
+                let distance = signed_distance(&footprint, &moved);
+                distance < config.safety_margin && distance < *initial
+            })
+        })
+        .unwrap_or(f32::INFINITY)
+}
+
+/// Linear ramp from 0 at the stop distance to 1 at the slow down distance
+fn speed_scale(clearance: f32, config: &FootprintConfig) -> f32 {
+    if clearance >= config.slow_down_distance {
+        return 1.0;
+    }
+    let range = config.slow_down_distance - config.stop_distance;
+    if range <= f32::EPSILON {
+        return if clearance > config.stop_distance {
+            1.0
+        } else {
+            0.0
+        };
+    }
+    ((clearance - config.stop_distance) / range).clamp(0.0, 1.0)
+}
+
+/// Distance from a point to a polygon outline, negative inside
+fn signed_distance(polygon: &[na::Point2<f32>], point: &na::Point2<f32>) -> f32 {
+    let mut inside = false;
+    let mut distance = f32::INFINITY;
+    for (index, a) in polygon.iter().enumerate() {
+        let b = &polygon[(index + 1) % polygon.len()];
+        let edge = b - a;
+        let t = ((point - a).dot(&edge) / edge.norm_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+        distance = distance.min(na::distance(point, &(a + edge * t)));
+        // even-odd rule
+        if (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) / edge.y * edge.x {
+            inside = !inside;
+        }
+    }
+    if inside {
+        -distance
+    } else {
+        distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wall across the front of the robot
+    fn wall_ahead(distance: f32) -> Vec<na::Point2<f32>> {
+        (-10..=10)
+            .map(|i| na::Point2::new(distance, i as f32 * 0.05))
+            .collect()
+    }
+
+    fn is_safe(points: &[na::Point2<f32>], command: MoveCommand, config: &FootprintConfig) -> bool {
+        !sweep_collides(points, &command, config)
+    }
+
+    fn scale(points: &[na::Point2<f32>], command: MoveCommand, config: &FootprintConfig) -> f32 {
+        speed_scale(translation_clearance(points, &command, config), config)
+    }
+
+    #[test]
+    fn blocks_moves_into_obstacles() {
+        let config = FootprintConfig::default();
+        // 0.2m half length, 0.05m margin and 0.125m of travel
+        let points = wall_ahead(0.35);
+        assert!(!is_safe(&points, MoveCommand::new(0.5, 0.0, 0.0), &config));
+        assert!(is_safe(&points, MoveCommand::new(-0.5, 0.0, 0.0), &config));
+        assert!(is_safe(&points, MoveCommand::new(0.0, 0.5, 0.0), &config));
+        assert!(is_safe(&points, MoveCommand::default(), &config));
+
+        let far = wall_ahead(0.6);
+        assert!(is_safe(&far, MoveCommand::new(0.5, 0.0, 0.0), &config));
+    }
+
+    #[test]
+    fn strafe_checks_sides() {
+        let config = FootprintConfig::default();
+        let points = vec![na::Point2::new(0.0, 0.35)];
+        assert!(!is_safe(&points, MoveCommand::new(0.0, 0.5, 0.0), &config));
+        assert!(is_safe(&points, MoveCommand::new(0.0, -0.5, 0.0), &config));
+        assert!(is_safe(&points, MoveCommand::new(0.5, 0.0, 0.0), &config));
+    }
+
+    #[test]
+    fn rotation_sweeps_corners() {
+        let config = FootprintConfig {
+            footprint: Footprint::Rectangle {
+                length: 0.8,
+                width: 0.2,
+            },
+            safety_margin: 0.0,
+            ..Default::default()
+        };
+        // clear of the long narrow body but inside the circle its ends sweep
+        let angle = 30_f32.to_radians();
+        let points = vec![na::Point2::new(0.35 * angle.cos(), 0.35 * angle.sin())];
+        assert!(is_safe(&points, MoveCommand::new(0.0, 0.0, 0.0), &config));
+        assert!(!is_safe(&points, MoveCommand::new(0.0, 0.0, 0.5), &config));
+    }
+
+    #[test]
+    fn can_back_away_from_close_points() {
+        let config = FootprintConfig::default();
+        let points = wall_ahead(0.22);
+        assert!(!is_safe(&points, MoveCommand::new(0.2, 0.0, 0.0), &config));
+        assert!(is_safe(&points, MoveCommand::new(-0.2, 0.0, 0.0), &config));
+    }
+
+    #[test]
+    fn slows_down_approaching_obstacles() {
+        let config = FootprintConfig::default();
+        let forward = MoveCommand::new(0.5, 0.0, 0.0);
+        // front face at 0.2m so clearance is the wall distance minus 0.25m
+        let far = scale(&wall_ahead(1.0), forward, &config);
+        let middle = scale(&wall_ahead(0.6), forward, &config);
+        let near = scale(&wall_ahead(0.35), forward, &config);
+        let touching = scale(&wall_ahead(0.28), forward, &config);
+        assert_eq!(far, 1.0);
+        assert!((middle - 0.55).abs() < 0.05, "{}", middle);
+        assert!(near < middle && near > 0.0, "{}", near);
+        assert_eq!(touching, 0.0);
+
+        // moving away or turning isn't slowed
+        let backwards = MoveCommand::new(-0.5, 0.0, 0.0);
+        assert_eq!(scale(&wall_ahead(0.3), backwards, &config), 1.0);
+        let turning = MoveCommand::new(0.0, 0.0, 0.5);
+        assert_eq!(scale(&wall_ahead(0.3), turning, &config), 1.0);
+    }
+
+    #[test]
+    fn clearance_follows_command_direction() {
+        let config = FootprintConfig::default();
+        let points = vec![na::Point2::new(0.0, 0.5)];
+        assert!(scale(&points, MoveCommand::new(0.0, 0.5, 0.0), &config) < 1.0);
+        assert_eq!(
+            scale(&points, MoveCommand::new(0.5, 0.0, 0.0), &config),
+            1.0
+        );
+        // diagonal moves clip the point later than straight strafing
+        assert!(
+            scale(&points, MoveCommand::new(0.3, 0.3, 0.0), &config)
+                > scale(&points, MoveCommand::new(0.0, 0.5, 0.0), &config)
+        );
+    }
+
+    #[test]
+    fn checker_slows_then_falls_back_to_rotation() {
+        let checker = FootprintChecker::new(FootprintConfig::default());
+        let command = MoveCommand::new(0.5, 0.0, 0.3);
+
+        let open = checker.check(&command, &wall_ahead(2.0));
+        assert_eq!(open.reason, CollisionReason::Clear);
+        assert_eq!(open.command, command);
+
+        let slowed = checker.check(&command, &wall_ahead(0.6));
+        assert!(matches!(slowed.reason, CollisionReason::SlowedDown { .. }));
+        assert!(slowed.command.forward() < command.forward());
+        assert_eq!(slowed.command.yaw(), command.yaw());
+
+        let blocked = checker.check(&MoveCommand::new(0.5, 0.0, 0.0), &wall_ahead(0.28));
+        assert_eq!(blocked.reason, CollisionReason::TranslationBlocked);
+        assert!(blocked.command.is_stopped());
+    }
+
+    #[test]
+    fn diagonal_moves_reach_further() {
+        let config = FootprintConfig::default();
+        // only the front left corner gets there when moving diagonally
+        let points = vec![na::Point2::new(0.43, 0.43)];
+        assert!(!is_safe(&points, MoveCommand::new(1.0, 1.0, 0.0), &config));
+        assert!(is_safe(&points, MoveCommand::new(1.0, 0.0, 0.0), &config));
+    }
+
+    #[test]
+    fn rejects_configs_that_cant_be_swept() {
+        let parse = |json: &str| serde_json::from_str::<FootprintConfig>(json);
+        assert!(parse(r#"{"step_s": 0.05, "horizon_s": 0.0}"#).is_ok());
+        assert!(parse(r#"{"step_s": 0.0}"#).is_err());
+        assert!(parse(r#"{"step_s": -0.1}"#).is_err());
+        assert!(parse(r#"{"horizon_s": -1.0}"#).is_err());
+        assert!(parse(r#"{"footprint": {"type": "polygon", "points": []}}"#).is_err());
+        assert!(parse(
+            r#"{"footprint": {"type": "polygon", "points": [[0.2, 0.0], [-0.2, 0.1]]}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn polygon_footprint() {
+        let config = FootprintConfig {
+            footprint: Footprint::Polygon {
+                points: vec![(0.4, 0.0), (-0.2, 0.2), (-0.2, -0.2)],
+            },
+            ..Default::default()
+        };
+        let polygon = config.footprint.vertices();
+        assert!(signed_distance(&polygon, &na::Point2::new(0.0, 0.0)) < 0.0);
+        assert!(signed_distance(&polygon, &na::Point2::new(0.3, 0.15)) > 0.0);
+        // tip reaches further than the default rectangle
+        let points = wall_ahead(0.55);
+        assert!(!is_safe(&points, MoveCommand::new(0.5, 0.0, 0.0), &config));
+        assert!(is_safe(
+            &points,
+            MoveCommand::new(0.5, 0.0, 0.0),
+            &FootprintConfig::default()
+        ));
+    }
+}
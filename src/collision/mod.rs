@@ -0,0 +1,547 @@
+pub mod cone;
+pub mod footprint;
+pub mod telemetry;
+
+use crate::{
+    gamepad::{feedback::FeedbackSender, messages::FeedbackEvent},
+    holonomic_controller::MoveCommand,
+    lidar::Lidar,
+};
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tracing::{info, warn};
+
+pub use cone::{ConeChecker, ConeConfig};
+pub use footprint::{Footprint, FootprintChecker, FootprintConfig};
+use telemetry::{CollisionEvent, CollisionEventSender, CollisionTelemetryConfig};
+
+/// Why a command was changed by a collision checker
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum CollisionReason {
+    Clear,
+    /// Translation scaled down by a factor from 0 to 1
+    SlowedDown {
+        scale: f32,
+    },
+    /// Only rotation is allowed
+    TranslationBlocked,
+    /// Nothing is allowed
+    Blocked,
+}
+
+impl CollisionReason {
+    /// Whether the requested motion was refused rather than just slowed down
+    pub fn is_blocked(&self) -> bool {
+        matches!(
+            self,
+            CollisionReason::TranslationBlocked | CollisionReason::Blocked
+        )
+    }
+
+    /// Factor translation was scaled by
+    pub fn speed_scale(&self) -> f32 {
+        match self {
+            CollisionReason::Clear => 1.0,
+            CollisionReason::SlowedDown { scale } => *scale,
+            CollisionReason::TranslationBlocked | CollisionReason::Blocked => 0.0,
+        }
+    }
+
+    fn severity(&self) -> u8 {
+        match self {
+            CollisionReason::Clear => 0,
+            CollisionReason::SlowedDown { .. } => 1,
+            CollisionReason::TranslationBlocked => 2,
+            CollisionReason::Blocked => 3,
+        }
+    }
+
+    /// Reason for applying two checks one after the other
+    fn combine(self, other: CollisionReason) -> CollisionReason {
+        match (self, other) {
+            (
+                CollisionReason::SlowedDown { scale: a },
+                CollisionReason::SlowedDown { scale: b },
+            ) => CollisionReason::SlowedDown { scale: a * b },
+            (a, b) if b.severity() > a.severity() => b,
+            (a, _) => a,
+        }
+    }
+}
+
+/// Allowed command and why it differs from the requested one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollisionCheck {
+    pub command: MoveCommand,
+    pub reason: CollisionReason,
+}
+
+impl CollisionCheck {
+    pub fn clear(command: &MoveCommand) -> Self {
+        Self {
+            command: *command,
+            reason: CollisionReason::Clear,
+        }
+    }
+
+    pub fn blocked() -> Self {
+        Self {
+            command: MoveCommand::default(),
+            reason: CollisionReason::Blocked,
+        }
+    }
+}
+
+/// Turns a requested command into one that is allowed given nearby obstacles
+pub trait CollisionChecker: Send + Sync {
+    /// `obstacles` are in the robot frame
+    fn check(&self, command: &MoveCommand, obstacles: &[na::Point2<f32>]) -> CollisionCheck;
+}
+
+/// Allows everything
+pub struct NoCollisionChecker;
+
+impl CollisionChecker for NoCollisionChecker {
+    fn check(&self, command: &MoveCommand, _obstacles: &[na::Point2<f32>]) -> CollisionCheck {
+        CollisionCheck::clear(command)
+    }
+}
+
+/// Runs checkers in order, each one getting the command allowed by the previous one
+pub struct ChainChecker {
+    checkers: Vec<Box<dyn CollisionChecker>>,
+}
+
+impl ChainChecker {
+    pub fn new(checkers: Vec<Box<dyn CollisionChecker>>) -> Self {
+        Self { checkers }
+    }
+}
+
+impl CollisionChecker for ChainChecker {
+    fn check(&self, command: &MoveCommand, obstacles: &[na::Point2<f32>]) -> CollisionCheck {
+        self.checkers
+            .iter()
+            .fold(CollisionCheck::clear(command), |result, checker| {
+                let next = checker.check(&result.command, obstacles);
+                CollisionCheck {
+                    command: next.command,
+                    reason: result.reason.combine(next.reason),
+                }
+            })
+    }
+}
+
+fn default_checker() -> CheckerConfig {
+    CheckerConfig::Footprint(FootprintConfig::default())
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CheckerConfig {
+    None,
+    Cone(ConeConfig),
+    Footprint(FootprintConfig),
+    Chain { checkers: Vec<CheckerConfig> },
+}
+
+impl CheckerConfig {
+    pub fn build(&self) -> Box<dyn CollisionChecker> {
+        match self {
+            CheckerConfig::None => Box::new(NoCollisionChecker),
+            CheckerConfig::Cone(config) => Box::new(ConeChecker::new(config.clone())),
+            CheckerConfig::Footprint(config) => Box::new(FootprintChecker::new(config.clone())),
+            CheckerConfig::Chain { checkers } => Box::new(ChainChecker::new(
+                checkers.iter().map(CheckerConfig::build).collect(),
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CollisionConfig {
+    #[serde(default = "default_checker")]
+    pub checker: CheckerConfig,
+    #[serde(default)]
+    pub telemetry: CollisionTelemetryConfig,
+}
+
+impl Default for CollisionConfig {
+    fn default() -> Self {
+        Self {
+            checker: default_checker(),
+            telemetry: CollisionTelemetryConfig::default(),
+        }
+    }
+}
+
+/// Checks commands against the latest lidar scan
+///
+/// Clones share the same lidar and checker but report collision events separately,
+/// so each caller should have its own clone. Without a lidar there are no obstacles.
+///
+/// While the lidar wakes up from an idle spin down translation is refused until it has scanned.
+/// When it was turned off or stopped producing scans, commands pass unchecked
+/// so that the robot can still be driven, and the operator is warned once.
+pub struct CollisionDetector {
+    lidar: Option<Lidar>,
+    checker: Arc<dyn CollisionChecker>,
+    events: Option<CollisionEventSender>,
+    /// Whether the last check of this clone was blocked
+    blocked: AtomicBool,
+    feedback: Option<FeedbackSender>,
+    /// Whether commands currently pass without a scan to check against
+    unchecked: Arc<AtomicBool>,
+}
+
+impl Clone for CollisionDetector {
+    fn clone(&self) -> Self {
+        Self {
+            lidar: self.lidar.clone(),
+            checker: Arc::clone(&self.checker),
+            events: self.events.clone(),
+            blocked: AtomicBool::new(false),
+            feedback: self.feedback.clone(),
+            unchecked: Arc::clone(&self.unchecked),
+        }
+    }
+}
+
+impl CollisionDetector {
+    pub fn new(lidar: Option<Lidar>, checker: Box<dyn CollisionChecker>) -> Self {
+        Self {
+            lidar,
+            checker: checker.into(),
+            events: None,
+            blocked: AtomicBool::new(false),
+            feedback: None,
+            unchecked: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn from_config(lidar: Option<Lidar>, config: &CollisionConfig) -> Self {
+        Self::new(lidar, config.checker.build())
+    }
+
+    /// Report commands as collision events when they become blocked
+    pub fn set_event_sender(&mut self, events: CollisionEventSender) {
+        self.events = Some(events);
+    }
+
+    /// Warn the operator when commands can't be checked
+    pub fn set_feedback(&mut self, feedback: FeedbackSender) {
+        self.feedback = Some(feedback);
+    }
+
+    pub fn check(&self, command: &MoveCommand) -> CollisionCheck {
+        self.check_with(self.checker.as_ref(), command)
+    }
+
+    /// Check against the latest scan with a checker other than the configured one
+    pub fn check_with(
+        &self,
+        checker: &dyn CollisionChecker,
+        command: &MoveCommand,
+    ) -> CollisionCheck {
+        let (obstacles, check) = match self.obstacles() {
+            Some(obstacles) => {
+                let check = checker.check(command, &obstacles);
+                (obstacles, check)
+            }
+            // the lidar is about to scan again, wait for it
+            None => {
+                let rotation_only = command.with_rotation_only();
+                let reason = if rotation_only == *command {
+                    CollisionReason::Clear
+                } else {
+                    CollisionReason::TranslationBlocked
+                };
+                let check = CollisionCheck {
+                    command: rotation_only,
+                    reason,
+                };
+                (vec![], check)
+            }
+        };
+        let blocked = check.reason.is_blocked();
+        // a command stays blocked for many checks, only report the collision once
+        if blocked && !self.blocked.swap(blocked, Ordering::SeqCst) {
+            if let Some(events) = &self.events {
+                events.send(CollisionEvent::new(command, &check, &obstacles));
+            }
+        }
+        self.blocked.store(blocked, Ordering::SeqCst);
+        check
+    }
+
+    /// Latest scan in the robot frame
+    ///
+    /// Keeps the lidar from spinning down.
+    pub fn scan_points(&self) -> Vec<na::Point2<f32>> {
+        let Some(lidar) = &self.lidar else {
+            return vec![];
+        };
+        lidar.keep_awake();
+        lidar.get_last_scan_points().unwrap_or_default()
+    }
+
+    /// Obstacles to check commands against, `None` while the lidar is waking up
+    fn obstacles(&self) -> Option<Vec<na::Point2<f32>>> {
+        let Some(lidar) = &self.lidar else {
+            return Some(vec![]);
+        };
+        lidar.keep_awake();
+        if lidar.is_waking() {
+            return None;
+        }
+        match lidar.get_last_scan_points() {
+            Some(points) => {
+                if self.unchecked.swap(false, Ordering::SeqCst) {
+                    info!("Lidar scans are back, checking for collisions again");
+                }
+                Some(points)
+            }
+            None => {
+                if !self.unchecked.swap(true, Ordering::SeqCst) {
+                    warn!(
+                        motor = ?lidar.motor_status(),
+                        "No lidar scans, moving without collision checking"
+                    );
+                    if let Some(feedback) = &self.feedback {
+                        feedback.send(FeedbackEvent::LidarStopped);
+                    }
+                }
+                Some(vec![])
+            }
+        }
+    }
+
+    pub fn lidar(&self) -> Option<&Lidar> {
+        self.lidar.as_ref()
+    }
+
+    pub fn start_lidar(&mut self) {
+        if let Some(lidar) = &mut self.lidar {
+            lidar.start_motor();
+        }
+    }
+
+    pub fn stop_lidar(&mut self) {
+        if let Some(lidar) = &mut self.lidar {
+            lidar.stop_motor();
+        }
+    }
+}
+
+impl Default for CollisionDetector {
+    /// No lidar and no checking
+    fn default() -> Self {
+        Self::new(None, Box::new(NoCollisionChecker))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::{
+        control::MotorStatus,
+        filter::ScanFilterChain,
+        simulator::{SimulatedLidar, SimulatedLidarConfig},
+        LidarMount,
+    };
+
+    #[test]
+    fn chain_applies_checkers_in_order() {
+        let checker = CheckerConfig::Chain {
+            checkers: vec![
+                CheckerConfig::Footprint(FootprintConfig::default()),
+                CheckerConfig::Cone(ConeConfig::default()),
+            ],
+        }
+        .build();
+        let command = MoveCommand::new(0.5, 0.0, 0.2);
+
+        let open = checker.check(&command, &[]);
+        assert_eq!(open, CollisionCheck::clear(&command));
+
+        // footprint slows down for the point but the cone doesn't see it
+        let slowed = checker.check(&command, &[na::Point2::new(0.6, 0.0)]);
+        assert!(matches!(slowed.reason, CollisionReason::SlowedDown { .. }));
+        assert!(slowed.command.forward() < 0.5);
+
+        // within the cone
+        let blocked = checker.check(&command, &[na::Point2::new(0.28, 0.0)]);
+        assert!(blocked.reason.is_blocked());
+        assert_eq!(blocked.command.forward(), 0.0);
+        assert_eq!(blocked.reason.speed_scale(), 0.0);
+    }
+
+    #[test]
+    fn detector_sees_simulated_walls() {
+        let simulation = SimulatedLidarConfig {
+            // wall just ahead of the robot
+            world: vec![vec![(0.24, -1.0), (0.3, -1.0), (0.3, 1.0), (0.24, 1.0)]],
+            scan_rate_hz: 100.0,
+            ..Default::default()
+        };
+        let mount = LidarMount::default();
+        let lidar = Lidar::from_source(
+            mount,
+            ScanFilterChain::default(),
+            SimulatedLidar::new(simulation, mount),
+        );
+        let detector = CollisionDetector::new(
+            Some(lidar),
+            CheckerConfig::Footprint(FootprintConfig::default()).build(),
+        );
+        let mut waited = 0;
+        while detector.scan_points().is_empty() && waited < 100 {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            waited += 1;
+        }
+
+        assert!(detector
+            .check(&MoveCommand::new(0.5, 0.0, 0.0))
+            .reason
+            .is_blocked());
+        let backwards = MoveCommand::new(-0.5, 0.0, 0.0);
+        assert_eq!(
+            detector.check(&backwards),
+            CollisionCheck::clear(&backwards)
+        );
+    }
+
+    #[test]
+    fn waits_for_scans_only_while_waking_up() {
+        let simulation = SimulatedLidarConfig {
+            scan_rate_hz: 100.0,
+            ..Default::default()
+        };
+        let mount = LidarMount::default();
+        let mut lidar = Lidar::from_source(
+            mount,
+            ScanFilterChain::default(),
+            SimulatedLidar::new(simulation, mount),
+        );
+        let mut detector =
+            CollisionDetector::new(Some(lidar.clone()), Box::new(NoCollisionChecker));
+        let (feedback, mut feedback_events) = tokio::sync::mpsc::channel(10);
+        detector.set_feedback(FeedbackSender::new(feedback));
+        let command = MoveCommand::new(0.5, 0.0, 0.2);
+        let rotation_only = CollisionCheck {
+            command: command.with_rotation_only(),
+            reason: CollisionReason::TranslationBlocked,
+        };
+        let wait_for_scan = |since: std::time::Instant| {
+            let mut waited = 0;
+            let scanned_since = || {
+                lidar
+                    .subscribe_scans()
+                    .borrow()
+                    .as_ref()
+                    .is_some_and(|scan| scan.received >= since)
+            };
+            while !scanned_since() && waited < 100 {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                waited += 1;
+            }
+        };
+        wait_for_scan(std::time::Instant::now());
+        assert_eq!(detector.check(&command), CollisionCheck::clear(&command));
+
+        // spun down for being idle, the last scan is from before
+        assert!(lidar.spin_down_if_idle(std::time::Duration::ZERO));
+        let woken = std::time::Instant::now();
+        assert_eq!(detector.check(&command), rotation_only);
+        assert_eq!(lidar.motor_status(), MotorStatus::On);
+        let turn = MoveCommand::new(0.0, 0.0, 0.2);
+        assert_eq!(detector.check(&turn), CollisionCheck::clear(&turn));
+        wait_for_scan(woken);
+        assert_eq!(detector.check(&command), CollisionCheck::clear(&command));
+        assert!(feedback_events.try_recv().is_err());
+
+        // turned off on purpose, still drivable but the operator is told
+        lidar.stop_motor();
+        std::thread::sleep(std::time::Duration::from_millis(600));
+        assert_eq!(detector.check(&command), CollisionCheck::clear(&command));
+        assert_eq!(
+            feedback_events.try_recv().unwrap(),
+            FeedbackEvent::LidarStopped
+        );
+        detector.check(&command);
+        assert!(feedback_events.try_recv().is_err());
+    }
+
+    /// Refuses anything moving forward
+    struct NoForwardChecker;
+
+    impl CollisionChecker for NoForwardChecker {
+        fn check(&self, command: &MoveCommand, _obstacles: &[na::Point2<f32>]) -> CollisionCheck {
+            if command.forward() > 0.0 {
+                CollisionCheck::blocked()
+            } else {
+                CollisionCheck::clear(command)
+            }
+        }
+    }
+
+    #[test]
+    fn reports_each_collision_once_per_clone() {
+        let mut detector = CollisionDetector::new(None, Box::new(NoForwardChecker));
+        let (events, mut received) = tokio::sync::mpsc::channel(10);
+        detector.set_event_sender(CollisionEventSender::new(events));
+        let forward = MoveCommand::new(0.5, 0.0, 0.0);
+        let backward = MoveCommand::new(-0.5, 0.0, 0.0);
+
+        assert!(detector.check(&forward).reason.is_blocked());
+        assert!(detector.check(&forward).reason.is_blocked());
+        assert!(received.try_recv().is_ok());
+        assert!(received.try_recv().is_err());
+
+        detector.check(&backward);
+        detector.check(&forward);
+        assert!(received.try_recv().is_ok());
+        assert!(received.try_recv().is_err());
+
+        // another caller blocked at the same time is a separate collision
+        let other = detector.clone();
+        other.check(&forward);
+        detector.check(&forward);
+        assert!(received.try_recv().is_ok());
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn parses_checker_config() {
+        let config: CollisionConfig = serde_json::from_str(
+            r#"{
+                "checker": {
+                    "type": "chain",
+                    "checkers": [
+                        {"type": "cone", "safe_distance": 0.4},
+                        {"type": "footprint", "footprint": {"type": "rectangle", "length": 0.5, "width": 0.3}}
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let CheckerConfig::Chain { checkers } = &config.checker else {
+            panic!("Expected chain {:?}", config.checker);
+        };
+        assert!(matches!(&checkers[0], CheckerConfig::Cone(cone) if cone.safe_distance == 0.4));
+        assert!(matches!(
+            &checkers[1],
+            CheckerConfig::Footprint(footprint)
+                if footprint.footprint == Footprint::Rectangle { length: 0.5, width: 0.3 }
+        ));
+
+        let config: CollisionConfig =
+            serde_json::from_str(r#"{"checker": {"type": "none"}}"#).unwrap();
+        assert!(matches!(config.checker, CheckerConfig::None));
+    }
+}
@@ -0,0 +1,324 @@
+use super::{CollisionCheck, CollisionReason};
+use crate::{error::ErrorWrapper, holonomic_controller::MoveCommand};
+use chrono::{DateTime, Utc};
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::FRAC_PI_4,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use tracing::*;
+use zenoh::{prelude::r#async::*, Session};
+
+pub const COLLISION_EVENT_TOPIC: &str = "hamilton/collision/event";
+pub const COLLISION_STATUS_TOPIC: &str = "hamilton/collision/status";
+
+fn default_min_interval_ms() -> u64 {
+    500
+}
+
+fn default_status_interval_ms() -> u64 {
+    5000
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CollisionTelemetryConfig {
+    /// Shortest time between published events, the rest are only counted
+    #[serde(default = "default_min_interval_ms")]
+    pub min_interval_ms: u64,
+    /// How often the status is republished when new events were counted
+    #[serde(default = "default_status_interval_ms")]
+    pub status_interval_ms: u64,
+}
+
+impl Default for CollisionTelemetryConfig {
+    fn default() -> Self {
+        Self {
+            min_interval_ms: default_min_interval_ms(),
+            status_interval_ms: default_status_interval_ms(),
+        }
+    }
+}
+
+/// Obstacles further than this off the direction of travel don't count as in the way
+const TRAVEL_CONE_HALF_ANGLE: f32 = FRAC_PI_4;
+
+/// Closest lidar point to the body origin in the direction of travel
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NearestObstacle {
+    /// Meters
+    pub distance: f32,
+    /// Radians counter clockwise from the front of the robot
+    pub bearing: f32,
+}
+
+impl NearestObstacle {
+    /// Only points ahead of the commanded translation count, any point for pure rotation
+    pub fn find(obstacles: &[na::Point2<f32>], command: &MoveCommand) -> Option<Self> {
+        let direction = na::Vector2::new(command.forward(), command.strafe());
+        let translating = direction.norm() > f32::EPSILON;
+        obstacles
+            .iter()
+            .filter(|point| {
+                !translating || direction.angle(&point.coords) <= TRAVEL_CONE_HALF_ANGLE
+            })
+            .min_by(|a, b| a.coords.norm().total_cmp(&b.coords.norm()))
+            .map(|point| Self {
+                distance: point.coords.norm(),
+                bearing: point.y.atan2(point.x),
+            })
+    }
+}
+
+/// Command refused by a collision checker
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CollisionEvent {
+    pub time: DateTime<Utc>,
+    pub requested: MoveCommand,
+    pub allowed: MoveCommand,
+    #[serde(flatten)]
+    pub reason: CollisionReason,
+    pub nearest: Option<NearestObstacle>,
+}
+
+impl CollisionEvent {
+    pub fn new(
+        requested: &MoveCommand,
+        check: &CollisionCheck,
+        obstacles: &[na::Point2<f32>],
+    ) -> Self {
+        Self {
+            time: Utc::now(),
+            requested: *requested,
+            allowed: check.command,
+            reason: check.reason,
+            nearest: NearestObstacle::find(obstacles, requested),
+        }
+    }
+}
+
+/// Running count of collision events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct CollisionStatus {
+    pub events: u64,
+    pub published: u64,
+    /// Events lost before being counted because the publisher fell behind
+    pub dropped: u64,
+    pub last_event: Option<DateTime<Utc>>,
+}
+
+/// Cheap to clone handle for reporting collision events
+///
+/// Events are dropped if the publisher falls behind, the status counts them.
+#[derive(Clone)]
+pub struct CollisionEventSender {
+    sender: mpsc::Sender<CollisionEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl CollisionEventSender {
+    pub fn new(sender: mpsc::Sender<CollisionEvent>) -> Self {
+        Self {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn send(&self, event: CollisionEvent) {
+        if self.sender.try_send(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Events that couldn't be queued
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+pub fn start_collision_telemetry(
+    zenoh_session: Arc<Session>,
+    config: CollisionTelemetryConfig,
+) -> CollisionEventSender {
+    let (sender, mut receiver) = mpsc::channel(20);
+    let sender = CollisionEventSender::new(sender);
+    let dropped = Arc::clone(&sender.dropped);
+
+    tokio::spawn(async move {
+        let mut counter = CollisionEventCounter::new(Duration::from_millis(config.min_interval_ms));
+        let mut status_interval =
+            tokio::time::interval(Duration::from_millis(config.status_interval_ms));
+        let mut published_status = CollisionStatus::default();
+        loop {
+            tokio::select! {
+                event = receiver.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+                    if !counter.record(&event, Instant::now()) {
+                        continue;
+                    }
+                    warn!(
+                        reason = ?event.reason,
+                        nearest = ?event.nearest,
+                        events = counter.status().events,
+                        "Collision checker refused {:?}",
+                        event.requested
+                    );
+                    if let Err(err) = publish_event(&zenoh_session, &event).await {
+                        error!("Failed to publish collision event {:?}", err);
+                    }
+                }
+                _ = status_interval.tick() => {
+                    counter.set_dropped(dropped.load(Ordering::Relaxed));
+                    if counter.status() == published_status {
+                        continue;
+                    }
+                }
+            }
+            counter.set_dropped(dropped.load(Ordering::Relaxed));
+            published_status = counter.status();
+            if let Err(err) = publish_status(&zenoh_session, &published_status).await {
+                error!("Failed to publish collision status {:?}", err);
+            }
+        }
+    });
+
+    sender
+}
+
+async fn publish_event(zenoh_session: &Session, event: &CollisionEvent) -> anyhow::Result<()> {
+    let message = serde_json::to_string(event)?;
+    zenoh_session
+        .put(COLLISION_EVENT_TOPIC, message)
+        .congestion_control(CongestionControl::Drop)
+        .res_async()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+    Ok(())
+}
+
+async fn publish_status(zenoh_session: &Session, status: &CollisionStatus) -> anyhow::Result<()> {
+    let message = serde_json::to_string(status)?;
+    zenoh_session
+        .put(COLLISION_STATUS_TOPIC, message)
+        .congestion_control(CongestionControl::Drop)
+        .res_async()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+    Ok(())
+}
+
+/// Counts every received event but lets through at most one per interval
+#[derive(Debug)]
+struct CollisionEventCounter {
+    min_interval: Duration,
+    last_published: Option<Instant>,
+    status: CollisionStatus,
+}
+
+impl CollisionEventCounter {
+    fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last_published: None,
+            status: CollisionStatus::default(),
+        }
+    }
+
+    /// Whether the event should be published
+    fn record(&mut self, event: &CollisionEvent, now: Instant) -> bool {
+        self.status.events += 1;
+        self.status.last_event = Some(event.time);
+        if self
+            .last_published
+            .is_some_and(|last| now.duration_since(last) < self.min_interval)
+        {
+            return false;
+        }
+        self.last_published = Some(now);
+        self.status.published += 1;
+        true
+    }
+
+    fn set_dropped(&mut self, dropped: u64) {
+        self.status.dropped = dropped;
+    }
+
+    fn status(&self) -> CollisionStatus {
+        self.status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn blocked_event(obstacles: &[na::Point2<f32>]) -> CollisionEvent {
+        let requested = MoveCommand::new(0.0, 0.5, 0.0);
+        let check = CollisionCheck {
+            command: MoveCommand::default(),
+            reason: CollisionReason::TranslationBlocked,
+        };
+        CollisionEvent::new(&requested, &check, obstacles)
+    }
+
+    #[test]
+    fn event_has_nearest_obstacle() {
+        // strafing left, the closer points behind and to the right aren't in the way
+        let event = blocked_event(&[
+            na::Point2::new(1.0, 0.0),
+            na::Point2::new(0.0, 0.3),
+            na::Point2::new(-0.2, 0.0),
+            na::Point2::new(0.0, -0.1),
+        ]);
+        let nearest = event.nearest.unwrap();
+        assert_relative_eq!(nearest.distance, 0.3);
+        assert_relative_eq!(nearest.bearing, std::f32::consts::FRAC_PI_2);
+        assert!(blocked_event(&[]).nearest.is_none());
+        assert!(blocked_event(&[na::Point2::new(0.0, -0.1)])
+            .nearest
+            .is_none());
+
+        // rotation sweeps all around
+        let turn = MoveCommand::new(0.0, 0.0, 0.5);
+        let nearest = NearestObstacle::find(&[na::Point2::new(-0.2, 0.0)], &turn).unwrap();
+        assert_relative_eq!(nearest.distance, 0.2);
+
+        let json: serde_json::Value = serde_json::to_value(event).unwrap();
+        assert_eq!(json["reason"], "translation_blocked");
+        assert_eq!(json["requested"]["strafe"], 0.5);
+    }
+
+    #[test]
+    fn events_are_counted_and_rate_limited() {
+        let mut counter = CollisionEventCounter::new(Duration::from_millis(500));
+        let event = blocked_event(&[]);
+        let now = Instant::now();
+        assert!(counter.record(&event, now));
+        assert!(!counter.record(&event, now + Duration::from_millis(100)));
+        assert!(!counter.record(&event, now + Duration::from_millis(400)));
+        assert!(counter.record(&event, now + Duration::from_millis(600)));
+        let status = counter.status();
+        assert_eq!(status.events, 4);
+        assert_eq!(status.published, 2);
+        assert_eq!(status.last_event, Some(event.time));
+    }
+
+    #[test]
+    fn dropped_events_are_counted() {
+        let (sender, _receiver) = mpsc::channel(1);
+        let sender = CollisionEventSender::new(sender);
+        sender.send(blocked_event(&[]));
+        assert_eq!(sender.dropped(), 0);
+        sender.send(blocked_event(&[]));
+        sender.clone().send(blocked_event(&[]));
+        assert_eq!(sender.dropped(), 2);
+    }
+}
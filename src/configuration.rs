@@ -3,7 +3,10 @@ use serde::Deserialize;
 use std::{path::PathBuf, str};
 use tracing::*;
 
-use crate::{driver::BodyConfig, error::ErrorWrapper, lidar::LidarConfig};
+use crate::{
+    collision::CollisionConfig, driver::BodyConfig, error::ErrorWrapper, gamepad::GamepadConfig,
+    lidar::LidarConfig, localisation::LocalisationConfig, navigation::NavigationConfig,
+};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct AppConfig {
@@ -12,6 +15,14 @@ pub struct AppConfig {
     pub lidar: Option<LidarConfig>,
     #[serde(default)]
     pub zenoh: HamiltonZenohConfig,
+    #[serde(default)]
+    pub gamepad: GamepadConfig,
+    #[serde(default)]
+    pub navigation: NavigationConfig,
+    #[serde(default)]
+    pub localisation: LocalisationConfig,
+    #[serde(default)]
+    pub collision: CollisionConfig,
 }
 
 impl AppConfig {
@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use super::messages::{Button, GamepadMessage, InputMessage};
+
+fn default_handoff_timeout_ms() -> u64 {
+    2000
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ArbitrationConfig {
+    /// Gamepad that should always be preferred when connected
+    #[serde(default)]
+    pub preferred_name: Option<String>,
+    /// Button a gamepad has to press to claim control
+    ///
+    /// When not set the lowest numbered connected gamepad is selected
+    #[serde(default)]
+    pub claim_button: Option<Button>,
+    /// How long the active gamepad has to be idle before another one can take over
+    #[serde(default = "default_handoff_timeout_ms")]
+    pub handoff_timeout_ms: u64,
+}
+
+impl Default for ArbitrationConfig {
+    fn default() -> Self {
+        Self {
+            preferred_name: None,
+            claim_button: None,
+            handoff_timeout_ms: default_handoff_timeout_ms(),
+        }
+    }
+}
+
+impl ArbitrationConfig {
+    fn handoff_timeout(&self) -> Duration {
+        Duration::from_millis(self.handoff_timeout_ms)
+    }
+}
+
+/// Published on change of the active gamepad
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ActiveGamepadMessage {
+    pub id: Option<usize>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct ActiveGamepad {
+    id: usize,
+    name: String,
+    last_input: Instant,
+}
+
+pub struct GamepadArbiter {
+    config: ArbitrationConfig,
+    active: Option<ActiveGamepad>,
+}
+
+impl GamepadArbiter {
+    pub fn new(config: ArbitrationConfig) -> Self {
+        Self {
+            config,
+            active: None,
+        }
+    }
+
+    pub fn active_gamepad(&self) -> ActiveGamepadMessage {
+        ActiveGamepadMessage {
+            id: self.active.as_ref().map(|active| active.id),
+            name: self.active.as_ref().map(|active| active.name.clone()),
+        }
+    }
+
+    /// Select the gamepad that is allowed to drive the robot
+    ///
+    /// Returns the selected gamepad and whether the selection changed
+    pub fn select(
+        &mut self,
+        message: &InputMessage,
+        now: Instant,
+    ) -> (Option<GamepadMessage>, bool) {
+        let previous = self.active.as_ref().map(|active| active.id);
+
+        // release gamepads that went away
+        if let Some(active) = &mut self.active {
+            match message.gamepads.get(&active.id) {
+                Some(gamepad) if gamepad.connected && gamepad.name == active.name => {
+                    if has_input(gamepad) {
+                        active.last_input = now;
+                    }
+                }
+                _ => self.active = None,
+            }
+        }
+        let active_idle = self.active.as_ref().is_none_or(|active| {
+            now.duration_since(active.last_input) >= self.config.handoff_timeout()
+        });
+
+        if let Some(candidate) = self.find_preferred(message) {
+            let is_preferred_active = self
+                .active
+                .as_ref()
+                .is_some_and(|active| active.id == candidate);
+            if !is_preferred_active {
+                self.activate(message, candidate, now);
+            }
+        } else if let Some(claim_button) = self.config.claim_button {
+            if let Some(candidate) = find_claiming(message, claim_button) {
+                if active_idle && previous != Some(candidate) {
+                    self.activate(message, candidate, now);
+                }
+            }
+        } else if self.active.is_none() {
+            if let Some((id, _)) = message
+                .gamepads
+                .iter()
+                .find(|(_, gamepad)| gamepad.connected)
+            {
+                self.activate(message, *id, now);
+            }
+        }
+
+        let current = self.active.as_ref().map(|active| active.id);
+        let selected = current.and_then(|id| message.gamepads.get(&id).cloned());
+        (selected, previous != current)
+    }
+
+    fn find_preferred(&self, message: &InputMessage) -> Option<usize> {
+        let preferred_name = self.config.preferred_name.as_ref()?;
+        message
+            .gamepads
+            .iter()
+            .find(|(_, gamepad)| gamepad.connected && &gamepad.name == preferred_name)
+            .map(|(id, _)| *id)
+    }
+
+    fn activate(&mut self, message: &InputMessage, id: usize, now: Instant) {
+        if let Some(gamepad) = message.gamepads.get(&id) {
+            tracing::info!(id, name = ?gamepad.name, "Gamepad took control");
+            self.active = Some(ActiveGamepad {
+                id,
+                name: gamepad.name.clone(),
+                last_input: now,
+            });
+        }
+    }
+}
+
+fn find_claiming(message: &InputMessage, claim_button: Button) -> Option<usize> {
+    message
+        .gamepads
+        .iter()
+        .find(|(_, gamepad)| {
+            gamepad.connected
+                && gamepad
+                    .button_down
+                    .get(&claim_button)
+                    .cloned()
+                    .unwrap_or_default()
+        })
+        .map(|(id, _)| *id)
+}
+
+fn has_input(gamepad: &GamepadMessage) -> bool {
+    gamepad.button_down.values().any(|down| *down)
+        || gamepad
+            .axis_state
+            .values()
+            .any(|value| super::apply_deadzone(*value) != 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::messages::Axis;
+    use std::collections::BTreeMap;
+
+    fn gamepad(name: &str) -> GamepadMessage {
+        GamepadMessage {
+            name: name.to_owned(),
+            connected: true,
+            ..Default::default()
+        }
+    }
+
+    fn driving(name: &str) -> GamepadMessage {
+        let mut gamepad = gamepad(name);
+        gamepad.axis_state.insert(Axis::LeftStickY, 0.5);
+        gamepad
+    }
+
+    fn pressing(name: &str, button: Button) -> GamepadMessage {
+        let mut gamepad = gamepad(name);
+        gamepad.button_down.insert(button, true);
+        gamepad
+    }
+
+    fn input(gamepads: Vec<(usize, GamepadMessage)>) -> InputMessage {
+        InputMessage {
+            gamepads: gamepads.into_iter().collect::<BTreeMap<_, _>>(),
+            time: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn first_gamepad_stays_active_when_second_connects() {
+        let mut arbiter = GamepadArbiter::new(ArbitrationConfig::default());
+        let now = Instant::now();
+
+        let (selected, changed) = arbiter.select(&input(vec![(1, driving("a"))]), now);
+        assert_eq!(selected.unwrap().name, "a");
+        assert!(changed);
+
+        let (selected, changed) =
+            arbiter.select(&input(vec![(0, driving("b")), (1, driving("a"))]), now);
+        assert_eq!(selected.unwrap().name, "a");
+        assert!(!changed);
+    }
+
+    #[test]
+    fn disconnected_gamepad_is_released() {
+        let mut arbiter = GamepadArbiter::new(ArbitrationConfig::default());
+        let now = Instant::now();
+
+        arbiter.select(&input(vec![(0, driving("a")), (1, driving("b"))]), now);
+        let mut disconnected = driving("a");
+        disconnected.connected = false;
+        let (selected, changed) =
+            arbiter.select(&input(vec![(0, disconnected), (1, driving("b"))]), now);
+        assert_eq!(selected.unwrap().name, "b");
+        assert!(changed);
+    }
+
+    #[test]
+    fn preferred_name_takes_over() {
+        let mut arbiter = GamepadArbiter::new(ArbitrationConfig {
+            preferred_name: Some(String::from("base")),
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        // fall back to other gamepads while the preferred one is missing
+        let (selected, _) = arbiter.select(&input(vec![(0, driving("other"))]), now);
+        assert_eq!(selected.unwrap().name, "other");
+
+        let (selected, changed) = arbiter.select(
+            &input(vec![(0, driving("other")), (1, gamepad("base"))]),
+            now,
+        );
+        assert_eq!(selected.unwrap().name, "base");
+        assert!(changed);
+    }
+
+    #[test]
+    fn claim_waits_for_handoff_timeout() {
+        let mut arbiter = GamepadArbiter::new(ArbitrationConfig {
+            claim_button: Some(Button::Start),
+            handoff_timeout_ms: 1000,
+            ..Default::default()
+        });
+        let start = Instant::now();
+
+        let (selected, _) = arbiter.select(&input(vec![(0, driving("a"))]), start);
+        assert!(selected.is_none());
+
+        let (selected, _) = arbiter.select(&input(vec![(0, pressing("a", Button::Start))]), start);
+        assert_eq!(selected.unwrap().name, "a");
+
+        // active gamepad is still driving so the claim is ignored
+        let later = start + Duration::from_millis(500);
+        let (selected, _) = arbiter.select(
+            &input(vec![(0, driving("a")), (1, pressing("b", Button::Start))]),
+            later,
+        );
+        assert_eq!(selected.unwrap().name, "a");
+
+        // active gamepad went idle
+        let much_later = later + Duration::from_millis(1500);
+        let (selected, changed) = arbiter.select(
+            &input(vec![(0, gamepad("a")), (1, pressing("b", Button::Start))]),
+            much_later,
+        );
+        assert_eq!(selected.unwrap().name, "b");
+        assert!(changed);
+    }
+}
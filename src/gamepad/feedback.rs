@@ -0,0 +1,140 @@
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc;
+use tracing::error;
+use zenoh::{prelude::r#async::*, Session};
+
+use super::messages::{FeedbackEvent, FeedbackMessage};
+use crate::error::ErrorWrapper;
+
+const FEEDBACK_TOPIC: &str = "remote-control/feedback";
+
+fn default_low_battery_voltage() -> f32 {
+    10.5
+}
+
+fn default_battery_check_interval_s() -> u64 {
+    30
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FeedbackConfig {
+    /// Voltage under which the operator is warned about the battery
+    #[serde(default = "default_low_battery_voltage")]
+    pub low_battery_voltage: f32,
+    #[serde(default = "default_battery_check_interval_s")]
+    pub battery_check_interval_s: u64,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        Self {
+            low_battery_voltage: default_low_battery_voltage(),
+            battery_check_interval_s: default_battery_check_interval_s(),
+        }
+    }
+}
+
+/// Cheap to clone handle for reporting feedback to the operator
+///
+/// Feedback is best effort. Events are dropped if the publisher falls behind.
+#[derive(Clone)]
+pub struct FeedbackSender {
+    sender: mpsc::Sender<FeedbackEvent>,
+}
+
+impl FeedbackSender {
+    pub fn new(sender: mpsc::Sender<FeedbackEvent>) -> Self {
+        Self { sender }
+    }
+
+    pub fn send(&self, event: FeedbackEvent) {
+        // ignore errors
+        _ = self.sender.try_send(event);
+    }
+}
+
+pub fn start_feedback_publisher(zenoh_session: Arc<Session>) -> FeedbackSender {
+    let (sender, mut receiver) = mpsc::channel(20);
+
+    tokio::spawn(async move {
+        let mut rate_limiter = FeedbackRateLimiter::default();
+        while let Some(event) = receiver.recv().await {
+            if !rate_limiter.should_send(event, Instant::now()) {
+                continue;
+            }
+            if let Err(err) = publish_feedback(&zenoh_session, event).await {
+                error!("Failed to publish feedback {:?}", err);
+            }
+        }
+    });
+
+    FeedbackSender::new(sender)
+}
+
+async fn publish_feedback(zenoh_session: &Session, event: FeedbackEvent) -> anyhow::Result<()> {
+    let message = serde_json::to_string(&FeedbackMessage::new(event))?;
+    zenoh_session
+        .put(FEEDBACK_TOPIC, message)
+        .congestion_control(CongestionControl::Drop)
+        .res_async()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+    Ok(())
+}
+
+fn min_interval(event: FeedbackEvent) -> Duration {
+    match event {
+        FeedbackEvent::CollisionBlocked => Duration::from_secs(1),
+        FeedbackEvent::BatteryLow => Duration::from_secs(60),
+        FeedbackEvent::LidarStopped => Duration::from_secs(5),
+    }
+}
+
+/// Keeps repeated events from rumbling the controller nonstop
+#[derive(Debug, Default)]
+struct FeedbackRateLimiter {
+    last_sent: BTreeMap<FeedbackEvent, Instant>,
+}
+
+impl FeedbackRateLimiter {
+    fn should_send(&mut self, event: FeedbackEvent, now: Instant) -> bool {
+        if let Some(last_sent) = self.last_sent.get(&event) {
+            if now.duration_since(*last_sent) < min_interval(event) {
+                return false;
+            }
+        }
+        self.last_sent.insert(event, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_events_are_rate_limited() {
+        let mut rate_limiter = FeedbackRateLimiter::default();
+        let now = Instant::now();
+        assert!(rate_limiter.should_send(FeedbackEvent::CollisionBlocked, now));
+        assert!(!rate_limiter.should_send(
+            FeedbackEvent::CollisionBlocked,
+            now + Duration::from_millis(500)
+        ));
+        // other events are limited separately
+        assert!(rate_limiter.should_send(
+            FeedbackEvent::LidarStopped,
+            now + Duration::from_millis(500)
+        ));
+        assert!(rate_limiter.should_send(
+            FeedbackEvent::CollisionBlocked,
+            now + Duration::from_millis(1500)
+        ));
+    }
+}
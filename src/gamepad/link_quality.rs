@@ -0,0 +1,270 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+fn default_expected_rate_hz() -> f32 {
+    10.0
+}
+
+fn default_window_ms() -> u64 {
+    5000
+}
+
+fn default_min_quality() -> f32 {
+    0.5
+}
+
+fn default_degraded_speed_cap() -> f32 {
+    0.3
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LinkQualityConfig {
+    /// Rate at which the gamepad bridge publishes
+    #[serde(default = "default_expected_rate_hz")]
+    pub expected_rate_hz: f32,
+    /// Window over which statistics are computed
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+    /// Quality below which teleop speed is capped
+    #[serde(default = "default_min_quality")]
+    pub min_quality: f32,
+    /// Speed cap applied to gamepad commands while the link is degraded
+    #[serde(default = "default_degraded_speed_cap")]
+    pub degraded_speed_cap: f32,
+}
+
+impl Default for LinkQualityConfig {
+    fn default() -> Self {
+        Self {
+            expected_rate_hz: default_expected_rate_hz(),
+            window_ms: default_window_ms(),
+            min_quality: default_min_quality(),
+            degraded_speed_cap: default_degraded_speed_cap(),
+        }
+    }
+}
+
+impl LinkQualityConfig {
+    fn expected_interval_ms(&self) -> f64 {
+        1000.0 / self.expected_rate_hz.max(f32::EPSILON) as f64
+    }
+
+    /// Link is considered silent after this long without a message
+    pub fn silence_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.expected_interval_ms() * 3.0 / 1000.0)
+    }
+}
+
+/// Rolling link statistics published for the operator
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct LinkQualityStatistics {
+    pub receive_rate_hz: f64,
+    /// Mean deviation of inter-arrival time from its average
+    pub jitter_ms: f64,
+    /// Smallest observed difference between robot and sender clock
+    ///
+    /// This is the clock offset plus the best case network latency.
+    /// The two can't be separated without round trips.
+    pub clock_offset_ms: f64,
+    /// Latency of the latest message above the best case
+    pub latency_ms: f64,
+    /// Link quality from 0.0 to 1.0
+    pub quality: f64,
+    pub speed_cap: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LinkSample {
+    received: Instant,
+    delay_ms: f64,
+}
+
+pub struct LinkQualityMonitor {
+    config: LinkQualityConfig,
+    samples: VecDeque<LinkSample>,
+    /// First message ever received, the window isn't full before `window_ms` past this
+    first_received: Option<Instant>,
+}
+
+impl LinkQualityMonitor {
+    pub fn new(config: LinkQualityConfig) -> Self {
+        Self {
+            config,
+            samples: VecDeque::new(),
+            first_received: None,
+        }
+    }
+
+    /// Record a message stamped with `sent` by the sender and received at `received`
+    pub fn record(&mut self, sent: DateTime<Utc>, received_time: DateTime<Utc>, received: Instant) {
+        let delay_ms = (received_time - sent)
+            .num_microseconds()
+            .unwrap_or(i64::MAX) as f64
+            / 1000.0;
+        self.first_received.get_or_insert(received);
+        self.samples.push_back(LinkSample { received, delay_ms });
+        self.trim(received);
+    }
+
+    fn trim(&mut self, now: Instant) {
+        let window = Duration::from_millis(self.config.window_ms);
+        while let Some(sample) = self.samples.front() {
+            if now.duration_since(sample.received) > window {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub fn statistics(&mut self, now: Instant) -> LinkQualityStatistics {
+        self.trim(now);
+        let expected_interval_ms = self.config.expected_interval_ms();
+        let Some(latest) = self.samples.back() else {
+            return LinkQualityStatistics {
+                speed_cap: self.config.degraded_speed_cap,
+                ..Default::default()
+            };
+        };
+
+        let window_s = self.config.window_ms as f64 / 1000.0;
+        let observed_s = self
+            .first_received
+            .map_or(0.0, |first| now.duration_since(first).as_secs_f64())
+            .min(window_s);
+        // until the window is full only the time since the first message counts
+        let receive_rate_hz = if observed_s >= window_s {
+            Some(self.samples.len() as f64 / window_s)
+        } else if self.samples.len() > 1 && observed_s > 0.0 {
+            Some((self.samples.len() - 1) as f64 / observed_s)
+        } else {
+            None
+        };
+
+        let intervals: Vec<f64> = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .map(|(a, b)| b.received.duration_since(a.received).as_secs_f64() * 1000.0)
+            .collect();
+        let jitter_ms = if intervals.is_empty() {
+            0.0
+        } else {
+            let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+            intervals.iter().map(|i| (i - mean).abs()).sum::<f64>() / intervals.len() as f64
+        };
+
+        let clock_offset_ms = self
+            .samples
+            .iter()
+            .map(|sample| sample.delay_ms)
+            .fold(f64::INFINITY, f64::min);
+        let latency_ms = latest.delay_ms - clock_offset_ms;
+
+        let quality = if now.duration_since(latest.received) > self.config.silence_timeout() {
+            // link went silent
+            0.0
+        } else {
+            let rate_score = receive_rate_hz.map_or(1.0, |rate| {
+                (rate / self.config.expected_rate_hz as f64).min(1.0)
+            });
+            let jitter_score = (1.0 - jitter_ms / expected_interval_ms).clamp(0.0, 1.0);
+            let latency_score = (1.0 - latency_ms / (expected_interval_ms * 5.0)).clamp(0.0, 1.0);
+            rate_score * jitter_score * latency_score
+        };
+
+        let speed_cap = if quality < self.config.min_quality as f64 {
+            self.config.degraded_speed_cap
+        } else {
+            1.0
+        };
+
+        LinkQualityStatistics {
+            receive_rate_hz: receive_rate_hz.unwrap_or_default(),
+            jitter_ms,
+            clock_offset_ms,
+            latency_ms,
+            quality,
+            speed_cap,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(
+        monitor: &mut LinkQualityMonitor,
+        start: Instant,
+        count: u64,
+        interval_ms: impl Fn(u64) -> u64,
+        delay_ms: impl Fn(u64) -> i64,
+    ) -> Instant {
+        let start_utc = Utc::now();
+        let mut elapsed = 0;
+        for i in 0..count {
+            elapsed += interval_ms(i);
+            let received_utc = start_utc + chrono::Duration::milliseconds(elapsed as i64);
+            let sent = received_utc - chrono::Duration::milliseconds(delay_ms(i));
+            monitor.record(sent, received_utc, start + Duration::from_millis(elapsed));
+        }
+        start + Duration::from_millis(elapsed)
+    }
+
+    #[test]
+    fn steady_link_has_full_quality() {
+        let mut monitor = LinkQualityMonitor::new(LinkQualityConfig::default());
+        let now = feed(&mut monitor, Instant::now(), 50, |_| 100, |_| 20);
+        let statistics = monitor.statistics(now);
+        assert!((statistics.receive_rate_hz - 10.0).abs() < 0.01);
+        assert!(statistics.jitter_ms < 0.01);
+        assert!((statistics.clock_offset_ms - 20.0).abs() < 0.01);
+        assert!(statistics.latency_ms.abs() < 0.01);
+        assert!(statistics.quality > 0.99);
+        assert_eq!(statistics.speed_cap, 1.0);
+    }
+
+    #[test]
+    fn new_link_is_not_penalised_before_window_fills() {
+        let mut monitor = LinkQualityMonitor::new(LinkQualityConfig::default());
+        let start = Instant::now();
+        monitor.record(Utc::now(), Utc::now(), start);
+        assert_eq!(monitor.statistics(start).speed_cap, 1.0);
+
+        let now = feed(&mut monitor, start, 10, |_| 100, |_| 0);
+        let statistics = monitor.statistics(now);
+        assert!((statistics.receive_rate_hz - 10.0).abs() < 0.01);
+        assert!(statistics.quality > 0.99);
+        assert_eq!(statistics.speed_cap, 1.0);
+    }
+
+    #[test]
+    fn jittery_link_caps_speed() {
+        let mut monitor = LinkQualityMonitor::new(LinkQualityConfig::default());
+        let now = feed(
+            &mut monitor,
+            Instant::now(),
+            30,
+            |i| if i % 2 == 0 { 20 } else { 300 },
+            |i| if i % 2 == 0 { 20 } else { 200 },
+        );
+        let statistics = monitor.statistics(now);
+        assert!(statistics.jitter_ms > 100.0);
+        assert!(statistics.quality < 0.5);
+        assert_eq!(statistics.speed_cap, 0.3);
+    }
+
+    #[test]
+    fn silent_link_has_no_quality() {
+        let mut monitor = LinkQualityMonitor::new(LinkQualityConfig::default());
+        let now = feed(&mut monitor, Instant::now(), 50, |_| 100, |_| 20);
+        let statistics = monitor.statistics(now + Duration::from_secs(1));
+        assert_eq!(statistics.quality, 0.0);
+        assert_eq!(statistics.speed_cap, 0.3);
+    }
+}
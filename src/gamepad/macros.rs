@@ -0,0 +1,304 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+use super::messages::Button;
+use crate::{holonomic_controller::MoveCommand, util::validate_file_name};
+
+fn default_record_button() -> Button {
+    Button::Select
+}
+
+fn default_play_button() -> Button {
+    Button::Mode
+}
+
+fn default_macro_name() -> String {
+    String::from("default")
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MacroConfig {
+    /// Toggles recording of the default macro
+    #[serde(default = "default_record_button")]
+    pub record_button: Button,
+    /// Plays the default macro
+    #[serde(default = "default_play_button")]
+    pub play_button: Button,
+    /// Name used for macros recorded and played from the gamepad
+    #[serde(default = "default_macro_name")]
+    pub default_name: String,
+    /// Directory macros are saved to
+    ///
+    /// Macros are only kept in memory when not set
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+impl Default for MacroConfig {
+    fn default() -> Self {
+        Self {
+            record_button: default_record_button(),
+            play_button: default_play_button(),
+            default_name: default_macro_name(),
+            directory: None,
+        }
+    }
+}
+
+/// Requests accepted on the macro zenoh topic
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum MacroRequest {
+    StartRecording { name: String },
+    StopRecording,
+    Play { name: String },
+    StopPlayback,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MacroStep {
+    pub offset_ms: u64,
+    pub command: MoveCommand,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GamepadMacro {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+}
+
+pub struct MacroRecorder {
+    name: String,
+    start: Instant,
+    steps: Vec<MacroStep>,
+}
+
+impl MacroRecorder {
+    pub fn start(name: &str, now: Instant) -> Self {
+        info!(name, "Started recording macro");
+        Self {
+            name: name.to_owned(),
+            start: now,
+            steps: vec![],
+        }
+    }
+
+    pub fn record(&mut self, command: MoveCommand, now: Instant) {
+        // only store changes to keep macros small
+        if self.steps.last().map(|step| step.command) == Some(command) {
+            return;
+        }
+        self.steps.push(MacroStep {
+            offset_ms: now.duration_since(self.start).as_millis() as u64,
+            command,
+        });
+    }
+
+    /// Finish recording
+    ///
+    /// A final stop step is appended so that playback ends with the robot stopped
+    pub fn finish(mut self, now: Instant) -> GamepadMacro {
+        self.steps.push(MacroStep {
+            offset_ms: now.duration_since(self.start).as_millis() as u64,
+            command: MoveCommand::default(),
+        });
+        info!(name = ?self.name, steps = self.steps.len(), "Finished recording macro");
+        GamepadMacro {
+            name: self.name,
+            steps: self.steps,
+        }
+    }
+}
+
+pub struct MacroPlayback {
+    gamepad_macro: GamepadMacro,
+    start: Instant,
+}
+
+impl MacroPlayback {
+    pub fn new(gamepad_macro: GamepadMacro, now: Instant) -> Self {
+        info!(name = ?gamepad_macro.name, "Playing macro");
+        Self {
+            gamepad_macro,
+            start: now,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.gamepad_macro.name
+    }
+
+    /// Command that should be applied at the given time
+    ///
+    /// Returns `None` once the macro is finished
+    pub fn command_at(&self, now: Instant) -> Option<MoveCommand> {
+        let elapsed = now.duration_since(self.start);
+        let last = self.gamepad_macro.steps.last()?;
+        if elapsed > Duration::from_millis(last.offset_ms) {
+            return None;
+        }
+        Some(
+            self.gamepad_macro
+                .steps
+                .iter()
+                .take_while(|step| Duration::from_millis(step.offset_ms) <= elapsed)
+                .last()
+                .map(|step| step.command)
+                .unwrap_or_default(),
+        )
+    }
+
+    /// When the command changes next or playback finishes
+    pub fn next_change(&self, now: Instant) -> Instant {
+        let elapsed = now.duration_since(self.start);
+        let Some(last) = self.gamepad_macro.steps.last() else {
+            return now;
+        };
+        let offset = self
+            .gamepad_macro
+            .steps
+            .iter()
+            .map(|step| Duration::from_millis(step.offset_ms))
+            .find(|offset| *offset > elapsed)
+            // command_at returns None once past the last step
+            .unwrap_or(Duration::from_millis(last.offset_ms + 1));
+        self.start + offset
+    }
+}
+
+pub struct MacroLibrary {
+    directory: Option<PathBuf>,
+    macros: HashMap<String, GamepadMacro>,
+}
+
+impl MacroLibrary {
+    pub fn new(directory: Option<PathBuf>) -> Self {
+        Self {
+            directory,
+            macros: HashMap::new(),
+        }
+    }
+
+    pub fn save(&mut self, gamepad_macro: GamepadMacro) -> Result<()> {
+        validate_file_name(&gamepad_macro.name)?;
+        if let Some(directory) = &self.directory {
+            std::fs::create_dir_all(directory)?;
+            let file = std::fs::File::create(macro_path(directory, &gamepad_macro.name))?;
+            let writer = std::io::BufWriter::new(file);
+            serde_json::to_writer_pretty(writer, &gamepad_macro)?;
+        }
+        self.macros
+            .insert(gamepad_macro.name.clone(), gamepad_macro);
+        Ok(())
+    }
+
+    pub fn get(&mut self, name: &str) -> Result<GamepadMacro> {
+        validate_file_name(name)?;
+        if let Some(gamepad_macro) = self.macros.get(name) {
+            return Ok(gamepad_macro.clone());
+        }
+        let directory = self
+            .directory
+            .as_ref()
+            .ok_or_else(|| anyhow!("Macro {:?} not found", name))?;
+        let file = std::fs::File::open(macro_path(directory, name))?;
+        let reader = std::io::BufReader::new(file);
+        let gamepad_macro: GamepadMacro = serde_json::from_reader(reader)?;
+        self.macros.insert(name.to_owned(), gamepad_macro.clone());
+        Ok(gamepad_macro)
+    }
+}
+
+fn macro_path(directory: &Path, name: &str) -> PathBuf {
+    directory.join(format!("{}.json", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_square(start: Instant) -> GamepadMacro {
+        let mut recorder = MacroRecorder::start("square", start);
+        recorder.record(MoveCommand::new(1.0, 0.0, 0.0), start);
+        recorder.record(
+            MoveCommand::new(1.0, 0.0, 0.0),
+            start + Duration::from_millis(50),
+        );
+        recorder.record(
+            MoveCommand::new(0.0, 1.0, 0.0),
+            start + Duration::from_millis(100),
+        );
+        recorder.finish(start + Duration::from_millis(200))
+    }
+
+    #[test]
+    fn recording_skips_repeated_commands() {
+        let gamepad_macro = record_square(Instant::now());
+        assert_eq!(gamepad_macro.steps.len(), 3);
+        assert_eq!(gamepad_macro.steps[1].offset_ms, 100);
+        assert!(gamepad_macro.steps[2].command.is_stopped());
+    }
+
+    #[test]
+    fn playback_follows_recorded_timing() {
+        let start = Instant::now();
+        let playback = MacroPlayback::new(record_square(start), start);
+        assert_eq!(
+            playback.command_at(start + Duration::from_millis(20)),
+            Some(MoveCommand::new(1.0, 0.0, 0.0))
+        );
+        assert_eq!(
+            playback.command_at(start + Duration::from_millis(150)),
+            Some(MoveCommand::new(0.0, 1.0, 0.0))
+        );
+        assert_eq!(
+            playback.command_at(start + Duration::from_millis(200)),
+            Some(MoveCommand::default())
+        );
+        assert_eq!(
+            playback.command_at(start + Duration::from_millis(250)),
+            None
+        );
+    }
+
+    #[test]
+    fn next_change_follows_step_offsets() {
+        let start = Instant::now();
+        let playback = MacroPlayback::new(record_square(start), start);
+        assert_eq!(
+            playback.next_change(start + Duration::from_millis(20)),
+            start + Duration::from_millis(100)
+        );
+        assert_eq!(
+            playback.next_change(start + Duration::from_millis(100)),
+            start + Duration::from_millis(200)
+        );
+        let end = playback.next_change(start + Duration::from_millis(200));
+        assert_eq!(end, start + Duration::from_millis(201));
+        assert_eq!(playback.command_at(end), None);
+    }
+
+    #[test]
+    fn library_rejects_path_names() {
+        let mut library = MacroLibrary::new(Some(PathBuf::from("/tmp")));
+        assert!(library.get("../etc/passwd").is_err());
+    }
+
+    #[test]
+    fn macro_request_format() {
+        let request: MacroRequest =
+            serde_json::from_str(r#"{"command": "play", "name": "dance"}"#).unwrap();
+        assert_eq!(
+            request,
+            MacroRequest::Play {
+                name: String::from("dance")
+            }
+        );
+    }
+}
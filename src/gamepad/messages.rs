@@ -28,6 +28,35 @@ pub struct GamepadMessage {
     pub axis_state: BTreeMap<Axis, f32>,
 }
 
+impl GamepadMessage {
+    pub fn axis(&self, axis: Axis) -> f32 {
+        self.axis_state.get(&axis).cloned().unwrap_or_default()
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.button_down.get(&button).cloned().unwrap_or_default()
+    }
+}
+
+/// Velocity command for clients that don't speak the gamepad protocol
+///
+/// Values are in the same -1.0 to 1.0 range as gamepad axes.
+/// Positive strafe moves left and positive yaw turns counterclockwise.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct VelocityCommandMessage {
+    pub forward: f32,
+    pub strafe: f32,
+    pub yaw: f32,
+    /// How long the command should be applied for
+    ///
+    /// Falls back to a short timeout so that a lost client doesn't leave the robot driving
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+}
+
+/// JSON schema of [`VelocityCommandMessage`]
+pub const VELOCITY_COMMAND_SCHEMA: &str = include_str!("../../schemas/velocity_command.json");
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
 pub enum Button {
     South,
@@ -80,3 +109,91 @@ impl Axis {
         ]
     }
 }
+
+/// Reason the robot is sending feedback to the operator
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedbackEvent {
+    CollisionBlocked,
+    BatteryLow,
+    LidarStopped,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct Rumble {
+    /// Rumble strength from 0.0 to 1.0
+    pub intensity: f32,
+    pub duration_ms: u64,
+}
+
+impl FeedbackEvent {
+    pub fn default_rumble(&self) -> Rumble {
+        match self {
+            FeedbackEvent::CollisionBlocked => Rumble {
+                intensity: 1.0,
+                duration_ms: 300,
+            },
+            FeedbackEvent::BatteryLow => Rumble {
+                intensity: 0.5,
+                duration_ms: 1000,
+            },
+            FeedbackEvent::LidarStopped => Rumble {
+                intensity: 0.7,
+                duration_ms: 500,
+            },
+        }
+    }
+}
+
+/// Feedback published for the remote gamepad bridge
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct FeedbackMessage {
+    pub event: FeedbackEvent,
+    pub rumble: Rumble,
+    pub time: DateTime<Utc>,
+}
+
+impl FeedbackMessage {
+    pub fn new(event: FeedbackEvent) -> Self {
+        Self {
+            event,
+            rumble: event.default_rumble(),
+            time: Utc::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn velocity_command_without_duration() {
+        let message: VelocityCommandMessage =
+            serde_json::from_str(r#"{"forward": 0.5, "strafe": -0.2, "yaw": 0.1}"#).unwrap();
+        assert_eq!(message.forward, 0.5);
+        assert_eq!(message.strafe, -0.2);
+        assert_eq!(message.yaw, 0.1);
+        assert_eq!(message.duration_ms, None);
+    }
+
+    #[test]
+    fn velocity_command_schema_lists_all_fields() {
+        let schema: serde_json::Value = serde_json::from_str(VELOCITY_COMMAND_SCHEMA).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        let message = serde_json::to_value(VelocityCommandMessage {
+            forward: 0.0,
+            strafe: 0.0,
+            yaw: 0.0,
+            duration_ms: Some(100),
+        })
+        .unwrap();
+        for field in message.as_object().unwrap().keys() {
+            assert!(
+                properties.contains_key(field),
+                "{} missing in schema",
+                field
+            );
+        }
+    }
+}
@@ -1,106 +1,519 @@
-mod messages;
+pub mod arbitration;
+pub mod feedback;
+pub mod link_quality;
+pub mod macros;
+pub mod messages;
+pub mod teleop;
 
-use std::sync::Arc;
+use std::{
+    collections::BTreeSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
-use tracing::error;
+use chrono::Utc;
+use serde::Deserialize;
+use tracing::{error, info, warn};
 use zenoh::{prelude::r#async::*, subscriber::FlumeSubscriber, Session, SessionDeclarations};
 
 use crate::{
-    driver::HamiltonDriver, error::ErrorWrapper, holonomic_controller::HolonomicWheelCommand,
+    collision::CollisionDetector,
+    error::ErrorWrapper,
+    holonomic_controller::MoveCommand,
+    lidar::control::LIDAR_STATE_TOPIC,
+    navigation::{
+        docking::{DockingRequest, DOCKING_COMMAND_TOPIC},
+        mission::{MissionRequest, MISSION_COMMAND_TOPIC},
+        SharedNavigationController,
+    },
 };
-use messages::InputMessage;
+use arbitration::{ArbitrationConfig, GamepadArbiter};
+use feedback::{FeedbackConfig, FeedbackSender};
+use link_quality::{LinkQualityConfig, LinkQualityMonitor};
+use macros::{MacroConfig, MacroLibrary, MacroPlayback, MacroRecorder, MacroRequest};
+use messages::{Button, FeedbackEvent, GamepadMessage, InputMessage, VelocityCommandMessage};
+use teleop::{TeleopConfig, TeleopState};
+
+const GAMEPAD_TOPIC: &str = "remote-control/gamepad";
+const VELOCITY_COMMAND_TOPIC: &str = "remote-control/velocity";
+const ACTIVE_GAMEPAD_TOPIC: &str = "hamilton/gamepad/active";
+const MACRO_TOPIC: &str = "hamilton/gamepad/macro";
+const LINK_QUALITY_TOPIC: &str = "hamilton/gamepad/link_quality";
+
+/// How often a moving teleop command is re-issued so navigation keeps following it
+///
+/// Must stay well under `USER_COMMAND_TIMEOUT`. Refreshing is safe because every teleop
+/// command expires on its own, gamepad input once the gamepad link goes silent.
+const USER_COMMAND_REFRESH: Duration = Duration::from_millis(250);
+/// How often playback is re-checked for collisions between steps
+const PLAYBACK_TICK: Duration = Duration::from_millis(100);
+/// How often the lidar and battery are checked for operator feedback
+const MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+
+fn default_patrol_button() -> Button {
+    Button::DPadRight
+}
+
+fn default_patrol_mission() -> String {
+    String::from("patrol")
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatrolButtonConfig {
+    /// Starts the patrol mission or stops the running one
+    #[serde(default = "default_patrol_button")]
+    pub button: Button,
+    /// Mission loaded from the mission directory
+    #[serde(default = "default_patrol_mission")]
+    pub mission: String,
+}
+
+impl Default for PatrolButtonConfig {
+    fn default() -> Self {
+        Self {
+            button: default_patrol_button(),
+            mission: default_patrol_mission(),
+        }
+    }
+}
+
+fn default_home_button() -> Button {
+    Button::DPadLeft
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HomeButtonConfig {
+    /// Sends the robot home to dock
+    #[serde(default = "default_home_button")]
+    pub button: Button,
+}
+
+impl Default for HomeButtonConfig {
+    fn default() -> Self {
+        Self {
+            button: default_home_button(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GamepadConfig {
+    #[serde(default)]
+    pub arbitration: ArbitrationConfig,
+    #[serde(default)]
+    pub macros: MacroConfig,
+    #[serde(default)]
+    pub feedback: FeedbackConfig,
+    #[serde(default)]
+    pub link_quality: LinkQualityConfig,
+    #[serde(default)]
+    pub patrol: PatrolButtonConfig,
+    #[serde(default)]
+    pub home: HomeButtonConfig,
+    #[serde(default)]
+    pub teleop: TeleopConfig,
+}
+
+struct GamepadSubscribers {
+    gamepad: FlumeSubscriber<'static>,
+    velocity: FlumeSubscriber<'static>,
+    macros: FlumeSubscriber<'static>,
+}
+
+struct GamepadLoop {
+    navigation: SharedNavigationController,
+    /// Whether the last command sent to navigation was moving
+    user_driving: bool,
+    collision_detector: CollisionDetector,
+    zenoh_session: Arc<Session>,
+    feedback: FeedbackSender,
+    feedback_config: FeedbackConfig,
+    last_battery_check: Option<Instant>,
+    lidar_was_scanning: bool,
+    link_quality: LinkQualityMonitor,
+    link_degraded: bool,
+    macro_config: MacroConfig,
+    patrol_config: PatrolButtonConfig,
+    home_config: HomeButtonConfig,
+    arbiter: GamepadArbiter,
+    teleop: TeleopState,
+    buttons: ButtonEdges,
+    library: MacroLibrary,
+    recorder: Option<MacroRecorder>,
+    playback: Option<MacroPlayback>,
+}
 
 pub async fn start_gamepad_loop(
     zenoh_session: Arc<Session>,
-    mut driver: Box<dyn HamiltonDriver>,
+    navigation: SharedNavigationController,
+    collision_detector: CollisionDetector,
+    feedback: FeedbackSender,
+    config: GamepadConfig,
 ) -> Result<()> {
-    let mut gamepad_subscriber = zenoh_session
-        .declare_subscriber("remote-control/gamepad")
+    let gamepad_subscriber = zenoh_session
+        .declare_subscriber(GAMEPAD_TOPIC)
         .res()
         .await
         .map_err(ErrorWrapper::ZenohError)?;
 
-    tokio::spawn({
-        let zenoh_session = zenoh_session.clone();
-        async move {
-            while let Err(err) =
-                run_gamepad_listener(&mut gamepad_subscriber, &mut *driver, zenoh_session.clone())
-                    .await
-            {
-                error!("Gamepad listener failed with {:?}", err);
-            }
+    let velocity_subscriber = zenoh_session
+        .declare_subscriber(VELOCITY_COMMAND_TOPIC)
+        .res()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+
+    let macro_subscriber = zenoh_session
+        .declare_subscriber(MACRO_TOPIC)
+        .res()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+
+    let mut subscribers = GamepadSubscribers {
+        gamepad: gamepad_subscriber,
+        velocity: velocity_subscriber,
+        macros: macro_subscriber,
+    };
+
+    let mut gamepad_loop = GamepadLoop {
+        navigation,
+        user_driving: false,
+        collision_detector,
+        zenoh_session,
+        feedback,
+        feedback_config: config.feedback,
+        last_battery_check: None,
+        lidar_was_scanning: false,
+        link_quality: LinkQualityMonitor::new(config.link_quality.clone()),
+        link_degraded: false,
+        library: MacroLibrary::new(config.macros.directory.clone()),
+        macro_config: config.macros,
+        patrol_config: config.patrol,
+        home_config: config.home,
+        arbiter: GamepadArbiter::new(config.arbitration),
+        teleop: TeleopState::new(&config.teleop, config.link_quality.silence_timeout()),
+        buttons: ButtonEdges::default(),
+        recorder: None,
+        playback: None,
+    };
+
+    tokio::spawn(async move {
+        while let Err(err) = gamepad_loop.run(&mut subscribers).await {
+            error!("Gamepad listener failed with {:?}", err);
         }
     });
     Ok(())
 }
 
-async fn run_gamepad_listener(
-    subscriber: &mut FlumeSubscriber<'_>,
-    driver: &mut dyn HamiltonDriver,
-    zenoh_session: Arc<Session>,
-) -> anyhow::Result<()> {
-    loop {
-        let sample = subscriber.recv_async().await?;
-        let message: String = sample.value.try_into()?;
-        let message: InputMessage = serde_json::from_str(&message)?;
+impl GamepadLoop {
+    async fn run(&mut self, subscribers: &mut GamepadSubscribers) -> anyhow::Result<()> {
+        let mut monitor_interval = tokio::time::interval(MONITOR_INTERVAL);
+        loop {
+            let deadline = self.next_deadline();
+            tokio::select! {
+                sample = subscribers.gamepad.recv_async() => {
+                    let message: String = sample?.value.try_into()?;
+                    let message: InputMessage = serde_json::from_str(&message)?;
+                    self.handle_gamepad_message(&message).await?;
+                }
+                sample = subscribers.velocity.recv_async() => {
+                    let message: String = sample?.value.try_into()?;
+                    let message: VelocityCommandMessage = serde_json::from_str(&message)?;
+                    self.teleop.set_velocity(&message, Instant::now());
+                }
+                sample = subscribers.macros.recv_async() => {
+                    let message: String = sample?.value.try_into()?;
+                    match serde_json::from_str::<MacroRequest>(&message) {
+                        Ok(request) => self.handle_macro_request(request),
+                        Err(err) => warn!("Failed to parse macro request {:?}", err),
+                    }
+                }
+                _ = sleep_until_deadline(deadline) => {
+                    // velocity or gamepad command ran out, playback needs to advance
+                    // or the current command needs refreshing
+                }
+                _ = monitor_interval.tick() => {
+                    self.monitor().await;
+                    self.update_link_quality().await?;
+                    continue;
+                }
+            }
 
-        // tracing::info!(?message, "Received gamepad message");
-        if let Some(gamepad_message) = message.get_first() {
-            let x = apply_deadzone(
-                gamepad_message
-                    .axis_state
-                    .get(&messages::Axis::LeftStickY)
-                    .cloned()
-                    .unwrap_or_default(),
-            );
-            let y = apply_deadzone(
-                gamepad_message
-                    .axis_state
-                    .get(&messages::Axis::LeftStickX)
-                    .cloned()
-                    .unwrap_or_default(),
-            );
-            let yaw = apply_deadzone(
-                gamepad_message
-                    .axis_state
-                    .get(&messages::Axis::RightStickX)
-                    .cloned()
-                    .unwrap_or_default(),
-            );
-
-            if gamepad_message
-                .button_down
-                .get(&messages::Button::DPadDown)
-                .cloned()
-                .unwrap_or_default()
-            {
-                zenoh_session
-                    .put("rplidar/state", "off")
-                    .res_async()
-                    .await
-                    .map_err(ErrorWrapper::ZenohError)?;
+            self.drive().await?;
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        next_deadline(
+            &self.teleop,
+            self.playback.as_ref(),
+            self.user_driving,
+            Instant::now(),
+        )
+    }
+
+    async fn drive(&mut self) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let user_command = self.teleop.current_command(now);
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(user_command, now);
+        }
+
+        let mut command = user_command;
+        if let Some(playback) = &self.playback {
+            let name = playback.name().to_owned();
+            let playback_command = playback.command_at(now);
+            if !user_command.is_stopped() {
+                info!(%name, "Macro playback interrupted by user");
+                self.playback = None;
+            } else if let Some(playback_command) = playback_command {
+                if self.is_move_safe(&playback_command) {
+                    command = playback_command;
+                } else {
+                    warn!(%name, "Macro playback aborted by collision check");
+                    self.feedback.send(FeedbackEvent::CollisionBlocked);
+                    self.playback = None;
+                }
+            } else {
+                info!(%name, "Macro playback finished");
+                self.playback = None;
             }
+        }
+
+        // Navigation treats any user command as a preemption.
+        // Only send stop once so that an idle gamepad doesn't block navigation
+        if !command.is_stopped() || self.user_driving {
+            self.navigation.lock().await.issue_user_command(command);
+        }
+        self.user_driving = !command.is_stopped();
+        Ok(())
+    }
 
-            if gamepad_message
-                .button_down
-                .get(&messages::Button::DPadUp)
-                .cloned()
-                .unwrap_or_default()
-            {
-                zenoh_session
-                    .put("rplidar/state", "on")
-                    .res_async()
-                    .await
-                    .map_err(ErrorWrapper::ZenohError)?;
+    /// Check lidar and battery state and warn the operator about problems
+    async fn monitor(&mut self) {
+        if let Some(lidar) = self.collision_detector.lidar() {
+            let scanning = lidar.get_last_scan().is_some();
+            if self.lidar_was_scanning && !scanning && lidar.is_motor_on() {
+                warn!("Lidar stopped producing scans");
+                self.feedback.send(FeedbackEvent::LidarStopped);
             }
+            self.lidar_was_scanning = scanning;
+        }
 
-            let command = HolonomicWheelCommand::from_move(x, -y, -yaw);
-            driver.send(command).await?;
+        let battery_check_interval =
+            Duration::from_secs(self.feedback_config.battery_check_interval_s);
+        let battery_check_due = self
+            .last_battery_check
+            .is_none_or(|last_check| last_check.elapsed() >= battery_check_interval);
+        if battery_check_due {
+            self.last_battery_check = Some(Instant::now());
+            let voltage = self.navigation.lock().await.read_voltage().await;
+            match voltage {
+                Ok(Some(voltage)) if voltage < self.feedback_config.low_battery_voltage => {
+                    warn!(voltage, "Battery low");
+                    self.feedback.send(FeedbackEvent::BatteryLow);
+                }
+                Ok(_) => (),
+                Err(err) => warn!("Failed to read voltage {:?}", err),
+            }
+        }
+    }
+
+    fn is_move_safe(&self, command: &MoveCommand) -> bool {
+        !self.collision_detector.check(command).reason.is_blocked()
+    }
+
+    async fn update_link_quality(&mut self) -> anyhow::Result<()> {
+        let statistics = self.link_quality.statistics(Instant::now());
+        let degraded = statistics.speed_cap < 1.0;
+        if degraded != self.link_degraded && self.arbiter.active_gamepad().id.is_some() {
+            if degraded {
+                warn!(
+                    quality = statistics.quality,
+                    "Gamepad link degraded, capping speed"
+                );
+            } else {
+                info!(quality = statistics.quality, "Gamepad link recovered");
+            }
+        }
+        self.link_degraded = degraded;
+        self.teleop.set_gamepad_speed_cap(statistics.speed_cap);
+
+        let statistics = serde_json::to_string(&statistics)?;
+        self.zenoh_session
+            .put(LINK_QUALITY_TOPIC, statistics)
+            .congestion_control(CongestionControl::Drop)
+            .res_async()
+            .await
+            .map_err(ErrorWrapper::ZenohError)?;
+        Ok(())
+    }
+
+    async fn handle_gamepad_message(&mut self, message: &InputMessage) -> anyhow::Result<()> {
+        // tracing::info!(?message, "Received gamepad message");
+        self.link_quality
+            .record(message.time, Utc::now(), Instant::now());
+        let (selected, changed) = self.arbiter.select(message, Instant::now());
+        if changed {
+            let active_gamepad = serde_json::to_string(&self.arbiter.active_gamepad())?;
+            self.zenoh_session
+                .put(ACTIVE_GAMEPAD_TOPIC, active_gamepad)
+                .res_async()
+                .await
+                .map_err(ErrorWrapper::ZenohError)?;
+            self.buttons = ButtonEdges::default();
+        }
+
+        let Some(gamepad_message) = selected else {
+            self.teleop.clear_gamepad();
+            return Ok(());
+        };
+        self.buttons.update(&gamepad_message);
+
+        if gamepad_message.is_pressed(Button::DPadDown) {
+            self.zenoh_session
+                .put(LIDAR_STATE_TOPIC, "off")
+                .res_async()
+                .await
+                .map_err(ErrorWrapper::ZenohError)?;
+        }
+
+        if gamepad_message.is_pressed(Button::DPadUp) {
+            self.zenoh_session
+                .put(LIDAR_STATE_TOPIC, "on")
+                .res_async()
+                .await
+                .map_err(ErrorWrapper::ZenohError)?;
+        }
+
+        if self.buttons.just_pressed(self.macro_config.record_button) {
+            let request = if self.recorder.is_some() {
+                MacroRequest::StopRecording
+            } else {
+                MacroRequest::StartRecording {
+                    name: self.macro_config.default_name.clone(),
+                }
+            };
+            self.handle_macro_request(request);
+        }
+
+        if self.buttons.just_pressed(self.macro_config.play_button) {
+            self.handle_macro_request(MacroRequest::Play {
+                name: self.macro_config.default_name.clone(),
+            });
+        }
+
+        if self.buttons.just_pressed(self.patrol_config.button) {
+            let request = serde_json::to_string(&MissionRequest::Toggle {
+                name: self.patrol_config.mission.clone(),
+            })?;
+            self.zenoh_session
+                .put(MISSION_COMMAND_TOPIC, request)
+                .res_async()
+                .await
+                .map_err(ErrorWrapper::ZenohError)?;
+        }
+
+        if self.buttons.just_pressed(self.home_config.button) {
+            let request = serde_json::to_string(&DockingRequest::GoHome)?;
+            self.zenoh_session
+                .put(DOCKING_COMMAND_TOPIC, request)
+                .res_async()
+                .await
+                .map_err(ErrorWrapper::ZenohError)?;
+        }
+
+        self.teleop.set_gamepad(&gamepad_message, Instant::now());
+        Ok(())
+    }
+
+    fn handle_macro_request(&mut self, request: MacroRequest) {
+        let now = Instant::now();
+        match request {
+            MacroRequest::StartRecording { name } => {
+                self.playback = None;
+                self.recorder = Some(MacroRecorder::start(&name, now));
+            }
+            MacroRequest::StopRecording => {
+                if let Some(recorder) = self.recorder.take() {
+                    if let Err(err) = self.library.save(recorder.finish(now)) {
+                        error!("Failed to save macro {:?}", err);
+                    }
+                }
+            }
+            MacroRequest::Play { name } => {
+                if self.recorder.is_some() {
+                    warn!("Can't play macro while recording");
+                    return;
+                }
+                match self.library.get(&name) {
+                    Ok(gamepad_macro) => {
+                        self.playback = Some(MacroPlayback::new(gamepad_macro, now));
+                    }
+                    Err(err) => error!("Failed to load macro {:?}", err),
+                }
+            }
+            MacroRequest::StopPlayback => {
+                self.playback = None;
+            }
         }
     }
 }
 
+/// Tracks button state between messages to detect presses
+#[derive(Debug, Default)]
+struct ButtonEdges {
+    previous: BTreeSet<Button>,
+    pressed: BTreeSet<Button>,
+}
+
+impl ButtonEdges {
+    fn update(&mut self, gamepad: &GamepadMessage) {
+        let current: BTreeSet<Button> = gamepad
+            .button_down
+            .iter()
+            .filter(|(_, down)| **down)
+            .map(|(button, _)| *button)
+            .collect();
+        self.pressed = current.difference(&self.previous).cloned().collect();
+        self.previous = current;
+    }
+
+    fn just_pressed(&self, button: Button) -> bool {
+        self.pressed.contains(&button)
+    }
+}
+
+/// Next time the loop has to drive without a new message
+fn next_deadline(
+    teleop: &TeleopState,
+    playback: Option<&MacroPlayback>,
+    user_driving: bool,
+    now: Instant,
+) -> Option<Instant> {
+    // advance exactly at each recorded step and re-check collisions in between
+    let playback_deadline =
+        playback.map(|playback| playback.next_change(now).min(now + PLAYBACK_TICK));
+    let refresh_deadline = user_driving.then_some(now + USER_COMMAND_REFRESH);
+    [
+        teleop.velocity_deadline(),
+        teleop.gamepad_deadline(),
+        playback_deadline,
+        refresh_deadline,
+    ]
+    .into_iter()
+    .flatten()
+    .min()
+}
+
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
 fn apply_deadzone(value: f32) -> f32 {
     const DEADZONE: f32 = 0.07;
     if value.abs() < DEADZONE {
@@ -109,3 +522,92 @@ fn apply_deadzone(value: f32) -> f32 {
         value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::navigation::USER_COMMAND_TIMEOUT;
+
+    #[test]
+    fn held_stick_stops_when_gamepad_messages_stop() {
+        let start = Instant::now();
+        let mut teleop = TeleopState::default();
+        let mut gamepad = GamepadMessage::default();
+        gamepad.axis_state.insert(messages::Axis::LeftStickY, 0.8);
+        teleop.set_gamepad(&gamepad, start);
+        let last_message = start + Duration::from_millis(100);
+        teleop.set_gamepad(&gamepad, last_message);
+
+        // mirror the loop with no further messages
+        let mut now = last_message;
+        let mut user_driving = true;
+        let mut issued = vec![];
+        while now < start + Duration::from_secs(10) {
+            let command = teleop.current_command(now);
+            if !command.is_stopped() || user_driving {
+                issued.push((now, command));
+            }
+            user_driving = !command.is_stopped();
+            match next_deadline(&teleop, None, user_driving, now) {
+                Some(deadline) => now = deadline,
+                None => break,
+            }
+        }
+
+        let (stopped_at, last) = issued.last().unwrap();
+        assert!(last.is_stopped());
+        assert_eq!(
+            stopped_at.duration_since(last_message),
+            LinkQualityConfig::default().silence_timeout()
+        );
+        assert!(issued[..issued.len() - 1]
+            .iter()
+            .all(|(_, command)| !command.is_stopped()));
+    }
+
+    #[test]
+    fn long_velocity_command_is_refreshed_until_it_expires() {
+        let start = Instant::now();
+        let mut teleop = TeleopState::default();
+        teleop.set_velocity(
+            &VelocityCommandMessage {
+                forward: 0.5,
+                strafe: 0.0,
+                yaw: 0.0,
+                duration_ms: Some(2000),
+            },
+            start,
+        );
+
+        // mirror the loop, driving at every deadline
+        let mut now = start;
+        let mut user_driving = false;
+        let mut issued = vec![];
+        loop {
+            let command = teleop.current_command(now);
+            if !command.is_stopped() || user_driving {
+                issued.push((now, command));
+            }
+            user_driving = !command.is_stopped();
+            match next_deadline(&teleop, None, user_driving, now) {
+                Some(deadline) => now = deadline,
+                None => break,
+            }
+        }
+
+        for ((previous, _), (next, _)) in issued.iter().zip(issued.iter().skip(1)) {
+            assert!(next.duration_since(*previous) < USER_COMMAND_TIMEOUT);
+        }
+        let (last_moving, _) = issued
+            .iter()
+            .rfind(|(_, command)| !command.is_stopped())
+            .unwrap();
+        assert!(last_moving.duration_since(start) >= Duration::from_millis(1750));
+        let (stopped_at, last) = issued.last().unwrap();
+        assert!(last.is_stopped());
+        assert_eq!(
+            stopped_at.duration_since(start),
+            Duration::from_millis(2000)
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use super::{
+    apply_deadzone,
+    link_quality::LinkQualityConfig,
+    messages::{Axis, GamepadMessage, VelocityCommandMessage},
+};
+use crate::holonomic_controller::MoveCommand;
+
+/// Velocity commands without a duration expire after this
+pub const DEFAULT_VELOCITY_COMMAND_DURATION: Duration = Duration::from_millis(500);
+
+fn default_max_velocity_duration_ms() -> u64 {
+    2000
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TeleopConfig {
+    /// Longer velocity command durations are cut down to this
+    #[serde(default = "default_max_velocity_duration_ms")]
+    pub max_velocity_duration_ms: u64,
+}
+
+impl Default for TeleopConfig {
+    fn default() -> Self {
+        Self {
+            max_velocity_duration_ms: default_max_velocity_duration_ms(),
+        }
+    }
+}
+
+/// Merges gamepad input and velocity commands into a single move command
+///
+/// The gamepad always wins while its sticks are deflected.
+/// Velocity commands are used while the gamepad is idle until they expire.
+/// Gamepad input expires when no new message arrives within the gamepad timeout
+/// so that the robot stops when the link is lost with a stick held.
+#[derive(Debug)]
+pub struct TeleopState {
+    gamepad_command: MoveCommand,
+    gamepad_received: Instant,
+    gamepad_timeout: Duration,
+    gamepad_speed_cap: f32,
+    velocity_command: Option<(MoveCommand, Instant)>,
+    max_velocity_duration: Duration,
+}
+
+impl Default for TeleopState {
+    fn default() -> Self {
+        Self::new(
+            &TeleopConfig::default(),
+            LinkQualityConfig::default().silence_timeout(),
+        )
+    }
+}
+
+impl TeleopState {
+    pub fn new(config: &TeleopConfig, gamepad_timeout: Duration) -> Self {
+        Self {
+            gamepad_command: MoveCommand::default(),
+            gamepad_received: Instant::now(),
+            gamepad_timeout,
+            gamepad_speed_cap: 1.0,
+            velocity_command: None,
+            max_velocity_duration: Duration::from_millis(config.max_velocity_duration_ms),
+        }
+    }
+
+    pub fn set_gamepad(&mut self, gamepad: &GamepadMessage, now: Instant) {
+        self.gamepad_command = sanitize(MoveCommand::new(
+            gamepad.axis(Axis::LeftStickY),
+            -gamepad.axis(Axis::LeftStickX),
+            -gamepad.axis(Axis::RightStickX),
+        ));
+        self.gamepad_received = now;
+    }
+
+    /// Limit gamepad speed, used when the link to the gamepad is degraded
+    pub fn set_gamepad_speed_cap(&mut self, speed_cap: f32) {
+        self.gamepad_speed_cap = speed_cap.clamp(0.0, 1.0);
+    }
+
+    pub fn clear_gamepad(&mut self) {
+        self.gamepad_command = MoveCommand::default();
+    }
+
+    pub fn set_velocity(&mut self, message: &VelocityCommandMessage, now: Instant) {
+        let duration = message
+            .duration_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_VELOCITY_COMMAND_DURATION)
+            .min(self.max_velocity_duration);
+        let command = sanitize(MoveCommand::new(
+            message.forward,
+            message.strafe,
+            message.yaw,
+        ));
+        self.velocity_command = Some((command, now + duration));
+    }
+
+    /// Time at which the current velocity command runs out
+    pub fn velocity_deadline(&self) -> Option<Instant> {
+        self.velocity_command.map(|(_, deadline)| deadline)
+    }
+
+    /// Time at which the held gamepad command runs out unless another message arrives
+    pub fn gamepad_deadline(&self) -> Option<Instant> {
+        (!self.gamepad_command.is_stopped()).then_some(self.gamepad_received + self.gamepad_timeout)
+    }
+
+    pub fn current_command(&mut self, now: Instant) -> MoveCommand {
+        if let Some((_, deadline)) = self.velocity_command {
+            if now >= deadline {
+                self.velocity_command = None;
+            }
+        }
+        if self
+            .gamepad_deadline()
+            .is_some_and(|deadline| now >= deadline)
+        {
+            self.clear_gamepad();
+        }
+        if !self.gamepad_command.is_stopped() {
+            return self.gamepad_command.scaled(self.gamepad_speed_cap);
+        }
+        self.velocity_command
+            .map(|(command, _)| command)
+            .unwrap_or_default()
+    }
+}
+
+/// Shared safety path for all teleop sources
+fn sanitize(command: MoveCommand) -> MoveCommand {
+    MoveCommand::new(
+        apply_deadzone(command.forward()).clamp(-1.0, 1.0),
+        apply_deadzone(command.strafe()).clamp(-1.0, 1.0),
+        apply_deadzone(command.yaw()).clamp(-1.0, 1.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn velocity(forward: f32, duration_ms: Option<u64>) -> VelocityCommandMessage {
+        VelocityCommandMessage {
+            forward,
+            strafe: 0.0,
+            yaw: 0.0,
+            duration_ms,
+        }
+    }
+
+    #[test]
+    fn velocity_command_expires() {
+        let mut state = TeleopState::default();
+        let now = Instant::now();
+        state.set_velocity(&velocity(0.5, Some(100)), now);
+        assert_eq!(state.current_command(now).forward(), 0.5);
+        let later = now + Duration::from_millis(150);
+        assert!(state.current_command(later).is_stopped());
+        assert!(state.velocity_deadline().is_none());
+    }
+
+    #[test]
+    fn velocity_command_duration_is_capped() {
+        let mut state = TeleopState::new(
+            &TeleopConfig {
+                max_velocity_duration_ms: 2000,
+            },
+            Duration::from_millis(300),
+        );
+        let now = Instant::now();
+        state.set_velocity(&velocity(0.5, Some(u64::MAX)), now);
+        assert_eq!(
+            state.velocity_deadline(),
+            Some(now + Duration::from_millis(2000))
+        );
+        let later = now + Duration::from_millis(2001);
+        assert!(state.current_command(later).is_stopped());
+    }
+
+    #[test]
+    fn velocity_command_is_clamped_and_deadzoned() {
+        let mut state = TeleopState::default();
+        let now = Instant::now();
+        state.set_velocity(
+            &VelocityCommandMessage {
+                forward: 3.0,
+                strafe: 0.01,
+                yaw: -2.0,
+                duration_ms: None,
+            },
+            now,
+        );
+        assert_eq!(state.current_command(now), MoveCommand::new(1.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn gamepad_overrides_velocity_command() {
+        let mut state = TeleopState::default();
+        let now = Instant::now();
+        state.set_velocity(&velocity(0.5, None), now);
+
+        let mut gamepad = GamepadMessage::default();
+        gamepad.axis_state.insert(Axis::LeftStickY, -0.3);
+        state.set_gamepad(&gamepad, now);
+        assert_eq!(state.current_command(now).forward(), -0.3);
+
+        state.clear_gamepad();
+        assert_eq!(state.current_command(now).forward(), 0.5);
+    }
+
+    #[test]
+    fn speed_cap_only_applies_to_gamepad() {
+        let mut state = TeleopState::default();
+        let now = Instant::now();
+        state.set_gamepad_speed_cap(0.5);
+
+        let mut gamepad = GamepadMessage::default();
+        gamepad.axis_state.insert(Axis::LeftStickY, 0.8);
+        state.set_gamepad(&gamepad, now);
+        assert_eq!(state.current_command(now).forward(), 0.4);
+
+        state.clear_gamepad();
+        state.set_velocity(&velocity(0.8, None), now);
+        assert_eq!(state.current_command(now).forward(), 0.8);
+    }
+
+    #[test]
+    fn gamepad_command_expires_without_messages() {
+        let mut state = TeleopState::new(&TeleopConfig::default(), Duration::from_millis(300));
+        let now = Instant::now();
+        let mut gamepad = GamepadMessage::default();
+        gamepad.axis_state.insert(Axis::LeftStickY, 0.8);
+        state.set_gamepad(&gamepad, now);
+        assert_eq!(
+            state.gamepad_deadline(),
+            Some(now + Duration::from_millis(300))
+        );
+
+        let later = now + Duration::from_millis(200);
+        assert_eq!(state.current_command(later).forward(), 0.8);
+        state.set_gamepad(&gamepad, later);
+        assert_eq!(
+            state
+                .current_command(later + Duration::from_millis(200))
+                .forward(),
+            0.8
+        );
+        assert!(state
+            .current_command(later + Duration::from_millis(300))
+            .is_stopped());
+        assert!(state.gamepad_deadline().is_none());
+    }
+}
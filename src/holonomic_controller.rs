@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug)]
 pub struct HolonomicWheelCommand {
     left_front: f32,
@@ -62,7 +64,7 @@ impl HolonomicWheelCommand {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
 pub struct MoveCommand {
     forward: f32,
     strafe: f32,
@@ -93,4 +95,69 @@ impl MoveCommand {
     pub fn with_rotation_only(&self) -> MoveCommand {
         MoveCommand::new(0., 0., self.yaw)
     }
+
+    pub fn scaled(&self, factor: f32) -> MoveCommand {
+        MoveCommand::new(
+            self.forward * factor,
+            self.strafe * factor,
+            self.yaw * factor,
+        )
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.forward == 0.0 && self.strafe == 0.0 && self.yaw == 0.0
+    }
+}
+
+fn default_max_forward() -> f32 {
+    0.5
+}
+
+fn default_max_strafe() -> f32 {
+    0.5
+}
+
+fn default_max_yaw() -> f32 {
+    0.5
+}
+
+/// Speed limits of the base in normalised wheel units
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MotionLimits {
+    #[serde(default = "default_max_forward")]
+    pub max_forward: f32,
+    #[serde(default = "default_max_strafe")]
+    pub max_strafe: f32,
+    #[serde(default = "default_max_yaw")]
+    pub max_yaw: f32,
+}
+
+impl Default for MotionLimits {
+    fn default() -> Self {
+        Self {
+            max_forward: default_max_forward(),
+            max_strafe: default_max_strafe(),
+            max_yaw: default_max_yaw(),
+        }
+    }
+}
+
+impl MotionLimits {
+    /// Clamp each axis to its limit
+    ///
+    /// The command is then scaled down uniformly if any wheel would exceed full speed
+    /// so that the direction of motion is kept.
+    pub fn saturate(&self, command: &MoveCommand) -> MoveCommand {
+        let command = MoveCommand::new(
+            command.forward.clamp(-self.max_forward, self.max_forward),
+            command.strafe.clamp(-self.max_strafe, self.max_strafe),
+            command.yaw.clamp(-self.max_yaw, self.max_yaw),
+        );
+        let wheel_speed = command.forward.abs() + command.strafe.abs() + command.yaw.abs();
+        if wheel_speed > 1.0 {
+            command.scaled(1.0 / wheel_speed)
+        } else {
+            command
+        }
+    }
 }
@@ -1,4 +1,5 @@
 #![doc = include_str!("../README.md")]
+pub mod collision;
 pub mod configuration;
 pub mod driver;
 pub mod error;
@@ -10,5 +11,4 @@ pub mod localisation;
 pub mod logging;
 pub mod map;
 pub mod navigation;
-pub mod simple_collision_detector;
 pub mod util;
@@ -0,0 +1,255 @@
+use super::Lidar;
+use crate::error::ErrorWrapper;
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::*;
+use zenoh::{prelude::r#async::*, subscriber::FlumeSubscriber, Session, SessionDeclarations};
+
+/// Takes `on` or `off`
+pub const LIDAR_STATE_TOPIC: &str = "rplidar/state";
+pub const LIDAR_STATUS_TOPIC: &str = "hamilton/lidar/status";
+
+/// How often the idle timeout and status are checked
+const CONTROL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MotorStatus {
+    On,
+    Off,
+    /// Stopped because nothing needed scans, starts again when something does
+    Idle,
+}
+
+/// Motor state shared between lidar handles and the lidar thread
+#[derive(Debug)]
+pub struct MotorState {
+    should_spin: AtomicBool,
+    idle: AtomicBool,
+    last_demand: Mutex<Instant>,
+    /// When demand last woke the motor from an idle spin down
+    woken: Mutex<Option<Instant>>,
+}
+
+impl MotorState {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            should_spin: AtomicBool::new(true),
+            idle: AtomicBool::new(false),
+            last_demand: Mutex::new(now),
+            woken: Mutex::new(None),
+        }
+    }
+
+    pub fn should_spin(&self) -> bool {
+        self.should_spin.load(Ordering::SeqCst)
+    }
+
+    pub fn start(&self, now: Instant) {
+        *self.last_demand.lock().unwrap() = now;
+        *self.woken.lock().unwrap() = None;
+        self.idle.store(false, Ordering::SeqCst);
+        self.should_spin.store(true, Ordering::SeqCst);
+    }
+
+    /// Stop until explicitly started again
+    pub fn stop(&self) {
+        *self.woken.lock().unwrap() = None;
+        self.idle.store(false, Ordering::SeqCst);
+        self.should_spin.store(false, Ordering::SeqCst);
+    }
+
+    /// Record that scans are needed, waking the motor if it spun down for being idle
+    pub fn keep_awake(&self, now: Instant) {
+        *self.last_demand.lock().unwrap() = now;
+        if self.idle.swap(false, Ordering::SeqCst) {
+            info!("Lidar needed, spinning up");
+            *self.woken.lock().unwrap() = Some(now);
+            self.should_spin.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// When demand last woke the motor from an idle spin down
+    ///
+    /// Cleared by an explicit start or stop.
+    pub fn woken_at(&self) -> Option<Instant> {
+        *self.woken.lock().unwrap()
+    }
+
+    /// Stop the motor if nothing needed scans for the timeout
+    ///
+    /// Returns whether the motor was stopped
+    pub fn spin_down_if_idle(&self, timeout: Duration, now: Instant) -> bool {
+        let idle_for = now.saturating_duration_since(*self.last_demand.lock().unwrap());
+        if !self.should_spin() || idle_for < timeout {
+            return false;
+        }
+        self.idle.store(true, Ordering::SeqCst);
+        self.should_spin.store(false, Ordering::SeqCst);
+        true
+    }
+
+    pub fn status(&self) -> MotorStatus {
+        if self.should_spin() {
+            MotorStatus::On
+        } else if self.idle.load(Ordering::SeqCst) {
+            MotorStatus::Idle
+        } else {
+            MotorStatus::Off
+        }
+    }
+}
+
+/// Acknowledges motor requests and reports changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LidarStatus {
+    pub motor: MotorStatus,
+    /// Whether a recent scan is available
+    pub scanning: bool,
+}
+
+impl LidarStatus {
+    fn of(lidar: &Lidar) -> Self {
+        Self {
+            motor: lidar.motor_status(),
+            scanning: lidar.get_last_scan().is_some(),
+        }
+    }
+}
+
+/// Listen for motor requests and spin the lidar down when idle
+pub async fn start_lidar_control(
+    zenoh_session: Arc<Session>,
+    lidar: Lidar,
+    idle_timeout: Option<Duration>,
+) -> Result<()> {
+    let mut state_subscriber = zenoh_session
+        .declare_subscriber(LIDAR_STATE_TOPIC)
+        .res()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+
+    tokio::spawn(async move {
+        let mut lidar = lidar;
+        let mut last_status = None;
+        while let Err(err) = run_lidar_control(
+            &zenoh_session,
+            &mut state_subscriber,
+            &mut lidar,
+            idle_timeout,
+            &mut last_status,
+        )
+        .await
+        {
+            error!("Lidar control failed with {:?}", err);
+        }
+    });
+    Ok(())
+}
+
+async fn run_lidar_control(
+    zenoh_session: &Session,
+    state_subscriber: &mut FlumeSubscriber<'_>,
+    lidar: &mut Lidar,
+    idle_timeout: Option<Duration>,
+    last_status: &mut Option<LidarStatus>,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(CONTROL_INTERVAL);
+    loop {
+        let acknowledge = tokio::select! {
+            sample = state_subscriber.recv_async() => {
+                let message: String = sample?.value.try_into()?;
+                match message.trim().to_lowercase().as_str() {
+                    "on" => {
+                        info!("Lidar motor turned on");
+                        lidar.start_motor();
+                    }
+                    "off" => {
+                        info!("Lidar motor turned off");
+                        lidar.stop_motor();
+                    }
+                    other => warn!("Unknown lidar state request {:?}", other),
+                }
+                true
+            }
+            _ = interval.tick() => {
+                if let Some(timeout) = idle_timeout {
+                    if lidar.spin_down_if_idle(timeout) {
+                        info!("Nothing needed the lidar for {:?}, spinning down", timeout);
+                    }
+                }
+                false
+            }
+        };
+
+        let status = LidarStatus::of(lidar);
+        if acknowledge || *last_status != Some(status) {
+            *last_status = Some(status);
+            let message = serde_json::to_string(&status)?;
+            zenoh_session
+                .put(LIDAR_STATUS_TOPIC, message)
+                .res_async()
+                .await
+                .map_err(ErrorWrapper::ZenohError)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spins_down_when_idle_and_wakes_on_demand() {
+        let start = Instant::now();
+        let motor = MotorState::new(start);
+        let timeout = Duration::from_secs(60);
+        assert_eq!(motor.status(), MotorStatus::On);
+
+        motor.keep_awake(start + Duration::from_secs(30));
+        assert!(!motor.spin_down_if_idle(timeout, start + Duration::from_secs(60)));
+        assert!(motor.spin_down_if_idle(timeout, start + Duration::from_secs(90)));
+        assert_eq!(motor.status(), MotorStatus::Idle);
+        assert!(!motor.should_spin());
+
+        motor.keep_awake(start + Duration::from_secs(100));
+        assert_eq!(motor.status(), MotorStatus::On);
+        assert_eq!(motor.woken_at(), Some(start + Duration::from_secs(100)));
+        // only waking up counts
+        motor.keep_awake(start + Duration::from_secs(101));
+        assert_eq!(motor.woken_at(), Some(start + Duration::from_secs(100)));
+    }
+
+    #[test]
+    fn explicit_stop_ignores_demand() {
+        let start = Instant::now();
+        let motor = MotorState::new(start);
+        motor.stop();
+        motor.keep_awake(start + Duration::from_secs(1));
+        assert_eq!(motor.status(), MotorStatus::Off);
+        assert!(!motor.spin_down_if_idle(Duration::ZERO, start + Duration::from_secs(2)));
+        assert_eq!(motor.status(), MotorStatus::Off);
+
+        motor.start(start + Duration::from_secs(3));
+        assert_eq!(motor.status(), MotorStatus::On);
+    }
+
+    #[test]
+    fn status_format() {
+        let status = LidarStatus {
+            motor: MotorStatus::Idle,
+            scanning: false,
+        };
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"motor":"idle","scanning":false}"#
+        );
+    }
+}
@@ -0,0 +1,482 @@
+use rplidar_driver::ScanPoint;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{PI, TAU};
+
+fn default_min_range() -> f32 {
+    0.05
+}
+
+fn default_max_range() -> f32 {
+    12.0
+}
+
+fn default_median_window() -> usize {
+    5
+}
+
+fn default_max_neighbour_gap() -> f32 {
+    0.1
+}
+
+fn default_smoothing() -> f32 {
+    0.5
+}
+
+fn default_max_jump() -> f32 {
+    0.1
+}
+
+/// About half the RPLidar point spacing
+fn default_max_angle_difference_deg() -> f32 {
+    0.25
+}
+
+/// Stage of the scan filter chain
+///
+/// Filters never remove points, rejected points are made invalid by zeroing their distance.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FilterConfig {
+    /// Reject points outside the range in meters
+    Range {
+        #[serde(default = "default_min_range")]
+        min_range: f32,
+        #[serde(default = "default_max_range")]
+        max_range: f32,
+    },
+    Quality {
+        min_quality: u8,
+    },
+    /// Replace ranges with the median of their neighbours
+    Median {
+        #[serde(default = "default_median_window")]
+        window: usize,
+    },
+    /// Reject points with no neighbour within the gap in meters
+    Speckle {
+        #[serde(default = "default_max_neighbour_gap")]
+        max_neighbour_gap: f32,
+    },
+    /// Blend ranges with the previous scan unless they jumped by more than `max_jump` meters
+    Temporal {
+        /// Weight of the new range
+        #[serde(default = "default_smoothing")]
+        smoothing: f32,
+        #[serde(default = "default_max_jump")]
+        max_jump: f32,
+        /// Points are only blended with the closest point of the previous scan within this angle
+        #[serde(default = "default_max_angle_difference_deg")]
+        max_angle_difference_deg: f32,
+    },
+    /// Reject points in sectors blocked by the robot itself
+    Mask {
+        sectors: Vec<MaskSector>,
+    },
+}
+
+impl FilterConfig {
+    pub fn build(&self) -> Box<dyn ScanFilter> {
+        match self {
+            FilterConfig::Range {
+                min_range,
+                max_range,
+            } => Box::new(RangeFilter {
+                min_range: *min_range,
+                max_range: *max_range,
+            }),
+            FilterConfig::Quality { min_quality } => Box::new(QualityFilter {
+                min_quality: *min_quality,
+            }),
+            FilterConfig::Median { window } => Box::new(MedianFilter { window: *window }),
+            FilterConfig::Speckle { max_neighbour_gap } => Box::new(SpeckleFilter {
+                max_neighbour_gap: *max_neighbour_gap,
+            }),
+            FilterConfig::Temporal {
+                smoothing,
+                max_jump,
+                max_angle_difference_deg,
+            } => Box::new(TemporalFilter::new(
+                *smoothing,
+                *max_jump,
+                *max_angle_difference_deg,
+            )),
+            FilterConfig::Mask { sectors } => Box::new(MaskFilter {
+                sectors: sectors.clone(),
+            }),
+        }
+    }
+}
+
+/// Sector of scan angles in degrees clockwise from `start_deg` to `end_deg`
+///
+/// Wraps around when the start is past the end.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct MaskSector {
+    pub start_deg: f32,
+    pub end_deg: f32,
+}
+
+impl MaskSector {
+    pub fn contains(&self, angle_deg: f32) -> bool {
+        let angle = angle_deg.rem_euclid(360.0);
+        let start = self.start_deg.rem_euclid(360.0);
+        let end = self.end_deg.rem_euclid(360.0);
+        if start <= end {
+            (start..=end).contains(&angle)
+        } else {
+            angle >= start || angle <= end
+        }
+    }
+}
+
+/// Filters a full rotation sorted by angle in place
+pub trait ScanFilter: Send {
+    fn apply(&mut self, scan: &mut [ScanPoint]);
+}
+
+fn invalidate(point: &mut ScanPoint) {
+    point.set_distance(0.0);
+}
+
+pub struct RangeFilter {
+    pub min_range: f32,
+    pub max_range: f32,
+}
+
+impl ScanFilter for RangeFilter {
+    fn apply(&mut self, scan: &mut [ScanPoint]) {
+        for point in scan.iter_mut().filter(|point| point.is_valid()) {
+            if !(self.min_range..=self.max_range).contains(&point.distance()) {
+                invalidate(point);
+            }
+        }
+    }
+}
+
+pub struct QualityFilter {
+    pub min_quality: u8,
+}
+
+impl ScanFilter for QualityFilter {
+    fn apply(&mut self, scan: &mut [ScanPoint]) {
+        for point in scan.iter_mut() {
+            if point.quality < self.min_quality {
+                invalidate(point);
+            }
+        }
+    }
+}
+
+/// Median over a window of neighbouring valid points
+///
+/// The window wraps around the end of the scan.
+pub struct MedianFilter {
+    pub window: usize,
+}
+
+impl ScanFilter for MedianFilter {
+    fn apply(&mut self, scan: &mut [ScanPoint]) {
+        let ranges: Vec<f32> = scan.iter().map(ScanPoint::distance).collect();
+        let half = (self.window / 2).min(ranges.len() / 2);
+        let mut neighbours = Vec::with_capacity(half * 2 + 1);
+        for (index, point) in scan.iter_mut().enumerate() {
+            if !point.is_valid() {
+                continue;
+            }
+            neighbours.clear();
+            neighbours.extend(
+                (index + ranges.len() - half..=index + ranges.len() + half)
+                    .map(|neighbour| ranges[neighbour % ranges.len()])
+                    .filter(|range| *range > 0.0),
+            );
+            neighbours.sort_by(f32::total_cmp);
+            point.set_distance(neighbours[neighbours.len() / 2]);
+        }
+    }
+}
+
+/// Removes isolated points that don't belong to any surface
+pub struct SpeckleFilter {
+    pub max_neighbour_gap: f32,
+}
+
+impl ScanFilter for SpeckleFilter {
+    fn apply(&mut self, scan: &mut [ScanPoint]) {
+        let ranges: Vec<f32> = scan.iter().map(ScanPoint::distance).collect();
+        let count = ranges.len();
+        for (index, point) in scan.iter_mut().enumerate() {
+            if !point.is_valid() {
+                continue;
+            }
+            let range = ranges[index];
+            let has_neighbour = [(index + count - 1) % count, (index + 1) % count]
+                .iter()
+                .filter(|neighbour| **neighbour != index)
+                .any(|neighbour| {
+                    ranges[*neighbour] > 0.0
+                        && (ranges[*neighbour] - range).abs() <= self.max_neighbour_gap
+                });
+            if !has_neighbour {
+                invalidate(point);
+            }
+        }
+    }
+}
+
+/// Exponential smoothing of ranges across scans
+///
+/// Every point is blended with the point of the previous scan closest in angle,
+/// so neighbouring points are never mixed up and edges stay sharp.
+/// Points that jumped are taken as is so moving obstacles aren't smeared.
+pub struct TemporalFilter {
+    smoothing: f32,
+    max_jump: f32,
+    max_angle_difference: f32,
+    /// Valid points of the previous scan as angle and range, sorted by angle
+    previous: Vec<(f32, f32)>,
+}
+
+impl TemporalFilter {
+    pub fn new(smoothing: f32, max_jump: f32, max_angle_difference_deg: f32) -> Self {
+        Self {
+            smoothing: smoothing.clamp(0.0, 1.0),
+            max_jump,
+            max_angle_difference: max_angle_difference_deg.max(0.0).to_radians(),
+            previous: vec![],
+        }
+    }
+
+    /// Range of the previous point closest in angle, if close enough
+    fn previous_range(&self, angle: f32) -> Option<f32> {
+        if self.previous.is_empty() {
+            return None;
+        }
+        let index = self
+            .previous
+            .partition_point(|(previous, _)| *previous < angle);
+        // neighbours on both sides, wrapping around
+        let before = self.previous[(index + self.previous.len() - 1) % self.previous.len()];
+        let after = self.previous[index % self.previous.len()];
+        [before, after]
+            .into_iter()
+            .map(|(previous, range)| (angle_difference(previous, angle), range))
+            .filter(|(difference, _)| *difference <= self.max_angle_difference)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, range)| range)
+    }
+}
+
+/// Absolute difference between angles in radians
+fn angle_difference(a: f32, b: f32) -> f32 {
+    ((a - b + PI).rem_euclid(TAU) - PI).abs()
+}
+
+impl ScanFilter for TemporalFilter {
+    fn apply(&mut self, scan: &mut [ScanPoint]) {
+        let mut current = Vec::with_capacity(scan.len());
+        for point in scan.iter_mut().filter(|point| point.is_valid()) {
+            let range = point.distance();
+            if let Some(previous) = self.previous_range(point.angle()) {
+                if (range - previous).abs() <= self.max_jump {
+                    point.set_distance(previous + self.smoothing * (range - previous));
+                }
+            }
+            current.push((point.angle(), point.distance()));
+        }
+        current.sort_by(|a, b| a.0.total_cmp(&b.0));
+        self.previous = current;
+    }
+}
+
+pub struct MaskFilter {
+    pub sectors: Vec<MaskSector>,
+}
+
+impl ScanFilter for MaskFilter {
+    fn apply(&mut self, scan: &mut [ScanPoint]) {
+        for point in scan.iter_mut() {
+            let angle = point.angle().to_degrees();
+            if self.sectors.iter().any(|sector| sector.contains(angle)) {
+                invalidate(point);
+            }
+        }
+    }
+}
+
+/// Filters applied in order to every scan
+#[derive(Default)]
+pub struct ScanFilterChain {
+    filters: Vec<Box<dyn ScanFilter>>,
+}
+
+impl ScanFilterChain {
+    pub fn new(filters: Vec<Box<dyn ScanFilter>>) -> Self {
+        Self { filters }
+    }
+
+    pub fn from_config(config: &[FilterConfig]) -> Self {
+        Self::new(config.iter().map(FilterConfig::build).collect())
+    }
+
+    pub fn apply(&mut self, scan: &mut [ScanPoint]) {
+        for filter in &mut self.filters {
+            filter.apply(scan);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// Points one degree apart starting at 0
+    fn scan(ranges: &[f32]) -> Vec<ScanPoint> {
+        ranges
+            .iter()
+            .enumerate()
+            .map(|(index, range)| {
+                let mut point = ScanPoint {
+                    angle_z_q14: 0,
+                    dist_mm_q2: 0,
+                    quality: 100,
+                    flag: 0,
+                };
+                point.set_angle((index as f32).to_radians());
+                point.set_distance(*range);
+                point
+            })
+            .collect()
+    }
+
+    fn ranges(scan: &[ScanPoint]) -> Vec<f32> {
+        scan.iter()
+            .map(|point| (point.distance() * 100.0).round() / 100.0)
+            .collect()
+    }
+
+    #[test]
+    fn range_filter_rejects_out_of_range() {
+        let mut points = scan(&[0.02, 1.0, 15.0, 0.0]);
+        RangeFilter {
+            min_range: 0.05,
+            max_range: 12.0,
+        }
+        .apply(&mut points);
+        assert_eq!(ranges(&points), vec![0.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn quality_filter_rejects_weak_returns() {
+        let mut points = scan(&[1.0, 1.0]);
+        points[0].quality = 5;
+        QualityFilter { min_quality: 10 }.apply(&mut points);
+        assert_eq!(ranges(&points), vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn median_filter_removes_spikes() {
+        let mut points = scan(&[1.0, 1.0, 3.0, 1.0, 1.0, 0.0]);
+        MedianFilter { window: 3 }.apply(&mut points);
+        assert_eq!(ranges(&points), vec![1.0, 1.0, 1.0, 1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn speckle_filter_removes_isolated_points() {
+        let mut points = scan(&[1.0, 1.05, 0.0, 2.0, 0.0, 3.0, 3.5, 1.0]);
+        SpeckleFilter {
+            max_neighbour_gap: 0.1,
+        }
+        .apply(&mut points);
+        // the last point wraps around to the first
+        assert_eq!(
+            ranges(&points),
+            vec![1.0, 1.05, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn temporal_filter_smooths_but_follows_jumps() {
+        let mut filter = TemporalFilter::new(0.5, 0.2, 0.25);
+        let mut first = scan(&[1.0, 2.0]);
+        filter.apply(&mut first);
+        assert_eq!(ranges(&first), vec![1.0, 2.0]);
+
+        let mut second = scan(&[1.1, 1.0]);
+        filter.apply(&mut second);
+        assert_relative_eq!(second[0].distance(), 1.05, epsilon = 1e-2);
+        // jumped, so taken as is
+        assert_relative_eq!(second[1].distance(), 1.0, epsilon = 1e-2);
+    }
+
+    #[test]
+    fn temporal_filter_keeps_close_points_apart() {
+        // RPLidar spacing, both points would share a 1 degree bin
+        let scan_at = |ranges: &[f32]| -> Vec<ScanPoint> {
+            ranges
+                .iter()
+                .enumerate()
+                .map(|(i, range)| {
+                    let mut point = scan(&[*range]).remove(0);
+                    point.set_angle((10.0 + i as f32 * 0.45).to_radians());
+                    point
+                })
+                .collect()
+        };
+        let mut filter = TemporalFilter::new(0.5, 0.2, 0.25);
+        let mut first = scan_at(&[1.0, 1.15]);
+        filter.apply(&mut first);
+
+        let mut second = scan_at(&[1.0, 1.15]);
+        filter.apply(&mut second);
+        assert_relative_eq!(second[0].distance(), 1.0, epsilon = 1e-2);
+        assert_relative_eq!(second[1].distance(), 1.15, epsilon = 1e-2);
+
+        // matched to the closest point even when the angles shift a little
+        let mut shifted = scan_at(&[1.1, 1.15]);
+        for point in &mut shifted {
+            point.set_angle(point.angle() + 0.1_f32.to_radians());
+        }
+        filter.apply(&mut shifted);
+        assert_relative_eq!(shifted[0].distance(), 1.05, epsilon = 1e-2);
+        assert_relative_eq!(shifted[1].distance(), 1.15, epsilon = 1e-2);
+    }
+
+    #[test]
+    fn mask_filter_rejects_sectors() {
+        let sector = MaskSector {
+            start_deg: 350.0,
+            end_deg: 10.0,
+        };
+        assert!(sector.contains(355.0));
+        assert!(sector.contains(5.0));
+        assert!(!sector.contains(180.0));
+
+        let mut points = scan(&[1.0, 1.0, 1.0]);
+        MaskFilter {
+            sectors: vec![MaskSector {
+                start_deg: 0.5,
+                end_deg: 1.5,
+            }],
+        }
+        .apply(&mut points);
+        assert_eq!(ranges(&points), vec![1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn chain_from_config() {
+        let config: Vec<FilterConfig> = serde_json::from_str(
+            r#"[
+                {"type": "quality", "min_quality": 10},
+                {"type": "range", "max_range": 2.0},
+                {"type": "mask", "sectors": [{"start_deg": 1.5, "end_deg": 2.5}]}
+            ]"#,
+        )
+        .unwrap();
+        let mut chain = ScanFilterChain::from_config(&config);
+        let mut points = scan(&[1.0, 3.0, 1.0, 1.0]);
+        points[3].quality = 0;
+        chain.apply(&mut points);
+        assert_eq!(ranges(&points), vec![1.0, 0.0, 0.0, 0.0]);
+    }
+}
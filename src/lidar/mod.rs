@@ -0,0 +1,424 @@
+pub mod control;
+pub mod filter;
+pub mod publisher;
+pub mod recording;
+pub mod simulator;
+pub mod source;
+
+use crate::navigation::Pose2d;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use control::{MotorState, MotorStatus};
+use filter::{FilterConfig, ScanFilterChain};
+use nalgebra as na;
+use publisher::ScanPublishConfig;
+use recording::{RecordingConfig, ReplaySource};
+use rplidar_driver::ScanPoint;
+use serde::{Deserialize, Serialize};
+use simulator::SimulatedLidar;
+use source::{LidarSource, LidarSourceConfig, RplidarSource, SourceScan};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::spawn,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tracing::*;
+
+/// Full rotation of the lidar
+#[derive(Debug, Clone)]
+pub struct LidarScan {
+    /// Points after the filter chain
+    pub points: Vec<ScanPoint>,
+    /// Points as the source reported them
+    pub raw: Vec<ScanPoint>,
+    /// Counts up from 0 for every scan since the lidar was opened
+    pub sequence: u64,
+    pub received: Instant,
+    pub time: DateTime<Utc>,
+}
+
+type ScanSender = Arc<watch::Sender<Option<LidarScan>>>;
+
+fn default_lidar_port() -> String {
+    String::from("/dev/rplidar")
+}
+
+fn default_idle_timeout_s() -> Option<u64> {
+    Some(120)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LidarConfig {
+    #[serde(default = "default_lidar_port")]
+    pub port: String,
+    #[serde(default)]
+    pub mount: LidarMount,
+    #[serde(default)]
+    pub publish: ScanPublishConfig,
+    /// Spin down after nothing needed scans for this long
+    #[serde(default = "default_idle_timeout_s")]
+    pub idle_timeout_s: Option<u64>,
+    #[serde(default)]
+    pub recording: RecordingConfig,
+    #[serde(default)]
+    pub source: LidarSourceConfig,
+    /// Applied in order to every scan
+    #[serde(default)]
+    pub filters: Vec<FilterConfig>,
+}
+
+impl LidarConfig {
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_s.map(Duration::from_secs)
+    }
+}
+
+/// Where the lidar sits on the robot
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct LidarMount {
+    /// Offset from the robot origin in meters
+    #[serde(default)]
+    pub x: f32,
+    #[serde(default)]
+    pub y: f32,
+    /// Rotation of the lidar front from the robot front in radians counter clockwise
+    #[serde(default)]
+    pub yaw: f32,
+    /// Mounted upside down
+    #[serde(default)]
+    pub flipped: bool,
+}
+
+impl LidarMount {
+    pub fn pose(&self) -> Pose2d {
+        Pose2d::new((self.x, self.y), self.yaw)
+    }
+
+    /// Scan point in the robot frame
+    ///
+    /// Scan angles grow clockwise when looking down on an upright lidar
+    /// and counter clockwise when it is flipped.
+    pub fn to_robot(&self, point: &ScanPoint) -> na::Point2<f32> {
+        let angle = if self.flipped {
+            point.angle()
+        } else {
+            -point.angle()
+        };
+        let local = na::Point2::new(
+            point.distance() * angle.cos(),
+            point.distance() * angle.sin(),
+        );
+        self.pose().transform_point(&local)
+    }
+
+    /// Valid points of a scan in the robot frame
+    pub fn scan_to_robot(&self, scan: &[ScanPoint]) -> Vec<na::Point2<f32>> {
+        scan.iter()
+            .filter(|point| point.is_valid())
+            .map(|point| self.to_robot(point))
+            .collect()
+    }
+}
+
+/// Handle to the lidar thread
+///
+/// Clones share the same lidar. The thread exits once the last clone is dropped
+#[derive(Clone)]
+pub struct Lidar {
+    mount: LidarMount,
+    motor: Arc<MotorState>,
+    last_scan: ScanSender,
+    _shutdown: Arc<LidarShutdown>,
+}
+
+struct LidarShutdown {
+    should_exit: Arc<AtomicBool>,
+    motor: Arc<MotorState>,
+}
+
+const SCAN_TIMEOUT: Duration = Duration::from_millis(500);
+/// Longest a wake up from an idle spin down is waited for before giving up on scans
+const WAKE_UP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Wait before restarting a failed lidar source
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+impl Lidar {
+    pub fn open(config: LidarConfig) -> Result<Self> {
+        let filters = ScanFilterChain::from_config(&config.filters);
+        match config.source {
+            LidarSourceConfig::Rplidar => Ok(Self::from_source(
+                config.mount,
+                filters,
+                RplidarSource::new(config.port),
+            )),
+            LidarSourceConfig::Replay(replay) => {
+                info!(file = ?replay.file, "Replaying lidar recording");
+                let source = ReplaySource::open(replay)?;
+                // scans are only meaningful with the mount they were recorded with
+                Ok(Self::from_source(
+                    source.header().lidar.mount,
+                    filters,
+                    source,
+                ))
+            }
+            LidarSourceConfig::Simulated(simulation) => Ok(Self::from_source(
+                config.mount,
+                filters,
+                SimulatedLidar::new(simulation, config.mount),
+            )),
+        }
+    }
+
+    /// Run any scan source on the lidar thread
+    ///
+    /// Its scans are filtered and handed out exactly like those of the RPLidar.
+    pub fn from_source(
+        mount: LidarMount,
+        filters: ScanFilterChain,
+        source: impl LidarSource + 'static,
+    ) -> Self {
+        let last_scan = Arc::new(watch::Sender::new(None));
+        let should_exit = Arc::new(AtomicBool::new(false));
+        let motor = Arc::new(MotorState::new(Instant::now()));
+        spawn({
+            let last_scan = Arc::clone(&last_scan);
+            let should_exit = Arc::clone(&should_exit);
+            let motor = Arc::clone(&motor);
+            move || run_lidar_loop(Box::new(source), filters, should_exit, motor, last_scan)
+        });
+        Self {
+            mount,
+            motor: Arc::clone(&motor),
+            last_scan,
+            _shutdown: Arc::new(LidarShutdown { should_exit, motor }),
+        }
+    }
+
+    pub fn stop_motor(&mut self) {
+        self.motor.stop();
+    }
+
+    pub fn start_motor(&mut self) {
+        self.motor.start(Instant::now());
+    }
+
+    pub fn is_motor_on(&self) -> bool {
+        self.motor.should_spin()
+    }
+
+    pub fn motor_status(&self) -> MotorStatus {
+        self.motor.status()
+    }
+
+    /// Record that scans are needed so the lidar doesn't spin down
+    pub fn keep_awake(&self) {
+        self.motor.keep_awake(Instant::now());
+    }
+
+    /// Whether the lidar is spun down for being idle or hasn't scanned since waking up
+    ///
+    /// Gives up after `WAKE_UP_TIMEOUT` so that a lidar which never comes back isn't waited for.
+    pub fn is_waking(&self) -> bool {
+        if self.motor.status() == MotorStatus::Idle {
+            return true;
+        }
+        let Some(woken) = self.motor.woken_at() else {
+            return false;
+        };
+        let scanned_since = self
+            .last_scan
+            .borrow()
+            .as_ref()
+            .is_some_and(|scan| scan.received >= woken);
+        !scanned_since && woken.elapsed() < WAKE_UP_TIMEOUT
+    }
+
+    /// Returns whether the motor was stopped
+    pub fn spin_down_if_idle(&self, timeout: Duration) -> bool {
+        self.motor.spin_down_if_idle(timeout, Instant::now())
+    }
+
+    pub fn mount(&self) -> &LidarMount {
+        &self.mount
+    }
+
+    /// Latest scan in the robot frame
+    pub fn get_last_scan_points(&self) -> Option<Vec<na::Point2<f32>>> {
+        self.get_last_scan()
+            .map(|scan| self.mount.scan_to_robot(&scan))
+    }
+
+    pub fn get_last_scan(&self) -> Option<Vec<ScanPoint>> {
+        self.last_scan
+            .borrow()
+            .as_ref()
+            .filter(|scan| scan.received.elapsed() < SCAN_TIMEOUT)
+            .map(|scan| scan.points.clone())
+    }
+
+    /// Receiver notified on every new scan
+    pub fn subscribe_scans(&self) -> watch::Receiver<Option<LidarScan>> {
+        self.last_scan.subscribe()
+    }
+}
+
+impl Drop for LidarShutdown {
+    fn drop(&mut self) {
+        self.motor.stop();
+        self.should_exit.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Filter a scan and hand it to subscribers with the next sequence number
+fn publish_scan(
+    last_scan: &ScanSender,
+    filters: &mut ScanFilterChain,
+    raw: Vec<ScanPoint>,
+    time: DateTime<Utc>,
+) {
+    let mut points = raw.clone();
+    filters.apply(&mut points);
+    let sequence = last_scan
+        .borrow()
+        .as_ref()
+        .map_or(0, |last| last.sequence + 1);
+    last_scan.send_replace(Some(LidarScan {
+        points,
+        raw,
+        sequence,
+        received: Instant::now(),
+        time,
+    }));
+}
+
+fn run_lidar_loop(
+    mut source: Box<dyn LidarSource>,
+    mut filters: ScanFilterChain,
+    exit_loop: Arc<AtomicBool>,
+    motor: Arc<MotorState>,
+    last_scan: ScanSender,
+) {
+    while !exit_loop.load(Ordering::SeqCst) {
+        info!("Starting lidar task");
+        match inner_lidar_loop(
+            source.as_mut(),
+            &mut filters,
+            &exit_loop,
+            &motor,
+            &last_scan,
+        ) {
+            Ok(()) => return,
+            Err(err) => {
+                error!("Lidar task failed {:?}", err);
+                std::thread::sleep(RESTART_DELAY);
+            }
+        }
+    }
+}
+
+/// Returns once asked to exit or the source finished
+fn inner_lidar_loop(
+    source: &mut dyn LidarSource,
+    filters: &mut ScanFilterChain,
+    exit_loop: &AtomicBool,
+    motor: &MotorState,
+    last_scan: &ScanSender,
+) -> Result<()> {
+    let mut is_spinning = true;
+    while !exit_loop.load(Ordering::SeqCst) {
+        if motor.should_spin() {
+            if !is_spinning {
+                is_spinning = true;
+                source.start_motor()?;
+            }
+            match source.grab_scan()? {
+                SourceScan::Scan { points, time } => publish_scan(last_scan, filters, points, time),
+                SourceScan::Timeout => (),
+                SourceScan::Finished => {
+                    info!("Lidar source finished");
+                    return Ok(());
+                }
+            }
+        } else {
+            // shouldn't spin
+            if is_spinning {
+                is_spinning = false;
+                source.stop_motor()?;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(500));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// Scan point at an angle in degrees clockwise from the front of the lidar
+    fn scan_point(angle_deg: f32, distance: f32) -> ScanPoint {
+        let mut point = ScanPoint {
+            angle_z_q14: 0,
+            dist_mm_q2: 0,
+            quality: 255,
+            flag: 0,
+        };
+        point.set_angle(angle_deg.rem_euclid(360.0).to_radians());
+        point.set_distance(distance);
+        point
+    }
+
+    #[test]
+    fn upright_lidar_angles_are_clockwise() {
+        let mount = LidarMount::default();
+        let ahead = mount.to_robot(&scan_point(0.0, 1.0));
+        assert_relative_eq!(ahead, na::Point2::new(1.0, 0.0), epsilon = 1e-3);
+        let left = mount.to_robot(&scan_point(270.0, 0.5));
+        assert_relative_eq!(left, na::Point2::new(0.0, 0.5), epsilon = 1e-3);
+    }
+
+    #[test]
+    fn flipped_lidar_angles_are_counter_clockwise() {
+        let mount = LidarMount {
+            flipped: true,
+            ..Default::default()
+        };
+        let left = mount.to_robot(&scan_point(90.0, 0.5));
+        assert_relative_eq!(left, na::Point2::new(0.0, 0.5), epsilon = 1e-3);
+    }
+
+    #[test]
+    fn mount_offset_and_yaw_are_applied() {
+        // at the back of the robot facing backwards
+        let mount = LidarMount {
+            x: -0.2,
+            yaw: std::f32::consts::PI,
+            ..Default::default()
+        };
+        let point = mount.to_robot(&scan_point(180.0, 0.5));
+        assert_relative_eq!(point, na::Point2::new(0.3, 0.0), epsilon = 1e-3);
+        let point = mount.to_robot(&scan_point(0.0, 0.5));
+        assert_relative_eq!(point, na::Point2::new(-0.7, 0.0), epsilon = 1e-3);
+    }
+
+    #[test]
+    fn invalid_points_are_dropped() {
+        let scan = [scan_point(0.0, 1.0), scan_point(10.0, 0.0)];
+        assert_eq!(LidarMount::default().scan_to_robot(&scan).len(), 1);
+    }
+
+    #[test]
+    fn parses_mount() {
+        let config: LidarConfig =
+            serde_json::from_str(r#"{"mount": {"x": 0.1, "yaw": 3.14, "flipped": true}}"#).unwrap();
+        assert_eq!(config.port, "/dev/rplidar");
+        assert_eq!(config.mount.x, 0.1);
+        assert_eq!(config.mount.y, 0.0);
+        assert!(config.mount.flipped);
+    }
+}
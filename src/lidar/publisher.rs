@@ -0,0 +1,290 @@
+use super::{Lidar, LidarScan};
+use crate::error::ErrorWrapper;
+use anyhow::{ensure, Context};
+use bytes::{Buf, BufMut, BytesMut};
+use chrono::{DateTime, Utc};
+use rplidar_driver::ScanPoint;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::*;
+use zenoh::{prelude::r#async::*, Session};
+
+pub const SCAN_TOPIC: &str = "hamilton/lidar/scan";
+pub const SCAN_JSON_TOPIC: &str = "hamilton/lidar/scan/json";
+
+/// Bumped whenever the binary layout changes
+const SCAN_ENCODING_VERSION: u8 = 2;
+/// Version, sequence, timestamp and point count
+const HEADER_SIZE: usize = 1 + 8 + 8 + 4;
+/// Angle, distance, quality and flag
+const POINT_SIZE: usize = 2 + 4 + 1 + 1;
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_json_decimation() -> usize {
+    8
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ScanPublishConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Only every nth point is included in the JSON scan
+    #[serde(default = "default_json_decimation")]
+    pub json_decimation: usize,
+}
+
+impl Default for ScanPublishConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            json_decimation: default_json_decimation(),
+        }
+    }
+}
+
+/// Lidar scan as published over zenoh
+///
+/// Angles are in radians clockwise from the front of the lidar and ranges in meters.
+/// Invalid points have a range of 0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanMessage {
+    pub sequence: u64,
+    pub time: DateTime<Utc>,
+    pub angles: Vec<f32>,
+    pub ranges: Vec<f32>,
+    pub qualities: Vec<u8>,
+}
+
+impl ScanMessage {
+    /// Filtered points of a scan
+    pub fn from_scan(scan: &LidarScan) -> Self {
+        Self::from(&FixedPointScan::from_scan(scan))
+    }
+
+    /// Decode a binary scan
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self::from(&FixedPointScan::decode(data)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.angles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.angles.is_empty()
+    }
+
+    /// Every nth point
+    pub fn decimated(&self, step: usize) -> Self {
+        let step = step.max(1);
+        Self {
+            sequence: self.sequence,
+            time: self.time,
+            angles: self.angles.iter().step_by(step).copied().collect(),
+            ranges: self.ranges.iter().step_by(step).copied().collect(),
+            qualities: self.qualities.iter().step_by(step).copied().collect(),
+        }
+    }
+}
+
+impl From<&FixedPointScan> for ScanMessage {
+    fn from(scan: &FixedPointScan) -> Self {
+        Self {
+            sequence: scan.sequence,
+            time: scan.time,
+            angles: scan.points.iter().map(ScanPoint::angle).collect(),
+            ranges: scan.points.iter().map(ScanPoint::distance).collect(),
+            qualities: scan.points.iter().map(|point| point.quality).collect(),
+        }
+    }
+}
+
+/// Scan points exactly as the lidar reported them
+///
+/// This is what the binary encoding carries so that nothing is lost on the way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedPointScan {
+    pub sequence: u64,
+    pub time: DateTime<Utc>,
+    pub points: Vec<ScanPoint>,
+}
+
+impl FixedPointScan {
+    /// Filtered points of a scan
+    pub fn from_scan(scan: &LidarScan) -> Self {
+        Self::from_points(scan, &scan.points)
+    }
+
+    /// Points of a scan before filtering
+    pub fn from_raw_scan(scan: &LidarScan) -> Self {
+        Self::from_points(scan, &scan.raw)
+    }
+
+    fn from_points(scan: &LidarScan, points: &[ScanPoint]) -> Self {
+        Self {
+            sequence: scan.sequence,
+            time: scan.time,
+            points: points.to_vec(),
+        }
+    }
+
+    /// Little endian header followed by the lidar's own fixed point values per point
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = BytesMut::with_capacity(HEADER_SIZE + self.points.len() * POINT_SIZE);
+        buffer.put_u8(SCAN_ENCODING_VERSION);
+        buffer.put_u64_le(self.sequence);
+        buffer.put_i64_le(self.time.timestamp_micros());
+        buffer.put_u32_le(self.points.len() as u32);
+        for point in &self.points {
+            buffer.put_u16_le(point.angle_z_q14);
+            buffer.put_u32_le(point.dist_mm_q2);
+            buffer.put_u8(point.quality);
+            buffer.put_u8(point.flag);
+        }
+        buffer.to_vec()
+    }
+
+    pub fn decode(mut data: &[u8]) -> anyhow::Result<Self> {
+        ensure!(data.len() >= HEADER_SIZE, "Scan message too short");
+        let version = data.get_u8();
+        ensure!(
+            version == SCAN_ENCODING_VERSION,
+            "Unsupported scan encoding version {}",
+            version
+        );
+        let sequence = data.get_u64_le();
+        let time = DateTime::from_timestamp_micros(data.get_i64_le())
+            .context("Scan timestamp out of range")?;
+        let count = data.get_u32_le() as usize;
+        let size = count
+            .checked_mul(POINT_SIZE)
+            .context("Scan point count out of range")?;
+        ensure!(
+            data.len() == size,
+            "Expected {} scan points but got {} bytes",
+            count,
+            data.len()
+        );
+        let points = (0..count)
+            .map(|_| ScanPoint {
+                angle_z_q14: data.get_u16_le(),
+                dist_mm_q2: data.get_u32_le(),
+                quality: data.get_u8(),
+                flag: data.get_u8(),
+            })
+            .collect();
+        Ok(Self {
+            sequence,
+            time,
+            points,
+        })
+    }
+}
+
+/// Publish every scan in binary and a decimated copy as JSON
+pub fn start_scan_publisher(zenoh_session: Arc<Session>, lidar: &Lidar, config: ScanPublishConfig) {
+    if !config.enabled {
+        return;
+    }
+    let mut scans = lidar.subscribe_scans();
+    tokio::spawn(async move {
+        while scans.changed().await.is_ok() {
+            let Some(scan) = scans
+                .borrow_and_update()
+                .as_ref()
+                .map(FixedPointScan::from_scan)
+            else {
+                continue;
+            };
+            if let Err(err) = publish_scan(&zenoh_session, &scan, config.json_decimation).await {
+                error!("Failed to publish lidar scan {:?}", err);
+            }
+        }
+    });
+}
+
+async fn publish_scan(
+    zenoh_session: &Session,
+    scan: &FixedPointScan,
+    json_decimation: usize,
+) -> anyhow::Result<()> {
+    zenoh_session
+        .put(SCAN_TOPIC, scan.encode())
+        .congestion_control(CongestionControl::Drop)
+        .res_async()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+    let json = serde_json::to_string(&ScanMessage::from(scan).decimated(json_decimation))?;
+    zenoh_session
+        .put(SCAN_JSON_TOPIC, json)
+        .congestion_control(CongestionControl::Drop)
+        .res_async()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn scan() -> FixedPointScan {
+        FixedPointScan {
+            sequence: 42,
+            time: DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap(),
+            points: (0..10)
+                .map(|i| ScanPoint {
+                    angle_z_q14: i * 6553 + 1,
+                    dist_mm_q2: i as u32 * 1001 + 3,
+                    quality: i as u8 * 10,
+                    flag: i as u8 % 2,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let scan = scan();
+        let encoded = scan.encode();
+        assert_eq!(encoded.len(), HEADER_SIZE + 10 * POINT_SIZE);
+        assert_eq!(FixedPointScan::decode(&encoded).unwrap(), scan);
+
+        let message = ScanMessage::decode(&encoded).unwrap();
+        assert_eq!(message.sequence, 42);
+        assert_eq!(message.time, scan.time);
+        for (angle, point) in message.angles.iter().zip(&scan.points) {
+            assert_relative_eq!(*angle, point.angle());
+        }
+        for (range, point) in message.ranges.iter().zip(&scan.points) {
+            assert_relative_eq!(*range, point.distance());
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let encoded = scan().encode();
+        assert!(FixedPointScan::decode(&encoded[..HEADER_SIZE - 1]).is_err());
+        assert!(FixedPointScan::decode(&encoded[..encoded.len() - 1]).is_err());
+        let mut wrong_version = encoded.clone();
+        wrong_version[0] = SCAN_ENCODING_VERSION + 1;
+        assert!(FixedPointScan::decode(&wrong_version).is_err());
+        let mut huge_count = encoded[..HEADER_SIZE].to_vec();
+        huge_count[HEADER_SIZE - 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(FixedPointScan::decode(&huge_count).is_err());
+    }
+
+    #[test]
+    fn decimates_json() {
+        let decimated = ScanMessage::from(&scan()).decimated(4);
+        assert_eq!(decimated.len(), 3);
+        assert_eq!(decimated.qualities, vec![0, 40, 80]);
+        let json: serde_json::Value = serde_json::to_value(&decimated).unwrap();
+        assert_eq!(json["sequence"], 42);
+        assert_eq!(json["ranges"].as_array().unwrap().len(), 3);
+    }
+}
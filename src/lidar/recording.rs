@@ -0,0 +1,631 @@
+use super::{
+    publisher::FixedPointScan,
+    source::{LidarSource, SourceScan},
+    Lidar, LidarConfig, LidarScan,
+};
+use crate::{error::ErrorWrapper, util::validate_file_name};
+use anyhow::{ensure, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+use tracing::*;
+use zenoh::{prelude::r#async::*, subscriber::FlumeSubscriber, Session, SessionDeclarations};
+
+pub const RECORDING_COMMAND_TOPIC: &str = "hamilton/lidar/recording";
+
+/// Start of every recording file
+const RECORDING_MAGIC: &[u8; 4] = b"HLSR";
+const RECORDING_VERSION: u32 = 2;
+/// Far above any header or scan, guards against corrupt lengths
+const MAX_RECORD_SIZE: usize = 1 << 20;
+/// Scans waiting to be written before new ones are dropped
+const RECORDING_QUEUE_SIZE: usize = 64;
+
+fn default_rate() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RecordingConfig {
+    /// Directory recordings are saved to
+    ///
+    /// Recording requests are ignored when not set
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReplayConfig {
+    pub file: PathBuf,
+    /// Playback speed where 1 is the original rate
+    #[serde(default = "default_rate")]
+    pub rate: f32,
+    /// Start over once the end is reached
+    #[serde(default)]
+    pub looped: bool,
+}
+
+/// Requests accepted on the recording zenoh topic
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RecordingRequest {
+    /// Named after the start time when no name is given
+    Start {
+        name: Option<String>,
+    },
+    Stop,
+}
+
+/// Written once at the start of a recording
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecordingHeader {
+    pub started: DateTime<Utc>,
+    /// Config of the lidar that was recorded
+    pub lidar: LidarConfig,
+}
+
+/// Writes scans to a file
+///
+/// Magic, version and a length prefixed JSON header followed by
+/// length prefixed scans in the binary scan message encoding.
+pub struct ScanRecordingWriter {
+    writer: BufWriter<File>,
+    scans: u64,
+}
+
+impl ScanRecordingWriter {
+    pub fn create(path: &Path, header: &RecordingHeader) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(RECORDING_MAGIC)?;
+        writer.write_all(&RECORDING_VERSION.to_le_bytes())?;
+        write_record(&mut writer, &serde_json::to_vec(header)?)?;
+        Ok(Self { writer, scans: 0 })
+    }
+
+    pub fn write(&mut self, scan: &FixedPointScan) -> Result<()> {
+        write_record(&mut self.writer, &scan.encode())?;
+        self.scans += 1;
+        Ok(())
+    }
+
+    pub fn scans(&self) -> u64 {
+        self.scans
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn write_record(writer: &mut impl Write, data: &[u8]) -> Result<()> {
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// Reads scans written by `ScanRecordingWriter`
+pub struct ScanRecordingReader {
+    header: RecordingHeader,
+    reader: BufReader<File>,
+}
+
+impl ScanRecordingReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(
+            File::open(path).with_context(|| format!("Failed to open recording {:?}", path))?,
+        );
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        ensure!(
+            &magic == RECORDING_MAGIC,
+            "{:?} is not a lidar recording",
+            path
+        );
+        let mut version = [0; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        ensure!(
+            version == RECORDING_VERSION,
+            "Unsupported lidar recording version {}",
+            version
+        );
+        let header = read_record(&mut reader)?.context("Lidar recording has no header")?;
+        let header = serde_json::from_slice(&header)?;
+        Ok(Self { header, reader })
+    }
+
+    pub fn header(&self) -> &RecordingHeader {
+        &self.header
+    }
+
+    /// Next scan or `None` at the end of the recording
+    pub fn next_scan(&mut self) -> Result<Option<FixedPointScan>> {
+        match read_record(&mut self.reader)? {
+            Some(data) => Ok(Some(FixedPointScan::decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Record or `None` at the end of the file
+fn read_record(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => (),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let length = u32::from_le_bytes(length) as usize;
+    ensure!(
+        length <= MAX_RECORD_SIZE,
+        "Lidar recording record of {} bytes is too large",
+        length
+    );
+    let mut data = vec![0; length];
+    reader.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+/// Time to wait between two recorded scans at a playback rate
+pub fn playback_delay(previous: &FixedPointScan, next: &FixedPointScan, rate: f32) -> Duration {
+    let recorded = (next.time - previous.time).to_std().unwrap_or_default();
+    if rate <= 0.0 {
+        return recorded;
+    }
+    recorded.div_f64(rate as f64)
+}
+
+/// Plays a recording back at the configured rate
+pub struct ReplaySource {
+    header: RecordingHeader,
+    reader: Option<ScanRecordingReader>,
+    config: ReplayConfig,
+    previous: Option<FixedPointScan>,
+}
+
+impl ReplaySource {
+    pub fn open(config: ReplayConfig) -> Result<Self> {
+        let reader = ScanRecordingReader::open(&config.file)?;
+        Ok(Self {
+            header: reader.header().clone(),
+            reader: Some(reader),
+            config,
+            previous: None,
+        })
+    }
+
+    pub fn header(&self) -> &RecordingHeader {
+        &self.header
+    }
+
+    fn next_scan(&mut self) -> Result<Option<FixedPointScan>> {
+        let Some(reader) = &mut self.reader else {
+            return Ok(None);
+        };
+        match reader.next_scan() {
+            Ok(Some(scan)) => Ok(Some(scan)),
+            Ok(None) if self.config.looped => {
+                info!("Restarting lidar replay");
+                self.previous = None;
+                self.reader = None;
+                let reader = self
+                    .reader
+                    .insert(ScanRecordingReader::open(&self.config.file)?);
+                reader.next_scan()
+            }
+            Ok(None) => {
+                self.reader = None;
+                Ok(None)
+            }
+            Err(err) => {
+                // the rest of the file can't be trusted
+                self.reader = None;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl LidarSource for ReplaySource {
+    fn grab_scan(&mut self) -> Result<SourceScan> {
+        let Some(scan) = self.next_scan()? else {
+            return Ok(SourceScan::Finished);
+        };
+        if let Some(previous) = &self.previous {
+            std::thread::sleep(playback_delay(previous, &scan, self.config.rate));
+        }
+        let points = scan.points.clone();
+        let time = scan.time;
+        self.previous = Some(scan);
+        Ok(SourceScan::Scan { points, time })
+    }
+}
+
+fn recording_path(directory: &Path, name: &str) -> PathBuf {
+    directory.join(format!("{}.lidar", name))
+}
+
+/// Listen for recording requests and write unfiltered scans while recording
+pub async fn start_scan_recorder(
+    zenoh_session: Arc<Session>,
+    lidar: &Lidar,
+    config: LidarConfig,
+) -> Result<()> {
+    let mut request_subscriber = zenoh_session
+        .declare_subscriber(RECORDING_COMMAND_TOPIC)
+        .res()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+
+    let mut recorder = ScanRecorder {
+        lidar: lidar.clone(),
+        scans: lidar.subscribe_scans(),
+        config,
+        writer: None,
+    };
+    tokio::spawn(async move {
+        while let Err(err) = recorder.run(&mut request_subscriber).await {
+            error!("Lidar recorder failed with {:?}", err);
+            recorder.stop();
+        }
+    });
+    Ok(())
+}
+
+/// Writes scans on its own thread so that the disk never blocks the async runtime
+struct RecordingThread {
+    scans: SyncSender<FixedPointScan>,
+    thread: JoinHandle<()>,
+}
+
+impl RecordingThread {
+    fn start(mut writer: ScanRecordingWriter) -> Self {
+        let (scans, receiver) = sync_channel::<FixedPointScan>(RECORDING_QUEUE_SIZE);
+        let thread = std::thread::spawn(move || {
+            for scan in receiver {
+                if let Err(err) = writer.write(&scan) {
+                    error!("Failed to write lidar recording {:?}", err);
+                    return;
+                }
+            }
+            let scans = writer.scans();
+            match writer.finish() {
+                Ok(()) => info!(scans, "Stopped lidar recording"),
+                Err(err) => error!("Failed to finish lidar recording {:?}", err),
+            }
+        });
+        Self { scans, thread }
+    }
+
+    fn write(&self, scan: FixedPointScan) -> Result<()> {
+        match self.scans.try_send(scan) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                warn!("Lidar recording is falling behind, dropping scan");
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => anyhow::bail!("Lidar recording writer stopped"),
+        }
+    }
+
+    /// Write what's queued and close the file in the background
+    fn finish(self) -> JoinHandle<()> {
+        drop(self.scans);
+        self.thread
+    }
+}
+
+struct ScanRecorder {
+    lidar: Lidar,
+    scans: tokio::sync::watch::Receiver<Option<LidarScan>>,
+    config: LidarConfig,
+    writer: Option<RecordingThread>,
+}
+
+impl ScanRecorder {
+    async fn run(&mut self, request_subscriber: &mut FlumeSubscriber<'_>) -> Result<()> {
+        loop {
+            tokio::select! {
+                sample = request_subscriber.recv_async() => {
+                    let message: String = sample?.value.try_into()?;
+                    match serde_json::from_str::<RecordingRequest>(&message) {
+                        Ok(request) => self.handle_request(request)?,
+                        Err(err) => warn!("Failed to parse recording request {:?}", err),
+                    }
+                }
+                changed = self.scans.changed() => {
+                    changed?;
+                    self.record_latest_scan()?;
+                }
+            }
+        }
+    }
+
+    fn record_latest_scan(&mut self) -> Result<()> {
+        let scan = self
+            .scans
+            .borrow_and_update()
+            .as_ref()
+            .map(FixedPointScan::from_raw_scan);
+        if let (Some(writer), Some(scan)) = (&mut self.writer, scan) {
+            // the lidar would otherwise spin down in the middle of a recording
+            self.lidar.keep_awake();
+            writer.write(scan)?;
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, request: RecordingRequest) -> Result<()> {
+        match request {
+            RecordingRequest::Start { name } => {
+                let Some(directory) = self.config.recording.directory.clone() else {
+                    warn!("Lidar recording directory not configured");
+                    return Ok(());
+                };
+                let started = Utc::now();
+                let name = name.unwrap_or_else(|| started.format("%Y%m%d_%H%M%S").to_string());
+                validate_file_name(&name)?;
+                self.stop();
+                std::fs::create_dir_all(&directory)?;
+                let path = recording_path(&directory, &name);
+                let header = RecordingHeader {
+                    started,
+                    lidar: self.config.clone(),
+                };
+                let writer = ScanRecordingWriter::create(&path, &header)?;
+                self.writer = Some(RecordingThread::start(writer));
+                self.lidar.keep_awake();
+                info!(?path, "Started lidar recording");
+            }
+            RecordingRequest::Stop => self.stop(),
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            writer.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::{
+        control::MotorStatus,
+        filter::ScanFilterChain,
+        simulator::{SimulatedLidar, SimulatedLidarConfig},
+    };
+    use rplidar_driver::ScanPoint;
+
+    fn scan(sequence: u64, time_ms: i64) -> FixedPointScan {
+        FixedPointScan {
+            sequence,
+            time: DateTime::from_timestamp_millis(1_700_000_000_000 + time_ms).unwrap(),
+            points: [(0, 2000, 10), (10430, 4000, 20), (20860, 0, 0)]
+                .into_iter()
+                .map(|(angle_z_q14, dist_mm_q2, quality)| ScanPoint {
+                    angle_z_q14,
+                    dist_mm_q2,
+                    quality,
+                    flag: 0,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn recording_round_trip() {
+        let path = std::env::temp_dir().join(format!("hamilton_test_{}.lidar", std::process::id()));
+        let config: LidarConfig =
+            serde_json::from_str(r#"{"port": "/dev/test", "mount": {"x": 0.1}}"#).unwrap();
+        let header = RecordingHeader {
+            started: Utc::now(),
+            lidar: config,
+        };
+        let mut writer = ScanRecordingWriter::create(&path, &header).unwrap();
+        writer.write(&scan(0, 0)).unwrap();
+        writer.write(&scan(1, 100)).unwrap();
+        assert_eq!(writer.scans(), 2);
+        writer.finish().unwrap();
+
+        let mut reader = ScanRecordingReader::open(&path).unwrap();
+        assert_eq!(reader.header().lidar.port, "/dev/test");
+        assert_eq!(reader.header().lidar.mount.x, 0.1);
+        assert_eq!(reader.next_scan().unwrap().unwrap().sequence, 0);
+        let second = reader.next_scan().unwrap().unwrap();
+        assert_eq!(second.sequence, 1);
+        assert_eq!(second, scan(1, 100));
+        assert!(reader.next_scan().unwrap().is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_oversized_records() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&[0; 16]);
+        assert!(read_record(&mut data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn records_raw_points_exactly() {
+        let directory =
+            std::env::temp_dir().join(format!("hamilton_test_raw_{}", std::process::id()));
+        let mut config: LidarConfig = serde_json::from_str("{}").unwrap();
+        config.recording.directory = Some(directory.clone());
+        let lidar = Lidar::from_source(
+            config.mount,
+            ScanFilterChain::default(),
+            SimulatedLidar::new(SimulatedLidarConfig::default(), config.mount),
+        );
+        let raw = vec![
+            ScanPoint {
+                angle_z_q14: 12345,
+                dist_mm_q2: 6789,
+                quality: 47,
+                flag: 1,
+            },
+            ScanPoint {
+                angle_z_q14: 23456,
+                dist_mm_q2: 0,
+                quality: 0,
+                flag: 0,
+            },
+        ];
+        let (sender, scans) = tokio::sync::watch::channel(None);
+        let mut recorder = ScanRecorder {
+            lidar,
+            scans,
+            config,
+            writer: None,
+        };
+        let start = RecordingRequest::Start {
+            name: Some("raw".to_owned()),
+        };
+        recorder.handle_request(start).unwrap();
+        sender.send_replace(Some(LidarScan {
+            points: raw[..1].to_vec(),
+            raw: raw.clone(),
+            sequence: 7,
+            received: std::time::Instant::now(),
+            time: Utc::now(),
+        }));
+        recorder.record_latest_scan().unwrap();
+        recorder.writer.take().unwrap().finish().join().unwrap();
+
+        let mut reader = ScanRecordingReader::open(&recording_path(&directory, "raw")).unwrap();
+        let recorded = reader.next_scan().unwrap().unwrap();
+        assert_eq!(recorded.sequence, 7);
+        assert_eq!(recorded.points, raw);
+        assert!(reader.next_scan().unwrap().is_none());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = std::env::temp_dir().join(format!("hamilton_test_{}.json", std::process::id()));
+        std::fs::write(&path, b"{\"not\": \"a recording\"}").unwrap();
+        assert!(ScanRecordingReader::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn playback_rate_scales_delay() {
+        let (first, second) = (scan(0, 0), scan(1, 100));
+        assert_eq!(
+            playback_delay(&first, &second, 1.0),
+            Duration::from_millis(100)
+        );
+        assert_eq!(
+            playback_delay(&first, &second, 4.0),
+            Duration::from_millis(25)
+        );
+        // out of order timestamps don't stall playback
+        assert_eq!(playback_delay(&second, &first, 1.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn parses_requests() {
+        let request: RecordingRequest =
+            serde_json::from_str(r#"{"command": "start", "name": "hallway"}"#).unwrap();
+        assert_eq!(
+            request,
+            RecordingRequest::Start {
+                name: Some("hallway".to_owned())
+            }
+        );
+        let request: RecordingRequest = serde_json::from_str(r#"{"command": "stop"}"#).unwrap();
+        assert_eq!(request, RecordingRequest::Stop);
+    }
+
+    #[test]
+    fn rejects_names_outside_the_directory() {
+        let directory =
+            std::env::temp_dir().join(format!("hamilton_test_recordings_{}", std::process::id()));
+        let mut config: LidarConfig = serde_json::from_str("{}").unwrap();
+        config.recording.directory = Some(directory.clone());
+        let lidar = Lidar::from_source(
+            config.mount,
+            ScanFilterChain::default(),
+            SimulatedLidar::new(SimulatedLidarConfig::default(), config.mount),
+        );
+        let mut recorder = ScanRecorder {
+            scans: lidar.subscribe_scans(),
+            lidar,
+            config,
+            writer: None,
+        };
+
+        let escape = RecordingRequest::Start {
+            name: Some("../escape".to_owned()),
+        };
+        assert!(recorder.handle_request(escape).is_err());
+        assert!(recorder.writer.is_none());
+
+        let hallway = RecordingRequest::Start {
+            name: Some("hallway".to_owned()),
+        };
+        recorder.handle_request(hallway).unwrap();
+        recorder.handle_request(RecordingRequest::Stop).unwrap();
+        assert!(recording_path(&directory, "hallway").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn recording_keeps_lidar_awake() {
+        let directory =
+            std::env::temp_dir().join(format!("hamilton_test_awake_{}", std::process::id()));
+        let mut config: LidarConfig = serde_json::from_str("{}").unwrap();
+        config.recording.directory = Some(directory.clone());
+        let lidar = Lidar::from_source(
+            config.mount,
+            ScanFilterChain::default(),
+            SimulatedLidar::new(SimulatedLidarConfig::default(), config.mount),
+        );
+        let mut recorder = ScanRecorder {
+            scans: lidar.subscribe_scans(),
+            lidar: lidar.clone(),
+            config,
+            writer: None,
+        };
+
+        let mut waited = 0;
+        while lidar.get_last_scan().is_none() && waited < 100 {
+            std::thread::sleep(Duration::from_millis(10));
+            waited += 1;
+        }
+
+        // starting wakes a lidar that spun down
+        assert!(lidar.spin_down_if_idle(Duration::ZERO));
+        let start = RecordingRequest::Start {
+            name: Some("awake".to_owned()),
+        };
+        recorder.handle_request(start).unwrap();
+        assert_eq!(lidar.motor_status(), MotorStatus::On);
+
+        // every recorded scan counts as demand
+        let idle_timeout = Duration::from_millis(200);
+        std::thread::sleep(Duration::from_millis(300));
+        recorder.record_latest_scan().unwrap();
+        assert!(!lidar.spin_down_if_idle(idle_timeout));
+
+        recorder.handle_request(RecordingRequest::Stop).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        recorder.record_latest_scan().unwrap();
+        assert!(lidar.spin_down_if_idle(idle_timeout));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
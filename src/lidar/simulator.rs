@@ -0,0 +1,290 @@
+use super::{
+    source::{LidarSource, SourceScan},
+    LidarMount,
+};
+use crate::navigation::Pose2d;
+use anyhow::Result;
+use chrono::Utc;
+use nalgebra as na;
+use rplidar_driver::ScanPoint;
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::TAU,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+fn default_points_per_scan() -> usize {
+    360
+}
+
+fn default_max_range() -> f32 {
+    12.0
+}
+
+fn default_scan_rate_hz() -> f32 {
+    10.0
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SimulatedLidarConfig {
+    /// Obstacle outlines in meters in the world frame
+    #[serde(default)]
+    pub world: Vec<Vec<(f32, f32)>>,
+    /// Initial robot pose in the world frame
+    #[serde(default)]
+    pub pose: Pose2d,
+    #[serde(default = "default_points_per_scan")]
+    pub points_per_scan: usize,
+    /// Beams that hit nothing within this range in meters are invalid
+    #[serde(default = "default_max_range")]
+    pub max_range: f32,
+    /// Standard deviation of the range noise in meters
+    #[serde(default)]
+    pub range_noise: f32,
+    /// Probability of a beam returning no reading
+    #[serde(default)]
+    pub dropout: f32,
+    #[serde(default = "default_scan_rate_hz")]
+    pub scan_rate_hz: f32,
+    /// Seed for the noise so runs are repeatable
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl Default for SimulatedLidarConfig {
+    fn default() -> Self {
+        Self {
+            world: vec![],
+            pose: Pose2d::default(),
+            points_per_scan: default_points_per_scan(),
+            max_range: default_max_range(),
+            range_noise: 0.0,
+            dropout: 0.0,
+            scan_rate_hz: default_scan_rate_hz(),
+            seed: 0,
+        }
+    }
+}
+
+/// Lidar that ray casts a polygon world from the robot pose
+pub struct SimulatedLidar {
+    config: SimulatedLidarConfig,
+    mount: LidarMount,
+    pose: Arc<Mutex<Pose2d>>,
+    walls: Vec<(na::Point2<f32>, na::Point2<f32>)>,
+    noise: NoiseGenerator,
+    last_scan: Option<Instant>,
+}
+
+impl SimulatedLidar {
+    pub fn new(config: SimulatedLidarConfig, mount: LidarMount) -> Self {
+        let walls = config
+            .world
+            .iter()
+            .flat_map(|polygon| {
+                let points: Vec<_> = polygon
+                    .iter()
+                    .map(|(x, y)| na::Point2::new(*x, *y))
+                    .collect();
+                (0..points.len()).map(move |i| (points[i], points[(i + 1) % points.len()]))
+            })
+            .collect();
+        Self {
+            pose: Arc::new(Mutex::new(config.pose.clone())),
+            noise: NoiseGenerator::new(config.seed),
+            walls,
+            config,
+            mount,
+            last_scan: None,
+        }
+    }
+
+    /// Robot pose the world is cast from, shared so the robot can be moved while scanning
+    pub fn pose(&self) -> Arc<Mutex<Pose2d>> {
+        Arc::clone(&self.pose)
+    }
+
+    /// Full rotation from the current pose
+    pub fn scan(&mut self) -> Vec<ScanPoint> {
+        let lidar_pose = self.pose.lock().unwrap().compose(&self.mount.pose());
+        let count = self.config.points_per_scan;
+        (0..count)
+            .map(|i| {
+                let angle = i as f32 * TAU / count as f32;
+                // same direction conventions as `LidarMount::to_robot`
+                let beam = if self.mount.flipped { angle } else { -angle };
+                let direction = na::Rotation2::new(lidar_pose.theta() + beam) * na::Vector2::x();
+                let distance = cast(&self.walls, lidar_pose.position(), &direction)
+                    .filter(|distance| *distance <= self.config.max_range)
+                    .filter(|_| self.noise.uniform() >= self.config.dropout)
+                    .map(|distance| distance + self.noise.gaussian() * self.config.range_noise)
+                    .filter(|distance| *distance > 0.0)
+                    .unwrap_or(0.0);
+                let mut point = ScanPoint {
+                    angle_z_q14: 0,
+                    dist_mm_q2: 0,
+                    quality: if distance > 0.0 { 255 } else { 0 },
+                    flag: 0,
+                };
+                point.set_angle(angle);
+                point.set_distance(distance);
+                point
+            })
+            .collect()
+    }
+}
+
+/// Distance to the nearest wall along a ray
+fn cast(
+    walls: &[(na::Point2<f32>, na::Point2<f32>)],
+    origin: &na::Point2<f32>,
+    direction: &na::Vector2<f32>,
+) -> Option<f32> {
+    walls
+        .iter()
+        .filter_map(|(start, end)| {
+            let edge = end - start;
+            let denominator = direction.perp(&edge);
+            if denominator.abs() < f32::EPSILON {
+                return None;
+            }
+            let to_start = start - origin;
+            let distance = to_start.perp(&edge) / denominator;
+            let along_edge = to_start.perp(direction) / denominator;
+            ((0.0..=1.0).contains(&along_edge) && distance >= 0.0).then_some(distance)
+        })
+        .min_by(|a, b| a.total_cmp(b))
+}
+
+impl LidarSource for SimulatedLidar {
+    fn grab_scan(&mut self) -> Result<SourceScan> {
+        let period = Duration::from_secs_f32(1.0 / self.config.scan_rate_hz.max(0.1));
+        if let Some(last_scan) = self.last_scan {
+            std::thread::sleep(period.saturating_sub(last_scan.elapsed()));
+        }
+        self.last_scan = Some(Instant::now());
+        Ok(SourceScan::Scan {
+            points: self.scan(),
+            time: Utc::now(),
+        })
+    }
+}
+
+/// Spreads small seeds over the whole state
+const SEED_MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Small xorshift generator so the simulator doesn't need a random crate
+struct NoiseGenerator {
+    state: u64,
+}
+
+impl NoiseGenerator {
+    fn new(seed: u64) -> Self {
+        // xorshift never leaves a zero state
+        let state = match seed ^ SEED_MIX {
+            0 => SEED_MIX,
+            state => state,
+        };
+        Self { state }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Uniform in [0, 1)
+    fn uniform(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Standard normal using the Box-Muller transform
+    fn gaussian(&mut self) -> f32 {
+        let radius = (-2.0 * self.uniform().max(f32::MIN_POSITIVE).ln()).sqrt();
+        radius * (TAU * self.uniform()).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// 2 by 2 meter room around the origin
+    fn room() -> SimulatedLidarConfig {
+        SimulatedLidarConfig {
+            world: vec![vec![(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]],
+            points_per_scan: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn casts_walls_around_the_robot() {
+        let mut lidar = SimulatedLidar::new(room(), LidarMount::default());
+        let scan = lidar.scan();
+        for point in &scan {
+            assert_relative_eq!(point.distance(), 1.0, epsilon = 1e-3);
+        }
+        // second beam is 90 degrees clockwise
+        let right = LidarMount::default().to_robot(&scan[1]);
+        assert_relative_eq!(right, na::Point2::new(0.0, -1.0), epsilon = 1e-3);
+    }
+
+    #[test]
+    fn follows_pose_and_mount() {
+        let mount = LidarMount {
+            x: 0.2,
+            ..Default::default()
+        };
+        let mut lidar = SimulatedLidar::new(room(), mount);
+        *lidar.pose().lock().unwrap() = Pose2d::new((0.3, 0.0), std::f32::consts::FRAC_PI_2);
+        let scan = lidar.scan();
+        // facing +y with the lidar ahead of the robot origin
+        assert_relative_eq!(scan[0].distance(), 0.8, epsilon = 1e-3);
+        // clockwise from +y is +x
+        assert_relative_eq!(scan[1].distance(), 0.7, epsilon = 1e-3);
+        assert_relative_eq!(scan[2].distance(), 1.2, epsilon = 1e-3);
+        let ahead = mount.to_robot(&scan[0]);
+        assert_relative_eq!(ahead, na::Point2::new(1.0, 0.0), epsilon = 1e-3);
+    }
+
+    #[test]
+    fn out_of_range_and_dropped_beams_are_invalid() {
+        let mut config = room();
+        config.max_range = 0.5;
+        let mut lidar = SimulatedLidar::new(config, LidarMount::default());
+        assert!(lidar.scan().iter().all(|point| !point.is_valid()));
+
+        let mut config = room();
+        config.dropout = 1.0;
+        let mut lidar = SimulatedLidar::new(config, LidarMount::default());
+        assert!(lidar.scan().iter().all(|point| !point.is_valid()));
+    }
+
+    #[test]
+    fn noise_is_repeatable() {
+        let mut config = room();
+        config.points_per_scan = 360;
+        config.range_noise = 0.02;
+        config.seed = 7;
+        let first = SimulatedLidar::new(config.clone(), LidarMount::default()).scan();
+        let second = SimulatedLidar::new(config, LidarMount::default()).scan();
+        assert_eq!(first, second);
+        let ahead = first[0].distance();
+        assert!(ahead != 1.0 && (ahead - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn every_seed_makes_noise() {
+        for seed in [0, SEED_MIX] {
+            let mut noise = NoiseGenerator::new(seed);
+            let first = noise.next();
+            assert_ne!(first, 0);
+            assert_ne!(noise.next(), first);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use super::{recording::ReplayConfig, simulator::SimulatedLidarConfig};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rplidar_driver::{utils::sort_scan, RplidarDevice, RplidarDriver, ScanOptions, ScanPoint};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+/// Where a `Lidar` gets its scans from
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LidarSourceConfig {
+    /// RPLidar on the configured port
+    #[default]
+    Rplidar,
+    /// Play back a recording instead of opening the lidar
+    Replay(ReplayConfig),
+    /// Ray cast a simulated world
+    Simulated(SimulatedLidarConfig),
+}
+
+/// Result of waiting for a scan
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceScan {
+    Scan {
+        points: Vec<ScanPoint>,
+        time: DateTime<Utc>,
+    },
+    /// Nothing arrived in time, try again
+    Timeout,
+    /// The source won't produce any more scans
+    Finished,
+}
+
+/// Produces scans for a `Lidar`
+///
+/// Runs on the lidar thread so implementations are free to block.
+/// Sources are never used directly, scans from any of them reach consumers
+/// through `Lidar::get_last_scan` and `Lidar::subscribe_scans` as before.
+pub trait LidarSource: Send {
+    /// Block until the next full rotation
+    ///
+    /// Errors are logged and the source is asked for a scan again.
+    fn grab_scan(&mut self) -> Result<SourceScan>;
+
+    fn start_motor(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn stop_motor(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// RPLidar connected over serial
+///
+/// The port is reopened on the next scan after an error.
+pub struct RplidarSource {
+    port: String,
+    device: Option<Box<dyn RplidarDriver>>,
+}
+
+impl RplidarSource {
+    pub fn new(port: String) -> Self {
+        Self { port, device: None }
+    }
+
+    fn device(&mut self) -> Result<&mut Box<dyn RplidarDriver>> {
+        if self.device.is_none() {
+            info!(port = self.port, "Opening lidar");
+            let mut device = RplidarDevice::open_port(&self.port)?;
+            device.start_scan_with_options(&ScanOptions::with_mode(2))?;
+            self.device = Some(device);
+        }
+        Ok(self.device.as_mut().expect("Lidar device was just opened"))
+    }
+}
+
+impl LidarSource for RplidarSource {
+    fn grab_scan(&mut self) -> Result<SourceScan> {
+        match self.device()?.grab_scan() {
+            Ok(mut scan) => {
+                sort_scan(&mut scan)?;
+                Ok(SourceScan::Scan {
+                    points: scan,
+                    time: Utc::now(),
+                })
+            }
+            Err(rplidar_driver::RposError::OperationTimeout) => Ok(SourceScan::Timeout),
+            Err(error) => {
+                self.device = None;
+                Err(anyhow::anyhow!("Lidar error {:?}", error))
+            }
+        }
+    }
+
+    fn start_motor(&mut self) -> Result<()> {
+        if let Some(device) = &mut self.device {
+            let started = device
+                .start_motor()
+                .and_then(|_| device.start_scan_with_options(&ScanOptions::with_mode(2)));
+            if let Err(error) = started {
+                self.device = None;
+                return Err(anyhow::anyhow!("Failed to start lidar {:?}", error));
+            }
+        }
+        Ok(())
+    }
+
+    fn stop_motor(&mut self) -> Result<()> {
+        if let Some(device) = &mut self.device {
+            if let Err(error) = device.stop_motor().and_then(|_| device.stop()) {
+                self.device = None;
+                return Err(anyhow::anyhow!("Failed to stop lidar {:?}", error));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,2 +1,192 @@
 pub mod ir_tracker_localiser;
 pub mod openvr_localiser;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{error, warn};
+use zenoh::{prelude::r#async::*, subscriber::FlumeSubscriber, Session, SessionDeclarations};
+
+use crate::{
+    error::ErrorWrapper,
+    navigation::{Pose2d, PoseMessage},
+};
+use ir_tracker_localiser::IrTrackers;
+use openvr_localiser::TrackedObjects;
+
+fn default_pose_topic() -> Option<String> {
+    Some(String::from("hamilton/localisation/pose"))
+}
+
+fn default_pose_timeout_ms() -> u64 {
+    500
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LocalisationConfig {
+    /// Topic publishing OpenVR tracked objects
+    #[serde(default)]
+    pub openvr_topic: Option<String>,
+    /// Topic publishing IR tracker points
+    #[serde(default)]
+    pub ir_tracker_topic: Option<String>,
+    /// Topic accepting poses from external localisers
+    #[serde(default = "default_pose_topic")]
+    pub pose_topic: Option<String>,
+    /// Poses older than this are ignored
+    #[serde(default = "default_pose_timeout_ms")]
+    pub pose_timeout_ms: u64,
+}
+
+impl Default for LocalisationConfig {
+    fn default() -> Self {
+        Self {
+            openvr_topic: None,
+            ir_tracker_topic: None,
+            pose_topic: default_pose_topic(),
+            pose_timeout_ms: default_pose_timeout_ms(),
+        }
+    }
+}
+
+/// Sources of pose estimates ordered from most to least accurate
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PoseSource {
+    OpenVr,
+    IrTracker,
+    External,
+}
+
+/// Latest pose from each localiser
+#[derive(Clone)]
+pub struct Localiser {
+    poses: Arc<Mutex<BTreeMap<PoseSource, (Pose2d, Instant)>>>,
+    timeout: Duration,
+}
+
+impl Localiser {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            poses: Arc::default(),
+            timeout,
+        }
+    }
+
+    pub fn update(&self, source: PoseSource, pose: Pose2d) {
+        self.poses
+            .lock()
+            .unwrap()
+            .insert(source, (pose, Instant::now()));
+    }
+
+    /// Most accurate pose that isn't stale
+    pub fn latest_pose(&self) -> Option<Pose2d> {
+        self.latest_pose_with_source().map(|(_, pose)| pose)
+    }
+
+    /// Latest pose from a single localiser unless it's stale
+    pub fn pose_from(&self, source: PoseSource) -> Option<Pose2d> {
+        self.poses
+            .lock()
+            .unwrap()
+            .get(&source)
+            .filter(|(_, time)| time.elapsed() < self.timeout)
+            .map(|(pose, _)| pose.clone())
+    }
+
+    pub fn latest_pose_with_source(&self) -> Option<(PoseSource, Pose2d)> {
+        self.poses
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, (_, time))| time.elapsed() < self.timeout)
+            .map(|(source, (pose, _))| (*source, pose.clone()))
+    }
+}
+
+pub async fn start_localisation(
+    zenoh_session: Arc<Session>,
+    config: &LocalisationConfig,
+) -> Result<Localiser> {
+    let localiser = Localiser::new(Duration::from_millis(config.pose_timeout_ms));
+
+    let sources = [
+        (PoseSource::OpenVr, &config.openvr_topic),
+        (PoseSource::IrTracker, &config.ir_tracker_topic),
+        (PoseSource::External, &config.pose_topic),
+    ];
+    for (source, topic) in sources {
+        let Some(topic) = topic else {
+            continue;
+        };
+        let mut subscriber = zenoh_session
+            .declare_subscriber(topic.as_str())
+            .res()
+            .await
+            .map_err(ErrorWrapper::ZenohError)?;
+        let localiser = localiser.clone();
+        tokio::spawn(async move {
+            while let Err(err) = run_pose_listener(&mut subscriber, source, &localiser).await {
+                error!(
+                    "Localisation listener for {:?} failed with {:?}",
+                    source, err
+                );
+            }
+        });
+    }
+
+    Ok(localiser)
+}
+
+async fn run_pose_listener(
+    subscriber: &mut FlumeSubscriber<'_>,
+    source: PoseSource,
+    localiser: &Localiser,
+) -> Result<()> {
+    loop {
+        let sample = subscriber.recv_async().await?;
+        let message: String = sample.value.try_into()?;
+        match parse_pose(source, &message) {
+            Ok(Some(pose)) => localiser.update(source, pose),
+            Ok(None) => (),
+            Err(err) => warn!("Failed to parse {:?} pose {:?}", source, err),
+        }
+    }
+}
+
+fn parse_pose(source: PoseSource, message: &str) -> Result<Option<Pose2d>> {
+    Ok(match source {
+        PoseSource::OpenVr => serde_json::from_str::<TrackedObjects>(message)?
+            .get_tracker_pose()
+            .map(|(position, rotation)| Pose2d::from_na(position, rotation)),
+        PoseSource::IrTracker => serde_json::from_str::<IrTrackers>(message)?.find_tracker_pose(),
+        PoseSource::External => Some(serde_json::from_str::<PoseMessage>(message)?.into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_most_accurate_fresh_pose() {
+        let localiser = Localiser::new(Duration::from_secs(10));
+        localiser.update(PoseSource::External, Pose2d::new((1.0, 0.0), 0.0));
+        localiser.update(PoseSource::OpenVr, Pose2d::new((2.0, 0.0), 0.0));
+        let (source, pose) = localiser.latest_pose_with_source().unwrap();
+        assert_eq!(source, PoseSource::OpenVr);
+        assert_eq!(pose.position().x, 2.0);
+    }
+
+    #[test]
+    fn stale_poses_are_ignored() {
+        let localiser = Localiser::new(Duration::ZERO);
+        localiser.update(PoseSource::External, Pose2d::new((1.0, 0.0), 0.0));
+        assert!(localiser.latest_pose().is_none());
+    }
+}
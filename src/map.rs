@@ -3,10 +3,31 @@ use nalgebra as na;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 
+/// Axis aligned rectangle the robot can't drive through
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MapObstacle {
+    pub corner_a: na::Vector2<f32>,
+    pub corner_b: na::Vector2<f32>,
+}
+
+impl MapObstacle {
+    pub fn new(corner_a: na::Vector2<f32>, corner_b: na::Vector2<f32>) -> Self {
+        Self { corner_a, corner_b }
+    }
+
+    pub fn contains(&self, point: &na::Point2<f32>) -> bool {
+        let min = self.corner_a.inf(&self.corner_b);
+        let max = self.corner_a.sup(&self.corner_b);
+        point.x >= min.x && point.x <= max.x && point.y >= min.y && point.y <= max.y
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Map {
     front_left: na::Vector2<f32>,
     rear_right: na::Vector2<f32>,
+    #[serde(default)]
+    obstacles: Vec<MapObstacle>,
 }
 
 impl Map {
@@ -14,9 +35,27 @@ impl Map {
         Self {
             front_left,
             rear_right,
+            obstacles: vec![],
         }
     }
 
+    pub fn with_obstacles(mut self, obstacles: Vec<MapObstacle>) -> Self {
+        self.obstacles = obstacles;
+        self
+    }
+
+    pub fn obstacles(&self) -> &[MapObstacle] {
+        &self.obstacles
+    }
+
+    /// Smallest and largest corner of the map
+    pub fn bounds(&self) -> (na::Point2<f32>, na::Point2<f32>) {
+        (
+            self.front_left.inf(&self.rear_right).into(),
+            self.front_left.sup(&self.rear_right).into(),
+        )
+    }
+
     pub fn get_size(&self) -> (f32, f32) {
         (
             (self.front_left.x - self.rear_right.x).abs(),
@@ -55,7 +94,7 @@ impl Map {
     }
 
     pub fn load_json(path: &str) -> Result<Self> {
-        let file = File::create(path)?;
+        let file = File::open(path)?;
         Ok(serde_json::from_reader(file)?)
     }
 }
@@ -0,0 +1,510 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+use super::{
+    state::{FailureReason, NavigationState},
+    NavigationController, Pose2d, PoseMessage, Tolerance,
+};
+use crate::{
+    collision::{CheckerConfig, FootprintConfig},
+    localisation::PoseSource,
+};
+
+pub const DOCKING_COMMAND_TOPIC: &str = "hamilton/docking/command";
+pub const DOCKING_STATUS_TOPIC: &str = "hamilton/docking/status";
+
+fn default_pre_dock_distance() -> f32 {
+    0.5
+}
+
+fn default_approach_speed() -> f32 {
+    0.15
+}
+
+fn default_dock_tolerance() -> Tolerance {
+    Tolerance {
+        position: 0.02,
+        heading_deg: 2.0,
+    }
+}
+
+/// Lets the footprint get right up to the dock
+fn default_approach_collision() -> CheckerConfig {
+    CheckerConfig::Footprint(FootprintConfig {
+        safety_margin: 0.0,
+        slow_down_distance: 0.1,
+        stop_distance: 0.0,
+        ..Default::default()
+    })
+}
+
+fn default_return_voltage() -> Option<f32> {
+    Some(10.5)
+}
+
+fn default_battery_check_interval_s() -> u64 {
+    30
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DockingConfig {
+    /// Home pose used until one is stored with a set home request
+    #[serde(default)]
+    pub home: Option<PoseMessage>,
+    /// File the home pose is stored in
+    #[serde(default)]
+    pub home_file: Option<PathBuf>,
+    /// How far in front of home the final approach starts in meters
+    #[serde(default = "default_pre_dock_distance")]
+    pub pre_dock_distance: f32,
+    /// Translation command limit during the final approach
+    #[serde(default = "default_approach_speed")]
+    pub approach_speed: f32,
+    #[serde(default = "default_dock_tolerance")]
+    pub tolerance: Tolerance,
+    /// Collision checking during the final approach, which drives straight to home
+    #[serde(default = "default_approach_collision")]
+    pub approach_collision: CheckerConfig,
+    /// Battery voltage under which the robot goes home on its own
+    #[serde(default = "default_return_voltage")]
+    pub return_voltage: Option<f32>,
+    #[serde(default = "default_battery_check_interval_s")]
+    pub battery_check_interval_s: u64,
+}
+
+impl Default for DockingConfig {
+    fn default() -> Self {
+        Self {
+            home: None,
+            home_file: None,
+            pre_dock_distance: default_pre_dock_distance(),
+            approach_speed: default_approach_speed(),
+            tolerance: default_dock_tolerance(),
+            approach_collision: default_approach_collision(),
+            return_voltage: default_return_voltage(),
+            battery_check_interval_s: default_battery_check_interval_s(),
+        }
+    }
+}
+
+/// Requests accepted on the docking zenoh topic
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum DockingRequest {
+    GoHome,
+    /// Store a new home pose
+    ///
+    /// The current pose is used when not set
+    SetHome {
+        #[serde(default)]
+        pose: Option<PoseMessage>,
+    },
+    Cancel,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DockingFailure {
+    NoHome,
+    /// Cancelled, preempted by the user or replaced by another target
+    Interrupted,
+    Stuck,
+    NotLocalised,
+    NoPath,
+}
+
+impl From<FailureReason> for DockingFailure {
+    fn from(reason: FailureReason) -> Self {
+        match reason {
+            FailureReason::Stuck => DockingFailure::Stuck,
+            FailureReason::NotLocalised => DockingFailure::NotLocalised,
+            FailureReason::NoPath => DockingFailure::NoPath,
+        }
+    }
+}
+
+/// Published on every change
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DockingStatus {
+    #[default]
+    Idle,
+    /// Driving to the pre-dock pose
+    Approaching,
+    /// Slow approach from the pre-dock pose to home
+    Docking {
+        source: PoseSource,
+    },
+    Docked,
+    Failed {
+        reason: DockingFailure,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Idle,
+    Approaching { goal_sent: bool },
+    Docking,
+}
+
+/// Drives home through a pre-dock pose
+pub struct Docking {
+    config: DockingConfig,
+    home: Option<Pose2d>,
+    phase: Phase,
+    status: DockingStatus,
+}
+
+impl Docking {
+    pub fn new(config: DockingConfig) -> Self {
+        let home = config
+            .home_file
+            .as_ref()
+            .filter(|path| path.exists())
+            .and_then(|path| match load_home(path) {
+                Ok(home) => Some(home),
+                Err(err) => {
+                    warn!("Failed to load home pose {:?}", err);
+                    None
+                }
+            })
+            .or(config.home)
+            .map(Pose2d::from);
+        Self {
+            config,
+            home,
+            phase: Phase::Idle,
+            status: DockingStatus::Idle,
+        }
+    }
+
+    pub fn config(&self) -> &DockingConfig {
+        &self.config
+    }
+
+    pub fn home(&self) -> Option<&Pose2d> {
+        self.home.as_ref()
+    }
+
+    pub fn status(&self) -> DockingStatus {
+        self.status
+    }
+
+    /// Whether docking is driving the robot
+    pub fn is_active(&self) -> bool {
+        self.phase != Phase::Idle
+    }
+
+    /// Pose the final approach starts from, in front of home
+    pub fn pre_dock(&self) -> Option<Pose2d> {
+        self.home
+            .as_ref()
+            .map(|home| home.compose(&Pose2d::new((-self.config.pre_dock_distance, 0.0), 0.0)))
+    }
+
+    pub fn set_home(&mut self, home: Pose2d) -> Result<()> {
+        info!("Home set to {}", home);
+        if let Some(path) = &self.config.home_file {
+            let file = File::create(path)?;
+            serde_json::to_writer_pretty(file, &PoseMessage::from(&home))?;
+        }
+        self.home = Some(home);
+        Ok(())
+    }
+
+    pub fn go_home(&mut self, navigation: &mut NavigationController) {
+        if self.home.is_none() {
+            warn!("Can't go home without a home pose");
+            self.status = DockingStatus::Failed {
+                reason: DockingFailure::NoHome,
+            };
+            return;
+        }
+        info!("Going home");
+        navigation.clear_target();
+        self.phase = Phase::Approaching { goal_sent: false };
+        self.status = DockingStatus::Approaching;
+    }
+
+    pub fn cancel(&mut self, navigation: &mut NavigationController) {
+        if !self.is_active() {
+            return;
+        }
+        info!("Docking cancelled");
+        navigation.clear_target();
+        self.fail(navigation, DockingFailure::Interrupted);
+    }
+
+    pub fn handle_request(
+        &mut self,
+        request: DockingRequest,
+        navigation: &mut NavigationController,
+        current_pose: Option<&Pose2d>,
+    ) -> Result<()> {
+        match request {
+            DockingRequest::GoHome => self.go_home(navigation),
+            DockingRequest::SetHome { pose } => {
+                let home = pose
+                    .map(Pose2d::from)
+                    .or_else(|| current_pose.cloned())
+                    .ok_or_else(|| anyhow!("Can't set home without a pose"))?;
+                self.set_home(home)?;
+            }
+            DockingRequest::Cancel => self.cancel(navigation),
+        }
+        Ok(())
+    }
+
+    /// Advance docking
+    ///
+    /// `best_source` is the most accurate localiser currently producing poses
+    pub fn tick(&mut self, navigation: &mut NavigationController, best_source: Option<PoseSource>) {
+        let Some(home) = self.home.clone() else {
+            return;
+        };
+        match self.phase {
+            Phase::Idle => (),
+            Phase::Approaching { goal_sent: false } => {
+                let pre_dock = self.pre_dock().expect("Home is set");
+                info!("Driving to pre-dock pose {}", pre_dock);
+                navigation.set_target(pre_dock);
+                self.phase = Phase::Approaching { goal_sent: true };
+            }
+            Phase::Approaching { goal_sent: true } => {
+                if navigation.target().is_some() {
+                    return;
+                }
+                if !navigation.has_arrived() {
+                    self.fail_with_state(navigation);
+                    return;
+                }
+                let Some(source) = best_source else {
+                    self.fail(navigation, DockingFailure::NotLocalised);
+                    return;
+                };
+                info!(?source, "Starting final approach to {}", home);
+                navigation.set_pose_source(Some(source));
+                navigation.set_speed_limit(Some(self.config.approach_speed));
+                navigation.set_approach_target(
+                    home,
+                    self.config.tolerance,
+                    self.config.approach_collision.build(),
+                );
+                self.phase = Phase::Docking;
+                self.status = DockingStatus::Docking { source };
+            }
+            Phase::Docking => {
+                if navigation.target().is_some() {
+                    return;
+                }
+                if navigation.has_arrived() {
+                    info!("Docked");
+                    self.finish(navigation, DockingStatus::Docked);
+                } else {
+                    self.fail_with_state(navigation);
+                }
+            }
+        }
+    }
+
+    fn fail_with_state(&mut self, navigation: &mut NavigationController) {
+        let reason = match navigation.state() {
+            NavigationState::Failed { reason, .. } => reason.into(),
+            _ => DockingFailure::Interrupted,
+        };
+        self.fail(navigation, reason);
+    }
+
+    fn fail(&mut self, navigation: &mut NavigationController, reason: DockingFailure) {
+        warn!(?reason, "Docking failed");
+        self.finish(navigation, DockingStatus::Failed { reason });
+    }
+
+    fn finish(&mut self, navigation: &mut NavigationController, status: DockingStatus) {
+        navigation.set_pose_source(None);
+        navigation.set_speed_limit(None);
+        self.phase = Phase::Idle;
+        self.status = status;
+    }
+}
+
+fn load_home(path: &Path) -> Result<PoseMessage> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(file)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{recording_controller, recording_controller_with, SimulatedBase};
+    use super::*;
+    use crate::collision::{CollisionConfig, CollisionDetector};
+    use crate::holonomic_controller::{HolonomicWheelCommand, MoveCommand};
+    use crate::lidar::{
+        filter::ScanFilterChain,
+        simulator::{SimulatedLidar, SimulatedLidarConfig},
+        Lidar, LidarMount,
+    };
+    use std::time::Duration;
+
+    fn docking_home() -> Docking {
+        Docking::new(DockingConfig {
+            home: Some(PoseMessage {
+                x: 2.0,
+                y: 1.0,
+                theta: std::f32::consts::FRAC_PI_2,
+            }),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn pre_dock_is_in_front_of_home() {
+        let pre_dock = docking_home().pre_dock().unwrap();
+        approx::assert_relative_eq!(pre_dock.position().x, 2.0, epsilon = 1e-6);
+        approx::assert_relative_eq!(pre_dock.position().y, 0.5, epsilon = 1e-6);
+    }
+
+    #[tokio::test]
+    async fn docks_through_pre_dock_pose() {
+        let (mut navigation, _recording) = recording_controller();
+        let mut docking = docking_home();
+        docking.go_home(&mut navigation);
+        docking.tick(&mut navigation, Some(PoseSource::External));
+        let pre_dock = navigation.target().unwrap().clone();
+        assert_eq!(docking.status(), DockingStatus::Approaching);
+
+        navigation.tick(Some(&pre_dock)).await.unwrap();
+        docking.tick(&mut navigation, Some(PoseSource::IrTracker));
+        assert_eq!(
+            docking.status(),
+            DockingStatus::Docking {
+                source: PoseSource::IrTracker
+            }
+        );
+        assert_eq!(navigation.pose_source(), Some(PoseSource::IrTracker));
+
+        // default tolerance isn't enough
+        navigation
+            .tick(Some(&Pose2d::new((2.0, 0.96), std::f32::consts::FRAC_PI_2)))
+            .await
+            .unwrap();
+        docking.tick(&mut navigation, Some(PoseSource::IrTracker));
+        assert!(docking.is_active());
+
+        navigation
+            .tick(Some(&Pose2d::new((2.0, 0.99), std::f32::consts::FRAC_PI_2)))
+            .await
+            .unwrap();
+        docking.tick(&mut navigation, Some(PoseSource::IrTracker));
+        assert_eq!(docking.status(), DockingStatus::Docked);
+        assert_eq!(navigation.pose_source(), None);
+    }
+
+    /// Move command a wheel command was made from
+    fn move_command(wheels: &HolonomicWheelCommand) -> MoveCommand {
+        let (lf, rf, lr, rr) = (
+            wheels.left_front(),
+            wheels.right_front(),
+            wheels.left_rear(),
+            wheels.right_rear(),
+        );
+        MoveCommand::new(
+            (lf + rf + lr + rr) / 4.0,
+            (rf + lr - lf - rr) / 4.0,
+            (rf + rr - lf - lr) / 4.0,
+        )
+    }
+
+    #[tokio::test]
+    async fn docks_against_dock_wall() {
+        // docked, the front of the robot is 2cm from the dock
+        let simulation = SimulatedLidarConfig {
+            world: vec![vec![(0.22, -1.0), (0.3, -1.0), (0.3, 1.0), (0.22, 1.0)]],
+            scan_rate_hz: 200.0,
+            ..Default::default()
+        };
+        let mount = LidarMount::default();
+        let simulated = SimulatedLidar::new(simulation, mount);
+        let lidar_pose = simulated.pose();
+        let lidar = Lidar::from_source(mount, ScanFilterChain::default(), simulated);
+        let detector = CollisionDetector::from_config(Some(lidar), &CollisionConfig::default());
+        let (mut navigation, recording) = recording_controller_with(detector);
+        let mut docking = Docking::new(DockingConfig {
+            home: Some(PoseMessage {
+                x: 0.0,
+                y: 0.0,
+                theta: 0.0,
+            }),
+            pre_dock_distance: 0.15,
+            ..Default::default()
+        });
+
+        docking.go_home(&mut navigation);
+        docking.tick(&mut navigation, Some(PoseSource::External));
+        let mut base = SimulatedBase::new(navigation.target().unwrap().clone());
+        navigation.tick(Some(&base.pose)).await.unwrap();
+        docking.tick(&mut navigation, Some(PoseSource::External));
+        assert!(matches!(docking.status(), DockingStatus::Docking { .. }));
+
+        let dt = Duration::from_millis(10);
+        for _ in 0..500 {
+            if !docking.is_active() {
+                break;
+            }
+            *lidar_pose.lock().unwrap() = base.pose.clone();
+            tokio::time::sleep(dt).await;
+            navigation.tick(Some(&base.pose)).await.unwrap();
+            docking.tick(&mut navigation, Some(PoseSource::External));
+            let command = move_command(recording.commands.lock().unwrap().last().unwrap());
+            base.step(&command, dt);
+        }
+        assert_eq!(docking.status(), DockingStatus::Docked, "{}", base.pose);
+    }
+
+    #[tokio::test]
+    async fn reports_failure() {
+        let (mut navigation, _recording) = recording_controller();
+        let mut docking = Docking::new(DockingConfig::default());
+        docking.go_home(&mut navigation);
+        assert_eq!(
+            docking.status(),
+            DockingStatus::Failed {
+                reason: DockingFailure::NoHome
+            }
+        );
+
+        let mut docking = docking_home();
+        docking.go_home(&mut navigation);
+        docking.tick(&mut navigation, None);
+        navigation.issue_user_command(crate::holonomic_controller::MoveCommand::new(0.5, 0.0, 0.0));
+        navigation
+            .tick(Some(&Pose2d::new((0.0, 0.0), 0.0)))
+            .await
+            .unwrap();
+        docking.tick(&mut navigation, None);
+        assert_eq!(
+            docking.status(),
+            DockingStatus::Failed {
+                reason: DockingFailure::Interrupted
+            }
+        );
+        assert!(!docking.is_active());
+    }
+
+    #[test]
+    fn status_format() {
+        let status = DockingStatus::Docking {
+            source: PoseSource::OpenVr,
+        };
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"status":"docking","source":"open_vr"}"#
+        );
+    }
+}
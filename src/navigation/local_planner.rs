@@ -0,0 +1,359 @@
+use nalgebra as na;
+use serde::Deserialize;
+
+use crate::holonomic_controller::{MotionLimits, MoveCommand};
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_samples() -> usize {
+    5
+}
+
+fn default_window() -> f32 {
+    0.5
+}
+
+fn default_horizon_s() -> f32 {
+    2.0
+}
+
+fn default_step_s() -> f32 {
+    0.1
+}
+
+fn default_robot_radius() -> f32 {
+    0.25
+}
+
+fn default_max_linear_speed() -> f32 {
+    0.5
+}
+
+fn default_max_yaw_rate() -> f32 {
+    1.5
+}
+
+fn default_goal_weight() -> f32 {
+    1.0
+}
+
+fn default_heading_weight() -> f32 {
+    0.3
+}
+
+fn default_clearance_weight() -> f32 {
+    0.4
+}
+
+fn default_smoothness_weight() -> f32 {
+    0.1
+}
+
+fn default_max_clearance() -> f32 {
+    0.5
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LocalPlannerConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Samples per axis
+    #[serde(default = "default_samples")]
+    pub samples: usize,
+    /// Largest change from the last command considered
+    #[serde(default = "default_window")]
+    pub window: f32,
+    /// How far candidates are simulated ahead
+    #[serde(default = "default_horizon_s")]
+    pub horizon_s: f32,
+    #[serde(default = "default_step_s")]
+    pub step_s: f32,
+    #[serde(default = "default_robot_radius")]
+    pub robot_radius: f32,
+    /// Speed in m/s at full forward or strafe command
+    #[serde(default = "default_max_linear_speed")]
+    pub max_linear_speed: f32,
+    /// Rotation speed in rad/s at full yaw command
+    #[serde(default = "default_max_yaw_rate")]
+    pub max_yaw_rate: f32,
+    #[serde(default = "default_goal_weight")]
+    pub goal_weight: f32,
+    #[serde(default = "default_heading_weight")]
+    pub heading_weight: f32,
+    #[serde(default = "default_clearance_weight")]
+    pub clearance_weight: f32,
+    #[serde(default = "default_smoothness_weight")]
+    pub smoothness_weight: f32,
+    /// Clearance in meters above which there is no extra reward
+    #[serde(default = "default_max_clearance")]
+    pub max_clearance: f32,
+}
+
+impl Default for LocalPlannerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            samples: default_samples(),
+            window: default_window(),
+            horizon_s: default_horizon_s(),
+            step_s: default_step_s(),
+            robot_radius: default_robot_radius(),
+            max_linear_speed: default_max_linear_speed(),
+            max_yaw_rate: default_max_yaw_rate(),
+            goal_weight: default_goal_weight(),
+            heading_weight: default_heading_weight(),
+            clearance_weight: default_clearance_weight(),
+            smoothness_weight: default_smoothness_weight(),
+            max_clearance: default_max_clearance(),
+        }
+    }
+}
+
+/// Where the local planner is trying to go in the robot frame
+#[derive(Debug, Clone, Copy)]
+pub struct LocalGoal {
+    pub position: na::Point2<f32>,
+    /// Heading error in radians
+    pub heading_error: f32,
+}
+
+/// Dynamic window approach
+///
+/// Samples commands around the last one, simulates each against lidar points
+/// and picks the best one by goal progress, clearance and smoothness.
+pub struct LocalPlanner {
+    config: LocalPlannerConfig,
+    limits: MotionLimits,
+}
+
+impl LocalPlanner {
+    pub fn new(config: LocalPlannerConfig, limits: MotionLimits) -> Self {
+        Self { config, limits }
+    }
+
+    pub fn config(&self) -> &LocalPlannerConfig {
+        &self.config
+    }
+
+    /// Best command or `None` if every candidate collides
+    ///
+    /// `obstacles` are in the robot frame
+    pub fn plan(
+        &self,
+        preferred: &MoveCommand,
+        last: &MoveCommand,
+        goal: &LocalGoal,
+        obstacles: &[na::Point2<f32>],
+    ) -> Option<MoveCommand> {
+        // ignore points that can't be reached within the horizon
+        let reach = self.config.max_linear_speed * self.config.horizon_s
+            + self.config.robot_radius
+            + self.config.max_clearance;
+        let obstacles: Vec<_> = obstacles
+            .iter()
+            .filter(|point| point.coords.norm() < reach)
+            .copied()
+            .collect();
+
+        self.candidates(preferred, last)
+            .filter_map(|candidate| {
+                self.score(&candidate, last, goal, &obstacles)
+                    .map(|score| (candidate, score))
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(candidate, _)| candidate)
+    }
+
+    fn candidates<'a>(
+        &'a self,
+        preferred: &MoveCommand,
+        last: &'a MoveCommand,
+    ) -> impl Iterator<Item = MoveCommand> + 'a {
+        let samples = self.config.samples.max(2);
+        let window = self.config.window;
+        let axis = move |center: f32, max: f32| {
+            let min = (center - window).max(-max);
+            let max = (center + window).min(max);
+            (0..samples).map(move |i| min + (max - min) * i as f32 / (samples - 1) as f32)
+        };
+        let limits = self.limits;
+        axis(last.forward(), limits.max_forward)
+            .flat_map(move |forward| {
+                axis(last.strafe(), limits.max_strafe).flat_map(move |strafe| {
+                    axis(last.yaw(), limits.max_yaw)
+                        .map(move |yaw| MoveCommand::new(forward, strafe, yaw))
+                })
+            })
+            .chain([*preferred, MoveCommand::default()])
+            .map(move |candidate| limits.saturate(&candidate))
+    }
+
+    /// Score of a candidate or `None` if it collides
+    fn score(
+        &self,
+        candidate: &MoveCommand,
+        last: &MoveCommand,
+        goal: &LocalGoal,
+        obstacles: &[na::Point2<f32>],
+    ) -> Option<f32> {
+        let velocity = na::Vector2::new(candidate.forward(), candidate.strafe())
+            * self.config.max_linear_speed;
+        let yaw_rate = candidate.yaw() * self.config.max_yaw_rate;
+        let steps = (self.config.horizon_s / self.config.step_s).ceil().max(1.0) as usize;
+
+        let mut position = na::Point2::origin();
+        let mut heading = 0.0_f32;
+        let mut clearance = f32::INFINITY;
+        for _ in 0..=steps {
+            let nearest = obstacles
+                .iter()
+                .map(|obstacle| na::distance(&position, obstacle))
+                .fold(f32::INFINITY, f32::min);
+            clearance = clearance.min(nearest - self.config.robot_radius);
+            if clearance <= 0.0 {
+                return None;
+            }
+            position += na::Rotation2::new(heading) * velocity * self.config.step_s;
+            heading += yaw_rate * self.config.step_s;
+        }
+
+        let max_travel = (self.config.max_linear_speed * self.config.horizon_s).max(f32::EPSILON);
+        let progress =
+            (goal.position.coords.norm() - na::distance(&position, &goal.position)) / max_travel;
+        let max_turn = (self.config.max_yaw_rate * self.config.horizon_s).max(f32::EPSILON);
+        let heading_progress =
+            (goal.heading_error.abs() - (goal.heading_error - heading).abs()) / max_turn;
+        let clearance = clearance.min(self.config.max_clearance) / self.config.max_clearance;
+        let change = (na::Vector3::new(candidate.forward(), candidate.strafe(), candidate.yaw())
+            - na::Vector3::new(last.forward(), last.strafe(), last.yaw()))
+        .norm();
+        let smoothness = 1.0 - change / self.config.window.max(f32::EPSILON);
+
+        Some(
+            self.config.goal_weight * progress
+                + self.config.heading_weight * heading_progress
+                + self.config.clearance_weight * clearance
+                + self.config.smoothness_weight * smoothness,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planner() -> LocalPlanner {
+        LocalPlanner::new(LocalPlannerConfig::default(), MotionLimits::default())
+    }
+
+    fn goal_ahead() -> LocalGoal {
+        LocalGoal {
+            position: na::Point2::new(3.0, 0.0),
+            heading_error: 0.0,
+        }
+    }
+
+    /// Points on a line segment
+    fn wall(from: (f32, f32), to: (f32, f32)) -> Vec<na::Point2<f32>> {
+        let from = na::Point2::new(from.0, from.1);
+        let to = na::Point2::new(to.0, to.1);
+        (0..=20)
+            .map(|i| from + (to - from) * (i as f32 / 20.0))
+            .collect()
+    }
+
+    #[test]
+    fn drives_to_goal_in_open_space() {
+        let last = MoveCommand::new(0.4, 0.0, 0.0);
+        let command = planner()
+            .plan(&MoveCommand::new(0.5, 0.0, 0.0), &last, &goal_ahead(), &[])
+            .unwrap();
+        assert!(command.forward() > 0.4);
+        assert!(command.strafe().abs() < 0.01);
+        assert!(command.yaw().abs() < 0.01);
+    }
+
+    #[test]
+    fn steers_around_obstacle() {
+        // post in the way, slightly to the right
+        let post = wall((1.0, -0.3), (1.0, 0.05));
+        let goal = na::Point2::new(3.0, 0.0);
+        let planner = planner();
+        let dt = 0.1;
+        let mut position = na::Point2::new(0.0, 0.0);
+        let mut heading = na::Rotation2::identity();
+        let mut last = MoveCommand::default();
+        let mut min_distance = f32::INFINITY;
+        for _ in 0..200 {
+            let to_robot =
+                |point: &na::Point2<f32>| na::Point2::from(heading.inverse() * (point - position));
+            let obstacles: Vec<_> = post.iter().map(to_robot).collect();
+            let local_goal = LocalGoal {
+                position: to_robot(&goal),
+                heading_error: heading.angle_to(&na::Rotation2::identity()),
+            };
+            let preferred = MoveCommand::new(0.5, 0.0, 0.0);
+            last = planner
+                .plan(&preferred, &last, &local_goal, &obstacles)
+                .expect("Open space on the left");
+            let velocity = na::Vector2::new(last.forward(), last.strafe()) * 0.5;
+            position += heading * velocity * dt;
+            heading = na::Rotation2::new(heading.angle() + last.yaw() * 1.5 * dt);
+            min_distance = post
+                .iter()
+                .map(|point| na::distance(point, &position))
+                .fold(min_distance, f32::min);
+        }
+        assert!(min_distance > 0.25, "collided {}", min_distance);
+        assert!(na::distance(&position, &goal) < 0.3, "{}", position);
+    }
+
+    fn ring(radius: f32) -> Vec<na::Point2<f32>> {
+        (0..36)
+            .map(|i| {
+                let angle = (i as f32 * 10.0).to_radians();
+                na::Point2::new(radius * angle.cos(), radius * angle.sin())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stops_when_boxed_in() {
+        let last = MoveCommand::default();
+        let command = planner()
+            .plan(
+                &MoveCommand::new(0.5, 0.0, 0.0),
+                &last,
+                &goal_ahead(),
+                &ring(0.3),
+            )
+            .unwrap();
+        assert_eq!(command.forward(), 0.0);
+        assert_eq!(command.strafe(), 0.0);
+    }
+
+    #[test]
+    fn nothing_admissible_when_already_colliding() {
+        let last = MoveCommand::default();
+        assert!(planner()
+            .plan(
+                &MoveCommand::new(0.5, 0.0, 0.0),
+                &last,
+                &goal_ahead(),
+                &ring(0.2)
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn candidates_stay_within_window() {
+        let planner = planner();
+        let last = MoveCommand::new(0.0, 0.0, 0.0);
+        for candidate in planner.candidates(&MoveCommand::default(), &last) {
+            assert!(candidate.forward().abs() <= 0.5);
+            assert!(candidate.strafe().abs() <= 0.5);
+        }
+    }
+}
@@ -0,0 +1,1109 @@
+use anyhow::{anyhow, Result};
+use config::Config;
+use lss_driver::LedColor;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+use zenoh::{prelude::r#async::*, subscriber::FlumeSubscriber, Session, SessionDeclarations};
+
+use super::{
+    docking::{
+        Docking, DockingConfig, DockingRequest, DockingStatus, DOCKING_COMMAND_TOPIC,
+        DOCKING_STATUS_TOPIC,
+    },
+    state::NavigationState,
+    NavigationController, Pose2d, PoseMessage, SharedNavigationController, Tolerance,
+};
+use crate::{error::ErrorWrapper, localisation::Localiser, util::validate_file_name};
+
+pub const MISSION_COMMAND_TOPIC: &str = "hamilton/mission/command";
+const MISSION_PROGRESS_TOPIC: &str = "hamilton/mission/progress";
+
+fn default_nominal_speed() -> f32 {
+    0.2
+}
+
+fn default_progress_interval_ms() -> u64 {
+    1000
+}
+
+fn default_user_idle_resume_ms() -> Option<u64> {
+    Some(5000)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MissionConfig {
+    /// Directory missions are loaded from by name
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+    /// Average speed in m/s used to estimate time remaining
+    #[serde(default = "default_nominal_speed")]
+    pub nominal_speed: f32,
+    #[serde(default = "default_progress_interval_ms")]
+    pub progress_interval_ms: u64,
+    /// Time without user commands after which a patrol paused by the user resumes
+    ///
+    /// Missions that run once always stay paused, as do patrols when not set
+    #[serde(default = "default_user_idle_resume_ms")]
+    pub user_idle_resume_ms: Option<u64>,
+}
+
+impl Default for MissionConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            nominal_speed: default_nominal_speed(),
+            progress_interval_ms: default_progress_interval_ms(),
+            user_idle_resume_ms: default_user_idle_resume_ms(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "LedColor")]
+enum LedColorDef {
+    Off,
+    Red,
+    Green,
+    Blue,
+    Yellow,
+    Cyan,
+    Magenta,
+    White,
+}
+
+/// Actions performed on arrival at a waypoint
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum WaypointAction {
+    SetLedColor {
+        #[serde(with = "LedColorDef")]
+        color: LedColor,
+    },
+    StartLidar,
+    StopLidar,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Waypoint {
+    pub pose: PoseMessage,
+    /// Time to wait after arriving
+    #[serde(default)]
+    pub dwell_ms: u64,
+    /// Navigation defaults are used when not set
+    #[serde(default)]
+    pub tolerance: Option<Tolerance>,
+    #[serde(default)]
+    pub actions: Vec<WaypointAction>,
+}
+
+impl Waypoint {
+    fn dwell(&self) -> Duration {
+        Duration::from_millis(self.dwell_ms)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MissionMode {
+    /// Drive through the waypoints once
+    #[default]
+    Once,
+    /// Patrol starting over from the first waypoint after the last one
+    Loop,
+    /// Patrol back and forth along the waypoints
+    PingPong,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Mission {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub mode: MissionMode,
+    pub waypoints: Vec<Waypoint>,
+}
+
+impl Mission {
+    /// Load a mission from a JSON or YAML file
+    pub fn load(path: &Path) -> Result<Self> {
+        let settings = Config::builder()
+            .add_source(config::File::from(path))
+            .build()?;
+        Ok(settings.try_deserialize()?)
+    }
+}
+
+/// Requests accepted on the mission zenoh topic
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum MissionRequest {
+    /// Start a mission from the mission directory
+    Start {
+        name: String,
+    },
+    /// Start a mission sent with the request
+    Run {
+        mission: Mission,
+    },
+    /// Start a mission from the mission directory or abort the one running
+    Toggle {
+        name: String,
+    },
+    Pause,
+    Resume,
+    Skip,
+    Abort,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissionStatus {
+    Driving,
+    Dwelling,
+    Paused,
+    Finished,
+    Aborted,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MissionProgress {
+    pub name: String,
+    pub status: MissionStatus,
+    pub current_index: usize,
+    pub waypoint_count: usize,
+    /// Passes through the waypoints completed by a patrol
+    pub lap: usize,
+    /// Distance along the remaining waypoints in meters
+    ///
+    /// Not set while the robot is not localised
+    pub distance_remaining: Option<f32>,
+    /// Estimated time remaining in seconds including dwell times
+    pub eta_s: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Driving {
+        goal_sent: bool,
+    },
+    Dwelling {
+        until: Instant,
+    },
+    Paused {
+        dwell_remaining: Option<Duration>,
+        /// Paused because the user took over
+        by_user: bool,
+    },
+    Finished,
+    Aborted,
+}
+
+impl Phase {
+    fn status(&self) -> MissionStatus {
+        match self {
+            Phase::Driving { .. } => MissionStatus::Driving,
+            Phase::Dwelling { .. } => MissionStatus::Dwelling,
+            Phase::Paused { .. } => MissionStatus::Paused,
+            Phase::Finished => MissionStatus::Finished,
+            Phase::Aborted => MissionStatus::Aborted,
+        }
+    }
+}
+
+struct ActiveMission {
+    mission: Mission,
+    index: usize,
+    /// Ping pong patrol is heading back to the first waypoint
+    reverse: bool,
+    lap: usize,
+    /// Waypoints skipped in a row because they couldn't be reached
+    failures: usize,
+    phase: Phase,
+}
+
+impl ActiveMission {
+    fn new(mission: Mission) -> Self {
+        Self {
+            mission,
+            index: 0,
+            reverse: false,
+            lap: 0,
+            failures: 0,
+            phase: Phase::Driving { goal_sent: false },
+        }
+    }
+
+    fn waypoint(&self) -> &Waypoint {
+        &self.mission.waypoints[self.index]
+    }
+
+    fn is_running(&self) -> bool {
+        !matches!(self.phase, Phase::Finished | Phase::Aborted)
+    }
+
+    /// Current waypoint followed by the rest of this pass
+    fn upcoming(&self) -> Vec<&Waypoint> {
+        let waypoints = &self.mission.waypoints;
+        if self.reverse {
+            waypoints[..=self.index].iter().rev().collect()
+        } else {
+            waypoints[self.index..].iter().collect()
+        }
+    }
+
+    fn advance(&mut self) {
+        let last = self.mission.waypoints.len() - 1;
+        match self.mission.mode {
+            MissionMode::Once if self.index == last => {
+                info!(name = ?self.mission.name, "Mission finished");
+                self.phase = Phase::Finished;
+                return;
+            }
+            MissionMode::Once => self.index += 1,
+            MissionMode::Loop if self.index == last => {
+                self.index = 0;
+                self.lap += 1;
+            }
+            MissionMode::Loop => self.index += 1,
+            MissionMode::PingPong => {
+                let at_end = if self.reverse {
+                    self.index == 0
+                } else {
+                    self.index == last
+                };
+                if at_end {
+                    self.reverse = !self.reverse;
+                    self.lap += 1;
+                }
+                if last > 0 {
+                    if self.reverse {
+                        self.index -= 1;
+                    } else {
+                        self.index += 1;
+                    }
+                }
+            }
+        }
+        self.phase = Phase::Driving { goal_sent: false };
+    }
+
+    fn resume(&mut self, now: Instant) {
+        let Phase::Paused {
+            dwell_remaining, ..
+        } = self.phase
+        else {
+            return;
+        };
+        self.phase = match dwell_remaining {
+            Some(dwell_remaining) => Phase::Dwelling {
+                until: now + dwell_remaining,
+            },
+            None => Phase::Driving { goal_sent: false },
+        };
+        info!(name = ?self.mission.name, "Mission resumed");
+    }
+}
+
+/// Drives through a list of waypoints using the navigation controller
+pub struct MissionExecutor {
+    config: MissionConfig,
+    active: Option<ActiveMission>,
+}
+
+impl MissionExecutor {
+    pub fn new(config: MissionConfig) -> Self {
+        Self {
+            config,
+            active: None,
+        }
+    }
+
+    pub fn status(&self) -> Option<MissionStatus> {
+        self.active.as_ref().map(|active| active.phase.status())
+    }
+
+    /// Load a mission by name from the mission directory
+    pub fn load(&self, name: &str) -> Result<Mission> {
+        validate_file_name(name)?;
+        let directory = self
+            .config
+            .directory
+            .as_ref()
+            .ok_or_else(|| anyhow!("Mission directory not configured"))?;
+        let path = directory
+            .join(name)
+            .to_str()
+            .ok_or_else(|| anyhow!("Failed to convert path"))?
+            .to_owned();
+        // extension is picked up from whichever file exists
+        let settings = Config::builder()
+            .add_source(config::File::with_name(&path))
+            .build()?;
+        let mut mission: Mission = settings.try_deserialize()?;
+        mission.name = name.to_owned();
+        Ok(mission)
+    }
+
+    pub fn start(&mut self, mission: Mission, navigation: &mut NavigationController) -> Result<()> {
+        if mission.waypoints.is_empty() {
+            return Err(anyhow!("Mission {:?} has no waypoints", mission.name));
+        }
+        info!(
+            name = ?mission.name,
+            mode = ?mission.mode,
+            waypoints = mission.waypoints.len(),
+            "Starting mission"
+        );
+        navigation.clear_target();
+        self.active = Some(ActiveMission::new(mission));
+        Ok(())
+    }
+
+    /// Whether a mission is driving, dwelling or paused
+    pub fn is_running(&self) -> bool {
+        self.active.as_ref().is_some_and(ActiveMission::is_running)
+    }
+
+    pub fn handle_request(
+        &mut self,
+        request: MissionRequest,
+        navigation: &mut NavigationController,
+        now: Instant,
+    ) -> Result<()> {
+        match request {
+            MissionRequest::Start { name } => {
+                let mission = self.load(&name)?;
+                self.start(mission, navigation)?;
+            }
+            MissionRequest::Run { mission } => self.start(mission, navigation)?,
+            MissionRequest::Toggle { name } => {
+                if self.is_running() {
+                    self.abort(navigation);
+                } else {
+                    let mission = self.load(&name)?;
+                    self.start(mission, navigation)?;
+                }
+            }
+            MissionRequest::Pause => self.pause(navigation, now),
+            MissionRequest::Resume => self.resume(now),
+            MissionRequest::Skip => self.skip(navigation),
+            MissionRequest::Abort => self.abort(navigation),
+        }
+        Ok(())
+    }
+
+    pub fn pause(&mut self, navigation: &mut NavigationController, now: Instant) {
+        let Some(active) = &mut self.active else {
+            return;
+        };
+        match active.phase {
+            Phase::Driving { .. } => {
+                navigation.clear_target();
+                active.phase = Phase::Paused {
+                    dwell_remaining: None,
+                    by_user: false,
+                };
+            }
+            Phase::Dwelling { until } => {
+                active.phase = Phase::Paused {
+                    dwell_remaining: Some(until.saturating_duration_since(now)),
+                    by_user: false,
+                };
+            }
+            _ => return,
+        }
+        info!(name = ?active.mission.name, "Mission paused");
+    }
+
+    pub fn resume(&mut self, now: Instant) {
+        if let Some(active) = &mut self.active {
+            active.resume(now);
+        }
+    }
+
+    /// Move on to the next waypoint
+    ///
+    /// A paused mission stays paused
+    pub fn skip(&mut self, navigation: &mut NavigationController) {
+        let Some(active) = &mut self.active else {
+            return;
+        };
+        let paused = match active.phase {
+            Phase::Driving { .. } | Phase::Dwelling { .. } => false,
+            Phase::Paused { .. } => true,
+            Phase::Finished | Phase::Aborted => return,
+        };
+        info!(name = ?active.mission.name, index = active.index, "Skipping waypoint");
+        navigation.clear_target();
+        active.advance();
+        if paused && active.phase != Phase::Finished {
+            active.phase = Phase::Paused {
+                dwell_remaining: None,
+                by_user: false,
+            };
+        }
+    }
+
+    pub fn abort(&mut self, navigation: &mut NavigationController) {
+        let Some(active) = &mut self.active else {
+            return;
+        };
+        if !active.is_running() {
+            return;
+        }
+        info!(name = ?active.mission.name, "Mission aborted");
+        navigation.clear_target();
+        active.phase = Phase::Aborted;
+    }
+
+    pub async fn tick(&mut self, navigation: &mut NavigationController, now: Instant) {
+        let Some(active) = &mut self.active else {
+            return;
+        };
+        match active.phase {
+            Phase::Driving { goal_sent: false } => {
+                let waypoint = active.waypoint();
+                let target = Pose2d::from(waypoint.pose);
+                info!(index = active.index, "Driving to waypoint {}", target);
+                match waypoint.tolerance {
+                    Some(tolerance) => navigation.set_target_with_tolerance(target, tolerance),
+                    None => navigation.set_target(target),
+                }
+                active.phase = Phase::Driving { goal_sent: true };
+            }
+            Phase::Driving { goal_sent: true } => {
+                if navigation.target().is_some() {
+                    return;
+                }
+                if navigation.has_arrived() {
+                    active.failures = 0;
+                    let waypoint = active.waypoint().clone();
+                    for action in &waypoint.actions {
+                        if let Err(err) = run_action(action, navigation).await {
+                            error!("Waypoint action {:?} failed with {:?}", action, err);
+                        }
+                    }
+                    active.phase = Phase::Dwelling {
+                        until: now + waypoint.dwell(),
+                    };
+                } else {
+                    match navigation.state() {
+                        NavigationState::UserControl => {
+                            info!(name = ?active.mission.name, "Mission paused by user");
+                            active.phase = Phase::Paused {
+                                dwell_remaining: None,
+                                by_user: true,
+                            };
+                        }
+                        NavigationState::Failed { reason, .. }
+                            if active.mission.mode != MissionMode::Once =>
+                        {
+                            active.failures += 1;
+                            if active.failures >= active.mission.waypoints.len() {
+                                warn!(name = ?active.mission.name, "Patrol aborted, no waypoint is reachable");
+                                active.phase = Phase::Aborted;
+                            } else {
+                                warn!(
+                                    ?reason,
+                                    index = active.index,
+                                    "Skipping unreachable waypoint"
+                                );
+                                active.advance();
+                            }
+                        }
+                        state => {
+                            warn!(
+                                name = ?active.mission.name,
+                                ?state,
+                                "Mission paused, waypoint was not reached"
+                            );
+                            active.phase = Phase::Paused {
+                                dwell_remaining: None,
+                                by_user: false,
+                            };
+                        }
+                    }
+                }
+            }
+            Phase::Dwelling { until } if now >= until => active.advance(),
+            Phase::Paused { by_user: true, .. } if active.mission.mode != MissionMode::Once => {
+                let Some(idle_resume_ms) = self.config.user_idle_resume_ms else {
+                    return;
+                };
+                let user_idle = now.saturating_duration_since(navigation.last_user_command_time());
+                if navigation.state() != NavigationState::UserControl
+                    && user_idle >= Duration::from_millis(idle_resume_ms)
+                {
+                    active.resume(now);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn progress(&self, current_pose: Option<&Pose2d>, now: Instant) -> Option<MissionProgress> {
+        let active = self.active.as_ref()?;
+        let upcoming = active.upcoming();
+        let done = !active.is_running();
+
+        let remaining_dwell = match active.phase {
+            Phase::Finished | Phase::Aborted => Duration::ZERO,
+            Phase::Dwelling { until } => {
+                until.saturating_duration_since(now)
+                    + upcoming[1..]
+                        .iter()
+                        .map(|waypoint| waypoint.dwell())
+                        .sum::<Duration>()
+            }
+            Phase::Paused {
+                dwell_remaining: Some(dwell_remaining),
+                ..
+            } => {
+                dwell_remaining
+                    + upcoming[1..]
+                        .iter()
+                        .map(|waypoint| waypoint.dwell())
+                        .sum::<Duration>()
+            }
+            _ => upcoming.iter().map(|waypoint| waypoint.dwell()).sum(),
+        };
+
+        let distance_remaining = current_pose.map(|current_pose| {
+            if done {
+                return 0.0;
+            }
+            let legs: f32 = upcoming
+                .windows(2)
+                .map(|leg| {
+                    na::distance(
+                        Pose2d::from(leg[0].pose).position(),
+                        Pose2d::from(leg[1].pose).position(),
+                    )
+                })
+                .sum();
+            na::distance(
+                current_pose.position(),
+                Pose2d::from(active.waypoint().pose).position(),
+            ) + legs
+        });
+        let eta_s = distance_remaining.map(|distance| {
+            distance / self.config.nominal_speed.max(f32::EPSILON) + remaining_dwell.as_secs_f32()
+        });
+
+        Some(MissionProgress {
+            name: active.mission.name.clone(),
+            status: active.phase.status(),
+            current_index: active.index,
+            waypoint_count: active.mission.waypoints.len(),
+            lap: active.lap,
+            distance_remaining,
+            eta_s,
+        })
+    }
+}
+
+async fn run_action(action: &WaypointAction, navigation: &mut NavigationController) -> Result<()> {
+    info!("Running waypoint action {:?}", action);
+    match action {
+        WaypointAction::SetLedColor { color } => {
+            navigation.set_color(*color).await?;
+        }
+        WaypointAction::StartLidar => navigation.start_lidar(),
+        WaypointAction::StopLidar => navigation.stop_lidar(),
+    }
+    Ok(())
+}
+
+/// Run missions and docking requested over zenoh
+///
+/// Both share a task so that only one of them sets navigation targets at a time.
+pub async fn start_mission_executor(
+    zenoh_session: Arc<Session>,
+    navigation: SharedNavigationController,
+    localiser: Localiser,
+    config: MissionConfig,
+    docking_config: DockingConfig,
+) -> Result<()> {
+    let command_subscriber = zenoh_session
+        .declare_subscriber(MISSION_COMMAND_TOPIC)
+        .res()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+
+    let docking_subscriber = zenoh_session
+        .declare_subscriber(DOCKING_COMMAND_TOPIC)
+        .res()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+
+    let mut subscribers = BehaviourSubscribers {
+        command: command_subscriber,
+        docking: docking_subscriber,
+    };
+
+    tokio::spawn(async move {
+        let mut executor = MissionExecutor::new(config);
+        let mut docking = Docking::new(docking_config);
+        while let Err(err) = run_mission_executor(
+            &mut executor,
+            &mut docking,
+            &mut subscribers,
+            &zenoh_session,
+            &navigation,
+            &localiser,
+        )
+        .await
+        {
+            error!("Mission executor failed with {:?}", err);
+        }
+    });
+
+    Ok(())
+}
+
+struct BehaviourSubscribers {
+    command: FlumeSubscriber<'static>,
+    docking: FlumeSubscriber<'static>,
+}
+
+async fn run_mission_executor(
+    executor: &mut MissionExecutor,
+    docking: &mut Docking,
+    subscribers: &mut BehaviourSubscribers,
+    zenoh_session: &Session,
+    navigation: &SharedNavigationController,
+    localiser: &Localiser,
+) -> Result<()> {
+    let tick_period = navigation.lock().await.config.tick_period();
+    let mut tick_interval = tokio::time::interval(tick_period);
+    tick_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut progress_interval =
+        tokio::time::interval(Duration::from_millis(executor.config.progress_interval_ms));
+    let mut battery_interval = tokio::time::interval(Duration::from_secs(
+        docking.config().battery_check_interval_s,
+    ));
+    // only go home once per discharge
+    let mut battery_low = false;
+    let mut docking_status = docking.status();
+
+    loop {
+        tokio::select! {
+            sample = subscribers.command.recv_async() => {
+                let message: String = sample?.value.try_into()?;
+                match serde_json::from_str::<MissionRequest>(&message) {
+                    Ok(request) => {
+                        let mut navigation = navigation.lock().await;
+                        docking.cancel(&mut navigation);
+                        if let Err(err) = executor.handle_request(request, &mut navigation, Instant::now()) {
+                            warn!("Failed to handle mission request {:?}", err);
+                        }
+                    }
+                    Err(err) => warn!("Failed to parse mission request {:?}", err),
+                }
+                publish_progress(executor, zenoh_session, localiser).await?;
+            }
+            sample = subscribers.docking.recv_async() => {
+                let message: String = sample?.value.try_into()?;
+                match serde_json::from_str::<DockingRequest>(&message) {
+                    Ok(request) => {
+                        let mut navigation = navigation.lock().await;
+                        if request == DockingRequest::GoHome {
+                            executor.abort(&mut navigation);
+                        }
+                        let current_pose = localiser.latest_pose();
+                        if let Err(err) = docking.handle_request(request, &mut navigation, current_pose.as_ref()) {
+                            warn!("Failed to handle docking request {:?}", err);
+                        }
+                    }
+                    Err(err) => warn!("Failed to parse docking request {:?}", err),
+                }
+            }
+            _ = tick_interval.tick() => {
+                let mut navigation = navigation.lock().await;
+                if docking.is_active() {
+                    let best_source = localiser.latest_pose_with_source().map(|(source, _)| source);
+                    docking.tick(&mut navigation, best_source);
+                } else {
+                    executor.tick(&mut navigation, Instant::now()).await;
+                }
+            }
+            _ = progress_interval.tick() => {
+                publish_progress(executor, zenoh_session, localiser).await?;
+            }
+            _ = battery_interval.tick() => {
+                let Some(return_voltage) = docking.config().return_voltage else {
+                    continue;
+                };
+                let mut navigation = navigation.lock().await;
+                match navigation.read_voltage().await {
+                    Ok(Some(voltage)) if voltage < return_voltage => {
+                        if !battery_low && !docking.is_active() && docking.status() != DockingStatus::Docked {
+                            warn!(voltage, "Battery low, going home");
+                            executor.abort(&mut navigation);
+                            docking.go_home(&mut navigation);
+                        }
+                        battery_low = true;
+                    }
+                    Ok(Some(_)) => battery_low = false,
+                    Ok(None) => (),
+                    Err(err) => warn!("Failed to read voltage {:?}", err),
+                }
+            }
+        }
+
+        if docking.status() != docking_status {
+            docking_status = docking.status();
+            publish_docking_status(&docking_status, zenoh_session).await?;
+        }
+    }
+}
+
+async fn publish_docking_status(status: &DockingStatus, zenoh_session: &Session) -> Result<()> {
+    let message = serde_json::to_string(status)?;
+    zenoh_session
+        .put(DOCKING_STATUS_TOPIC, message)
+        .res_async()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+    Ok(())
+}
+
+async fn publish_progress(
+    executor: &MissionExecutor,
+    zenoh_session: &Session,
+    localiser: &Localiser,
+) -> Result<()> {
+    let Some(progress) = executor.progress(localiser.latest_pose().as_ref(), Instant::now()) else {
+        return Ok(());
+    };
+    let message = serde_json::to_string(&progress)?;
+    zenoh_session
+        .put(MISSION_PROGRESS_TOPIC, message)
+        .congestion_control(CongestionControl::Drop)
+        .res_async()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::recording_controller;
+    use super::*;
+
+    static SQUARE_MISSION: &str = r#"
+name: square
+waypoints:
+  - pose: {x: 1.0, y: 0.0, theta: 0.0}
+    dwell_ms: 1000
+    actions:
+      - action: set_led_color
+        color: Red
+      - action: stop_lidar
+  - pose: {x: 1.0, y: 1.0, theta: 0.0}
+    tolerance: {position: 0.2, heading_deg: 20.0}
+  - pose: {x: 0.0, y: 1.0, theta: 0.0}
+"#;
+
+    fn square_mission() -> Mission {
+        Config::builder()
+            .add_source(config::File::from_str(
+                SQUARE_MISSION,
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_yaml_mission() {
+        let mission = square_mission();
+        assert_eq!(mission.name, "square");
+        assert_eq!(mission.waypoints.len(), 3);
+        assert_eq!(
+            mission.waypoints[0].actions[0],
+            WaypointAction::SetLedColor {
+                color: LedColor::Red
+            }
+        );
+        assert_eq!(mission.waypoints[1].tolerance.unwrap().position, 0.2);
+    }
+
+    #[test]
+    fn parses_json_request() {
+        let request: MissionRequest = serde_json::from_str(
+            r#"{"command": "run", "mission": {"waypoints": [{"pose": {"x": 1, "y": 2, "theta": 0}}]}}"#,
+        )
+        .unwrap();
+        let MissionRequest::Run { mission } = request else {
+            panic!("Unexpected request {:?}", request);
+        };
+        assert_eq!(mission.waypoints[0].pose.y, 2.0);
+    }
+
+    #[tokio::test]
+    async fn drives_through_waypoints() {
+        let (mut navigation, recording) = recording_controller();
+        let mut executor = MissionExecutor::new(MissionConfig::default());
+        executor.start(square_mission(), &mut navigation).unwrap();
+        let now = Instant::now();
+
+        executor.tick(&mut navigation, now).await;
+        assert_eq!(navigation.target().unwrap().position().x, 1.0);
+
+        navigation
+            .tick(Some(&Pose2d::new((1.0, 0.0), 0.0)))
+            .await
+            .unwrap();
+        executor.tick(&mut navigation, now).await;
+        assert_eq!(executor.status(), Some(MissionStatus::Dwelling));
+        assert_eq!(*recording.colors.lock().unwrap(), vec![LedColor::Red]);
+
+        // still dwelling
+        executor
+            .tick(&mut navigation, now + Duration::from_millis(500))
+            .await;
+        assert_eq!(executor.status(), Some(MissionStatus::Dwelling));
+
+        executor
+            .tick(&mut navigation, now + Duration::from_millis(1000))
+            .await;
+        executor
+            .tick(&mut navigation, now + Duration::from_millis(1000))
+            .await;
+        assert_eq!(navigation.target().unwrap().position().y, 1.0);
+
+        // wider tolerance of the second waypoint
+        navigation
+            .tick(Some(&Pose2d::new((0.9, 0.9), 0.0)))
+            .await
+            .unwrap();
+        executor.tick(&mut navigation, now).await;
+        assert_eq!(executor.status(), Some(MissionStatus::Dwelling));
+
+        executor.skip(&mut navigation);
+        executor.skip(&mut navigation);
+        assert_eq!(executor.status(), Some(MissionStatus::Finished));
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume() {
+        let (mut navigation, _recording) = recording_controller();
+        let mut executor = MissionExecutor::new(MissionConfig::default());
+        executor.start(square_mission(), &mut navigation).unwrap();
+        let now = Instant::now();
+        executor.tick(&mut navigation, now).await;
+
+        executor.pause(&mut navigation, now);
+        assert!(navigation.target().is_none());
+        executor.tick(&mut navigation, now).await;
+        assert_eq!(executor.status(), Some(MissionStatus::Paused));
+
+        executor.resume(now);
+        executor.tick(&mut navigation, now).await;
+        assert_eq!(navigation.target().unwrap().position().x, 1.0);
+
+        executor.abort(&mut navigation);
+        assert!(navigation.target().is_none());
+        assert_eq!(executor.status(), Some(MissionStatus::Aborted));
+    }
+
+    #[tokio::test]
+    async fn user_preemption_pauses_mission() {
+        let (mut navigation, _recording) = recording_controller();
+        let mut executor = MissionExecutor::new(MissionConfig::default());
+        executor.start(square_mission(), &mut navigation).unwrap();
+        let now = Instant::now();
+        executor.tick(&mut navigation, now).await;
+
+        navigation.issue_user_command(crate::holonomic_controller::MoveCommand::new(0.5, 0.0, 0.0));
+        navigation
+            .tick(Some(&Pose2d::new((0.0, 0.0), 0.0)))
+            .await
+            .unwrap();
+        executor.tick(&mut navigation, now).await;
+        assert_eq!(executor.status(), Some(MissionStatus::Paused));
+    }
+
+    #[tokio::test]
+    async fn progress_estimates_remaining_distance() {
+        let (mut navigation, _recording) = recording_controller();
+        let mut executor = MissionExecutor::new(MissionConfig::default());
+        executor.start(square_mission(), &mut navigation).unwrap();
+        let progress = executor
+            .progress(Some(&Pose2d::new((0.0, 0.0), 0.0)), Instant::now())
+            .unwrap();
+        assert_eq!(progress.current_index, 0);
+        assert_eq!(progress.waypoint_count, 3);
+        assert_eq!(progress.distance_remaining, Some(3.0));
+        // 3m at 0.2m/s and 1s dwell
+        assert_eq!(progress.eta_s, Some(16.0));
+
+        let progress = executor.progress(None, Instant::now()).unwrap();
+        assert_eq!(progress.distance_remaining, None);
+    }
+
+    fn patrol(mode: MissionMode, points: &[(f32, f32)]) -> Mission {
+        Mission {
+            name: String::from("patrol"),
+            mode,
+            waypoints: points
+                .iter()
+                .map(|(x, y)| Waypoint {
+                    pose: PoseMessage {
+                        x: *x,
+                        y: *y,
+                        theta: 0.0,
+                    },
+                    dwell_ms: 0,
+                    tolerance: None,
+                    actions: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    /// Arrive at the current target and return the next one
+    async fn arrive(executor: &mut MissionExecutor, navigation: &mut NavigationController) -> f32 {
+        let now = Instant::now();
+        let target = navigation.target().unwrap().clone();
+        navigation.tick(Some(&target)).await.unwrap();
+        // arrive, finish dwelling and send the next goal
+        for _ in 0..3 {
+            executor.tick(navigation, now).await;
+        }
+        navigation.target().unwrap().position().x
+    }
+
+    #[tokio::test]
+    async fn patrols_repeat() {
+        let points = [(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)];
+        let (mut navigation, _recording) = recording_controller();
+        let mut executor = MissionExecutor::new(MissionConfig::default());
+        executor
+            .start(patrol(MissionMode::Loop, &points), &mut navigation)
+            .unwrap();
+        executor.tick(&mut navigation, Instant::now()).await;
+        let mut visited = vec![];
+        for _ in 0..4 {
+            visited.push(arrive(&mut executor, &mut navigation).await);
+        }
+        assert_eq!(visited, vec![1.0, 2.0, 0.0, 1.0]);
+        let progress = executor.progress(None, Instant::now()).unwrap();
+        assert_eq!(progress.lap, 1);
+
+        executor
+            .start(patrol(MissionMode::PingPong, &points), &mut navigation)
+            .unwrap();
+        executor.tick(&mut navigation, Instant::now()).await;
+        let mut visited = vec![];
+        for _ in 0..5 {
+            visited.push(arrive(&mut executor, &mut navigation).await);
+        }
+        assert_eq!(visited, vec![1.0, 2.0, 1.0, 0.0, 1.0]);
+        let progress = executor
+            .progress(Some(&Pose2d::new((0.0, 0.0), 0.0)), Instant::now())
+            .unwrap();
+        assert_eq!(progress.lap, 2);
+        assert_eq!(progress.distance_remaining, Some(2.0));
+    }
+
+    #[tokio::test]
+    async fn patrol_skips_unreachable_waypoints() {
+        let (mut navigation, _recording) = recording_controller();
+        let map = crate::map::Map::new(na::Vector2::new(0.0, 4.0), na::Vector2::new(4.0, 0.0))
+            .with_obstacles(vec![crate::map::MapObstacle::new(
+                na::Vector2::new(1.9, 0.0),
+                na::Vector2::new(2.1, 3.0),
+            )]);
+        navigation.set_planner(super::super::planner::GlobalPlanner::new(
+            &map,
+            super::super::PlannerConfig::default(),
+        ));
+        let mut executor = MissionExecutor::new(MissionConfig::default());
+        let pose = Pose2d::new((1.0, 1.0), 0.0);
+
+        // second waypoint is inside an obstacle
+        executor
+            .start(
+                patrol(MissionMode::Loop, &[(1.0, 1.0), (2.0, 1.0)]),
+                &mut navigation,
+            )
+            .unwrap();
+        executor.tick(&mut navigation, Instant::now()).await;
+        assert_eq!(arrive(&mut executor, &mut navigation).await, 2.0);
+        navigation.tick(Some(&pose)).await.unwrap();
+        executor.tick(&mut navigation, Instant::now()).await;
+        executor.tick(&mut navigation, Instant::now()).await;
+        assert_eq!(executor.status(), Some(MissionStatus::Driving));
+        assert_eq!(navigation.target().unwrap().position().x, 1.0);
+
+        // gives up when nothing can be reached
+        executor
+            .start(
+                patrol(MissionMode::Loop, &[(2.0, 1.0), (2.0, 2.0)]),
+                &mut navigation,
+            )
+            .unwrap();
+        for _ in 0..2 {
+            executor.tick(&mut navigation, Instant::now()).await;
+            navigation.tick(Some(&pose)).await.unwrap();
+            executor.tick(&mut navigation, Instant::now()).await;
+        }
+        assert_eq!(executor.status(), Some(MissionStatus::Aborted));
+    }
+
+    #[tokio::test]
+    async fn patrols_resume_after_user_idle() {
+        for (mode, resumes) in [(MissionMode::Loop, true), (MissionMode::Once, false)] {
+            let (mut navigation, _recording) = recording_controller();
+            let mut executor = MissionExecutor::new(MissionConfig::default());
+            executor
+                .start(patrol(mode, &[(1.0, 0.0), (2.0, 0.0)]), &mut navigation)
+                .unwrap();
+            let now = Instant::now();
+            let pose = Pose2d::new((0.0, 0.0), 0.0);
+            executor.tick(&mut navigation, now).await;
+
+            navigation
+                .issue_user_command(crate::holonomic_controller::MoveCommand::new(0.5, 0.0, 0.0));
+            navigation.tick(Some(&pose)).await.unwrap();
+            executor.tick(&mut navigation, now).await;
+            assert_eq!(executor.status(), Some(MissionStatus::Paused));
+            // user still driving
+            executor
+                .tick(&mut navigation, now + Duration::from_secs(10))
+                .await;
+            assert_eq!(executor.status(), Some(MissionStatus::Paused));
+
+            navigation.set_user_command(
+                crate::holonomic_controller::MoveCommand::default(),
+                now - Duration::from_secs(2),
+            );
+            navigation.tick(Some(&pose)).await.unwrap();
+            executor.tick(&mut navigation, now).await;
+            assert_eq!(executor.status(), Some(MissionStatus::Paused));
+            executor
+                .tick(&mut navigation, now + Duration::from_secs(3))
+                .await;
+            executor
+                .tick(&mut navigation, now + Duration::from_secs(3))
+                .await;
+            if resumes {
+                assert_eq!(executor.status(), Some(MissionStatus::Driving));
+                assert_eq!(navigation.target().unwrap().position().x, 1.0);
+            } else {
+                // missions that run once wait for the user to resume them
+                assert_eq!(executor.status(), Some(MissionStatus::Paused));
+            }
+        }
+    }
+}
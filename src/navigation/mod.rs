@@ -0,0 +1,1131 @@
+pub mod docking;
+pub mod local_planner;
+pub mod mission;
+pub mod path_follower;
+pub mod planner;
+pub mod pose;
+pub mod pose_controller;
+pub mod state;
+
+use crate::collision::{CollisionChecker, CollisionDetector};
+use crate::driver::HamiltonDriver;
+use crate::error::ErrorWrapper;
+use crate::gamepad::{feedback::FeedbackSender, messages::FeedbackEvent};
+use crate::holonomic_controller::{HolonomicWheelCommand, MotionLimits, MoveCommand};
+use crate::localisation::{Localiser, PoseSource};
+use anyhow::Result;
+use docking::DockingConfig;
+use local_planner::{LocalGoal, LocalPlanner, LocalPlannerConfig};
+use lss_driver::LedColor;
+use mission::MissionConfig;
+use nalgebra as na;
+use path_follower::{AccelerationLimiter, PathFollower, PathFollowerConfig};
+use planner::{GlobalPlanner, PlannerConfig};
+pub use pose::{Pose2d, PoseMessage};
+use pose_controller::{PoseController, PoseControllerConfig};
+use serde::{Deserialize, Serialize};
+use state::{FailureReason, NavigationState, ProgressMonitor};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch, Mutex};
+use tracing::{error, info, warn};
+use zenoh::{prelude::r#async::*, subscriber::FlumeSubscriber, Session, SessionDeclarations};
+
+const GOAL_TOPIC: &str = "hamilton/navigation/goal";
+const CANCEL_TOPIC: &str = "hamilton/navigation/cancel";
+const GAINS_TOPIC: &str = "hamilton/navigation/gains";
+const STATE_TOPIC: &str = "hamilton/navigation/state";
+const SPEED_SCALE_TOPIC: &str = "hamilton/navigation/speed_scale";
+/// Smallest speed scale change worth publishing
+const SPEED_SCALE_RESOLUTION: f32 = 0.05;
+
+fn default_position_tolerance() -> f32 {
+    0.05
+}
+
+fn default_heading_tolerance_deg() -> f32 {
+    5.0
+}
+
+fn default_tick_rate_hz() -> f32 {
+    20.0
+}
+
+fn default_stuck_timeout_s() -> f32 {
+    10.0
+}
+
+fn default_min_progress() -> f32 {
+    0.05
+}
+
+fn default_min_heading_progress_deg() -> f32 {
+    5.0
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NavigationConfig {
+    /// Distance to the target in meters at which the robot has arrived
+    #[serde(default = "default_position_tolerance")]
+    pub position_tolerance: f32,
+    /// Heading error in degrees at which the robot has arrived
+    #[serde(default = "default_heading_tolerance_deg")]
+    pub heading_tolerance_deg: f32,
+    #[serde(default = "default_tick_rate_hz")]
+    pub tick_rate_hz: f32,
+    /// Navigation fails when no progress is made for this long
+    #[serde(default = "default_stuck_timeout_s")]
+    pub stuck_timeout_s: f32,
+    /// Smallest decrease in distance to the target in meters counted as progress
+    #[serde(default = "default_min_progress")]
+    pub min_progress: f32,
+    /// Smallest decrease in heading error in degrees counted as progress
+    #[serde(default = "default_min_heading_progress_deg")]
+    pub min_heading_progress_deg: f32,
+    /// Speed limits shared by all navigation controllers
+    #[serde(default)]
+    pub limits: MotionLimits,
+    #[serde(default)]
+    pub pose_controller: PoseControllerConfig,
+    #[serde(default)]
+    pub mission: MissionConfig,
+    #[serde(default)]
+    pub planner: PlannerConfig,
+    #[serde(default)]
+    pub path_follower: PathFollowerConfig,
+    #[serde(default)]
+    pub local_planner: LocalPlannerConfig,
+    #[serde(default)]
+    pub docking: DockingConfig,
+}
+
+impl Default for NavigationConfig {
+    fn default() -> Self {
+        Self {
+            position_tolerance: default_position_tolerance(),
+            heading_tolerance_deg: default_heading_tolerance_deg(),
+            tick_rate_hz: default_tick_rate_hz(),
+            stuck_timeout_s: default_stuck_timeout_s(),
+            min_progress: default_min_progress(),
+            min_heading_progress_deg: default_min_heading_progress_deg(),
+            limits: MotionLimits::default(),
+            pose_controller: PoseControllerConfig::default(),
+            mission: MissionConfig::default(),
+            planner: PlannerConfig::default(),
+            path_follower: PathFollowerConfig::default(),
+            local_planner: LocalPlannerConfig::default(),
+            docking: DockingConfig::default(),
+        }
+    }
+}
+
+impl NavigationConfig {
+    fn tick_period(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.tick_rate_hz.max(1.0))
+    }
+
+    fn stuck_timeout(&self) -> Duration {
+        Duration::from_secs_f32(self.stuck_timeout_s.max(0.0))
+    }
+}
+
+/// Arrival tolerance for a single target
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// Distance in meters
+    pub position: f32,
+    /// Heading error in degrees
+    pub heading_deg: f32,
+}
+
+/// User commands are followed for this long after they were issued
+pub const USER_COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// State transitions kept for subscribers that fall behind
+const STATE_TRANSITION_CAPACITY: usize = 32;
+
+pub type SharedNavigationController = Arc<Mutex<NavigationController>>;
+
+pub struct NavigationController {
+    driver: Box<dyn HamiltonDriver>,
+    config: NavigationConfig,
+    target: Option<Pose2d>,
+    target_tolerance: Option<Tolerance>,
+    state: watch::Sender<NavigationState>,
+    /// Every state change, a watch would skip states that only last a moment
+    transitions: broadcast::Sender<NavigationState>,
+    /// Translation scale applied for nearby obstacles
+    speed_scale: watch::Sender<f32>,
+    progress: ProgressMonitor,
+    planner: Option<GlobalPlanner>,
+    /// Intermediate waypoints to the target
+    path: Vec<na::Point2<f32>>,
+    needs_plan: bool,
+    /// Drive straight to the target with this checker instead of planning around obstacles
+    approach_checker: Option<Arc<dyn CollisionChecker>>,
+    pose_controller: PoseController,
+    path_follower: PathFollower,
+    local_planner: LocalPlanner,
+    acceleration_limiter: AccelerationLimiter,
+    last_tick: Option<Instant>,
+    /// Translation limit on top of the motion limits
+    speed_limit: Option<f32>,
+    /// Localiser navigation is restricted to
+    pose_source: Option<PoseSource>,
+    last_user_command: MoveCommand,
+    last_user_command_time: Instant,
+    collision_detector: CollisionDetector,
+    feedback: Option<FeedbackSender>,
+}
+
+impl NavigationController {
+    pub fn new(
+        driver: Box<dyn HamiltonDriver>,
+        collision_detector: CollisionDetector,
+        config: NavigationConfig,
+    ) -> Self {
+        Self {
+            driver,
+            pose_controller: PoseController::new(config.pose_controller, config.limits),
+            path_follower: PathFollower::new(config.path_follower.clone(), config.limits),
+            local_planner: LocalPlanner::new(config.local_planner.clone(), config.limits),
+            acceleration_limiter: AccelerationLimiter::default(),
+            config,
+            target: None,
+            target_tolerance: None,
+            state: watch::Sender::new(NavigationState::Idle),
+            transitions: broadcast::channel(STATE_TRANSITION_CAPACITY).0,
+            speed_scale: watch::Sender::new(1.0),
+            progress: ProgressMonitor::new(Instant::now()),
+            planner: None,
+            path: vec![],
+            needs_plan: false,
+            approach_checker: None,
+            last_tick: None,
+            speed_limit: None,
+            pose_source: None,
+            last_user_command: MoveCommand::new(0., 0., 0.),
+            last_user_command_time: Instant::now(),
+            collision_detector,
+            feedback: None,
+        }
+    }
+
+    /// Report blocked moves to the operator
+    pub fn set_feedback(&mut self, feedback: FeedbackSender) {
+        self.feedback = Some(feedback);
+    }
+
+    /// Plan paths around obstacles instead of driving straight to targets
+    pub fn set_planner(&mut self, planner: GlobalPlanner) {
+        self.planner = Some(planner);
+    }
+
+    pub fn path(&self) -> &[na::Point2<f32>] {
+        &self.path
+    }
+
+    pub fn set_target(&mut self, target: Pose2d) {
+        self.pose_controller.reset();
+        self.progress = ProgressMonitor::new(Instant::now());
+        self.set_state(NavigationState::Navigating {
+            target: PoseMessage::from(&target),
+        });
+        self.target = Some(target);
+        self.target_tolerance = None;
+        self.path.clear();
+        self.needs_plan = true;
+        self.approach_checker = None;
+    }
+
+    /// Set target with tolerances other than the configured ones
+    pub fn set_target_with_tolerance(&mut self, target: Pose2d, tolerance: Tolerance) {
+        self.set_target(target);
+        self.target_tolerance = Some(tolerance);
+    }
+
+    /// Drive straight to the target with the pose controller
+    ///
+    /// Skips the global and local planners so that the target can be next to obstacles,
+    /// as when docking. `checker` replaces the collision checker until the target is cleared.
+    pub fn set_approach_target(
+        &mut self,
+        target: Pose2d,
+        tolerance: Tolerance,
+        checker: Box<dyn CollisionChecker>,
+    ) {
+        self.set_target_with_tolerance(target, tolerance);
+        self.needs_plan = false;
+        self.approach_checker = Some(checker.into());
+    }
+
+    pub fn clear_target(&mut self) {
+        if self.target.is_some() {
+            self.set_state(NavigationState::Idle);
+        }
+        self.reset_target();
+    }
+
+    fn reset_target(&mut self) {
+        self.pose_controller.reset();
+        self.target = None;
+        self.target_tolerance = None;
+        self.path.clear();
+        self.approach_checker = None;
+    }
+
+    /// Whether the last target was reached rather than cancelled or preempted
+    pub fn has_arrived(&self) -> bool {
+        matches!(self.state(), NavigationState::Arrived { .. })
+    }
+
+    pub fn state(&self) -> NavigationState {
+        *self.state.borrow()
+    }
+
+    /// Receiver of every state change in order
+    ///
+    /// Receivers that fall more than `STATE_TRANSITION_CAPACITY` changes behind miss the oldest ones.
+    pub fn subscribe_state(&self) -> broadcast::Receiver<NavigationState> {
+        self.transitions.subscribe()
+    }
+
+    /// Receiver notified when the obstacle speed scale changes
+    pub fn subscribe_speed_scale(&self) -> watch::Receiver<f32> {
+        self.speed_scale.subscribe()
+    }
+
+    fn set_state(&self, state: NavigationState) {
+        self.state.send_if_modified(|current| {
+            if *current == state {
+                return false;
+            }
+            info!(?state, "Navigation state changed");
+            *current = state;
+            // no subscribers is fine
+            _ = self.transitions.send(state);
+            true
+        });
+    }
+
+    /// Update pose controller gains while running
+    pub fn set_pose_controller_config(&mut self, config: PoseControllerConfig) {
+        self.config.pose_controller = config;
+        self.pose_controller.set_config(config);
+    }
+
+    pub fn target(&self) -> Option<&Pose2d> {
+        self.target.as_ref()
+    }
+
+    pub fn issue_user_command(&mut self, command: MoveCommand) {
+        self.last_user_command = command;
+        self.last_user_command_time = Instant::now();
+    }
+
+    pub fn set_user_command(&mut self, command: MoveCommand, time: Instant) {
+        self.last_user_command = command;
+        self.last_user_command_time = time;
+    }
+
+    /// Limit translation commands for slow approaches
+    pub fn set_speed_limit(&mut self, speed_limit: Option<f32>) {
+        self.speed_limit = speed_limit;
+    }
+
+    /// Only navigate on poses from a single localiser
+    pub fn set_pose_source(&mut self, source: Option<PoseSource>) {
+        self.pose_source = source;
+    }
+
+    pub fn pose_source(&self) -> Option<PoseSource> {
+        self.pose_source
+    }
+
+    pub fn last_user_command_time(&self) -> Instant {
+        self.last_user_command_time
+    }
+
+    pub async fn tick(&mut self, current_pose: Option<&Pose2d>) -> Result<()> {
+        let now = Instant::now();
+        // fall back to the nominal period on the first tick and after long stalls
+        let dt = self
+            .last_tick
+            .map(|last_tick| now.duration_since(last_tick))
+            .filter(|dt| *dt <= self.config.tick_period() * 4)
+            .unwrap_or_else(|| self.config.tick_period());
+        self.last_tick = Some(now);
+
+        if self.last_user_command_time.elapsed() < USER_COMMAND_TIMEOUT {
+            if let Some(target) = self.target.clone() {
+                info!("Navigation to {} preempted by user", target);
+                self.reset_target();
+            }
+            self.set_state(NavigationState::UserControl);
+            self.acceleration_limiter.reset();
+            let command = self.last_user_command;
+            self.send_checked(&command).await?;
+            return Ok(());
+        }
+        if self.state() == NavigationState::UserControl {
+            self.set_state(NavigationState::Idle);
+        }
+
+        if let Some(target) = self.target.clone() {
+            let target_message = PoseMessage::from(&target);
+            if let Some(current_pose) = current_pose {
+                if self.is_at(current_pose, &target) {
+                    info!("Arrived at {}", target);
+                    self.reset_target();
+                    self.set_state(NavigationState::Arrived {
+                        target: target_message,
+                    });
+                    self.stop().await?;
+                    return Ok(());
+                }
+                let path_result = match self.approach_checker {
+                    Some(_) => Ok(()),
+                    None => self.update_path(current_pose, &target, now),
+                };
+                if let Err(err) = path_result {
+                    warn!("Failed to plan path to {} {:?}", target, err);
+                    self.reset_target();
+                    self.set_state(NavigationState::Failed {
+                        target: target_message,
+                        reason: FailureReason::NoPath,
+                    });
+                    self.stop().await?;
+                    return Ok(());
+                }
+                self.progress.update(
+                    self.remaining_distance(current_pose, &target),
+                    current_pose.heading_error(&target).abs(),
+                    self.config.min_progress,
+                    self.config.min_heading_progress_deg.to_radians(),
+                    now,
+                );
+            } else {
+                // can't navigate without knowing where we are
+                self.pose_controller.reset();
+            }
+
+            if let Some(reason) = self.progress.failure(self.config.stuck_timeout(), now) {
+                warn!(?reason, "Navigation to {} failed", target);
+                self.reset_target();
+                self.set_state(NavigationState::Failed {
+                    target: target_message,
+                    reason,
+                });
+            } else if let Some(current_pose) = current_pose {
+                let command = if self.approach_checker.is_none()
+                    && self.remaining_distance(current_pose, &target)
+                        > self.config.path_follower.handover_distance
+                {
+                    // holonomic base can hold the target heading the whole way
+                    let path: Vec<_> = self
+                        .path
+                        .iter()
+                        .map(|waypoint| Pose2d::from_na(*waypoint, *target.rotation()))
+                        .chain(std::iter::once(target.clone()))
+                        .collect();
+                    self.pose_controller.reset();
+                    let command = self.path_follower.update(current_pose, &path);
+                    match self.avoid_obstacles(current_pose, &target, &command) {
+                        Some(command) => command,
+                        None => {
+                            if let Some(feedback) = &self.feedback {
+                                feedback.send(FeedbackEvent::CollisionBlocked);
+                            }
+                            self.set_state(NavigationState::Blocked {
+                                target: target_message,
+                            });
+                            self.stop().await?;
+                            return Ok(());
+                        }
+                    }
+                } else {
+                    self.pose_controller.update(current_pose, &target, dt)
+                };
+                let command = match self.speed_limit {
+                    Some(speed_limit) => limit_translation(&command, speed_limit),
+                    None => command,
+                };
+                let command = self.acceleration_limiter.limit(
+                    &command,
+                    self.config.path_follower.max_acceleration,
+                    self.config.path_follower.max_yaw_acceleration,
+                    dt,
+                );
+                let safe = self.send_checked(&command).await?;
+                self.set_state(if safe {
+                    NavigationState::Navigating {
+                        target: target_message,
+                    }
+                } else {
+                    NavigationState::Blocked {
+                        target: target_message,
+                    }
+                });
+                return Ok(());
+            }
+        }
+
+        self.stop().await?;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.acceleration_limiter.reset();
+        self.driver.send(HolonomicWheelCommand::stopped()).await
+    }
+
+    /// Plan or replan the path when needed and drop waypoints that were passed
+    fn update_path(&mut self, current: &Pose2d, target: &Pose2d, now: Instant) -> Result<()> {
+        let Some(planner) = &self.planner else {
+            return Ok(());
+        };
+        // only gather obstacles when they will be used
+        let obstacles = if self.needs_plan || planner.replan_check_due(now) {
+            self.scan_obstacles(current)
+        } else {
+            vec![]
+        };
+        let Some(planner) = &mut self.planner else {
+            return Ok(());
+        };
+        let remaining: Vec<_> = self
+            .path
+            .iter()
+            .chain(std::iter::once(target.position()))
+            .copied()
+            .collect();
+        if self.needs_plan || planner.should_replan(current.position(), &remaining, &obstacles, now)
+        {
+            let path = planner.plan(current.position(), target.position(), &obstacles)?;
+            // first and last points are the current position and target
+            self.path = path[1..path.len() - 1].to_vec();
+            self.needs_plan = false;
+            info!(
+                "Planned path to {} through {} waypoints",
+                target,
+                self.path.len()
+            );
+        }
+        let waypoint_tolerance = planner.config().waypoint_tolerance;
+        while self
+            .path
+            .first()
+            .is_some_and(|waypoint| na::distance(current.position(), waypoint) < waypoint_tolerance)
+        {
+            self.path.remove(0);
+        }
+        Ok(())
+    }
+
+    /// Steer the follower command around obstacles in the latest scan
+    ///
+    /// Returns `None` when every candidate collides
+    fn avoid_obstacles(
+        &self,
+        current: &Pose2d,
+        target: &Pose2d,
+        command: &MoveCommand,
+    ) -> Option<MoveCommand> {
+        if !self.local_planner.config().enabled {
+            return Some(*command);
+        }
+        let obstacles = self.scan_points();
+        if obstacles.is_empty() {
+            return Some(*command);
+        }
+        let next = self.path.first().unwrap_or(target.position());
+        let goal = LocalGoal {
+            position: current.inverse_transform_point(next),
+            heading_error: current.heading_error(target),
+        };
+        self.local_planner.plan(
+            command,
+            &self.acceleration_limiter.last(),
+            &goal,
+            &obstacles,
+        )
+    }
+
+    /// Latest lidar points in the robot frame
+    fn scan_points(&self) -> Vec<na::Point2<f32>> {
+        self.collision_detector.scan_points()
+    }
+
+    /// Latest lidar points in the world frame
+    fn scan_obstacles(&self, current: &Pose2d) -> Vec<na::Point2<f32>> {
+        self.scan_points()
+            .into_iter()
+            .map(|point| current.transform_point(&point))
+            .collect()
+    }
+
+    /// Distance along the path to the target
+    fn remaining_distance(&self, current: &Pose2d, target: &Pose2d) -> f32 {
+        let mut distance = 0.0;
+        let mut from = *current.position();
+        for waypoint in self.path.iter().chain(std::iter::once(target.position())) {
+            distance += na::distance(&from, waypoint);
+            from = *waypoint;
+        }
+        distance
+    }
+
+    fn is_at(&self, current: &Pose2d, target: &Pose2d) -> bool {
+        let tolerance = self.target_tolerance.unwrap_or(Tolerance {
+            position: self.config.position_tolerance,
+            heading_deg: self.config.heading_tolerance_deg,
+        });
+        let distance = current.distance(target);
+        let heading_error = current.heading_error(target).abs();
+        distance <= tolerance.position && heading_error <= tolerance.heading_deg.to_radians()
+    }
+
+    /// Publish scale changes large enough to matter and always reaching either end
+    fn set_speed_scale(&self, scale: f32) {
+        self.speed_scale.send_if_modified(|current| {
+            let small_change = (*current - scale).abs() < SPEED_SCALE_RESOLUTION;
+            if *current == scale || (small_change && scale > 0.0 && scale < 1.0) {
+                return false;
+            }
+            *current = scale;
+            true
+        });
+    }
+
+    /// Send the command allowed by the collision checker
+    ///
+    /// Returns whether the command was safe rather than blocked
+    async fn send_checked(&mut self, command: &MoveCommand) -> Result<bool> {
+        let check = match &self.approach_checker {
+            Some(checker) => self
+                .collision_detector
+                .check_with(checker.as_ref(), command),
+            None => self.collision_detector.check(command),
+        };
+        self.set_speed_scale(check.reason.speed_scale());
+        let safe = !check.reason.is_blocked();
+        if !safe {
+            if let Some(feedback) = &self.feedback {
+                feedback.send(FeedbackEvent::CollisionBlocked);
+            }
+        }
+        self.driver
+            .send(HolonomicWheelCommand::from_move_command(&check.command))
+            .await?;
+        Ok(safe)
+    }
+
+    pub async fn read_voltage(&mut self) -> Result<Option<f32>> {
+        self.driver.read_voltage().await
+    }
+
+    pub async fn set_color(&mut self, color: LedColor) -> Result<Option<()>> {
+        self.driver.set_color(color).await
+    }
+
+    pub fn start_lidar(&mut self) {
+        self.collision_detector.start_lidar();
+    }
+
+    pub fn stop_lidar(&mut self) {
+        self.collision_detector.stop_lidar();
+    }
+}
+
+/// Scale translation down to a speed keeping the direction
+fn limit_translation(command: &MoveCommand, speed_limit: f32) -> MoveCommand {
+    let speed = command.forward().hypot(command.strafe());
+    if speed <= speed_limit {
+        return *command;
+    }
+    let factor = speed_limit.max(0.0) / speed;
+    MoveCommand::new(
+        command.forward() * factor,
+        command.strafe() * factor,
+        command.yaw(),
+    )
+}
+
+/// Run the navigation tick loop and listen for goals
+pub async fn start_navigation_loop(
+    zenoh_session: Arc<Session>,
+    navigation: SharedNavigationController,
+    localiser: Localiser,
+) -> Result<()> {
+    let (tick_period, initial_state, mut state_receiver, mut speed_scale_receiver) = {
+        let navigation = navigation.lock().await;
+        (
+            navigation.config.tick_period(),
+            navigation.state(),
+            navigation.subscribe_state(),
+            navigation.subscribe_speed_scale(),
+        )
+    };
+
+    tokio::spawn({
+        let zenoh_session = zenoh_session.clone();
+        async move {
+            let mut state = Some(initial_state);
+            loop {
+                if let Some(state) = state {
+                    if let Err(err) = publish_state(&zenoh_session, &state).await {
+                        error!("Failed to publish navigation state {:?}", err);
+                    }
+                }
+                state = match state_receiver.recv().await {
+                    Ok(state) => Some(state),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!(missed, "Navigation state publisher fell behind");
+                        None
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+            }
+        }
+    });
+
+    tokio::spawn({
+        let zenoh_session = zenoh_session.clone();
+        async move {
+            loop {
+                let scale = *speed_scale_receiver.borrow_and_update();
+                if let Err(err) = publish_speed_scale(&zenoh_session, scale).await {
+                    error!("Failed to publish speed scale {:?}", err);
+                }
+                if speed_scale_receiver.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    tokio::spawn({
+        let navigation = navigation.clone();
+        async move {
+            let mut interval = tokio::time::interval(tick_period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                let mut navigation = navigation.lock().await;
+                let current_pose = match navigation.pose_source() {
+                    Some(source) => localiser.pose_from(source),
+                    None => localiser.latest_pose(),
+                };
+                if let Err(err) = navigation.tick(current_pose.as_ref()).await {
+                    error!("Navigation tick failed with {:?}", err);
+                }
+            }
+        }
+    });
+
+    let mut goal_subscriber = zenoh_session
+        .declare_subscriber(GOAL_TOPIC)
+        .res()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+
+    let mut cancel_subscriber = zenoh_session
+        .declare_subscriber(CANCEL_TOPIC)
+        .res()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+
+    let mut gains_subscriber = zenoh_session
+        .declare_subscriber(GAINS_TOPIC)
+        .res()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+
+    tokio::spawn(async move {
+        while let Err(err) = run_goal_listener(
+            &mut goal_subscriber,
+            &mut cancel_subscriber,
+            &mut gains_subscriber,
+            &navigation,
+        )
+        .await
+        {
+            error!("Navigation goal listener failed with {:?}", err);
+        }
+    });
+
+    Ok(())
+}
+
+async fn publish_state(zenoh_session: &Session, state: &NavigationState) -> Result<()> {
+    let message = serde_json::to_string(state)?;
+    zenoh_session
+        .put(STATE_TOPIC, message)
+        .res_async()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+    Ok(())
+}
+
+async fn publish_speed_scale(zenoh_session: &Session, scale: f32) -> Result<()> {
+    let message = serde_json::to_string(&scale)?;
+    zenoh_session
+        .put(SPEED_SCALE_TOPIC, message)
+        .congestion_control(CongestionControl::Drop)
+        .res_async()
+        .await
+        .map_err(ErrorWrapper::ZenohError)?;
+    Ok(())
+}
+
+async fn run_goal_listener(
+    goal_subscriber: &mut FlumeSubscriber<'_>,
+    cancel_subscriber: &mut FlumeSubscriber<'_>,
+    gains_subscriber: &mut FlumeSubscriber<'_>,
+    navigation: &SharedNavigationController,
+) -> Result<()> {
+    loop {
+        tokio::select! {
+            sample = goal_subscriber.recv_async() => {
+                let message: String = sample?.value.try_into()?;
+                match serde_json::from_str::<PoseMessage>(&message) {
+                    Ok(goal) => {
+                        let goal = Pose2d::from(goal);
+                        info!("New navigation goal {}", goal);
+                        navigation.lock().await.set_target(goal);
+                    }
+                    Err(err) => warn!("Failed to parse navigation goal {:?}", err),
+                }
+            }
+            sample = cancel_subscriber.recv_async() => {
+                sample?;
+                info!("Navigation goal cancelled");
+                navigation.lock().await.clear_target();
+            }
+            sample = gains_subscriber.recv_async() => {
+                let message: String = sample?.value.try_into()?;
+                match serde_json::from_str::<PoseControllerConfig>(&message) {
+                    Ok(config) => {
+                        info!("New pose controller gains {:?}", config);
+                        navigation.lock().await.set_pose_controller_config(config);
+                    }
+                    Err(err) => warn!("Failed to parse pose controller gains {:?}", err),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// Everything sent to a `RecordingDriver`
+    #[derive(Clone, Default)]
+    pub(crate) struct Recording {
+        pub(crate) commands: Arc<std::sync::Mutex<Vec<HolonomicWheelCommand>>>,
+        pub(crate) colors: Arc<std::sync::Mutex<Vec<LedColor>>>,
+    }
+
+    struct RecordingDriver {
+        commands: Arc<std::sync::Mutex<Vec<HolonomicWheelCommand>>>,
+        colors: Arc<std::sync::Mutex<Vec<LedColor>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HamiltonDriver for RecordingDriver {
+        async fn send(&mut self, command: HolonomicWheelCommand) -> Result<()> {
+            self.commands.lock().unwrap().push(command);
+            Ok(())
+        }
+
+        async fn read_voltage(&mut self) -> Result<Option<f32>> {
+            Ok(None)
+        }
+
+        async fn set_color(&mut self, color: LedColor) -> Result<Option<()>> {
+            self.colors.lock().unwrap().push(color);
+            Ok(None)
+        }
+
+        fn set_halt_mode(&mut self, _on: bool) {}
+
+        fn halt_mode(&self) -> bool {
+            false
+        }
+    }
+
+    pub(crate) fn recording_controller() -> (NavigationController, Recording) {
+        recording_controller_with(CollisionDetector::default())
+    }
+
+    pub(crate) fn recording_controller_with(
+        collision_detector: CollisionDetector,
+    ) -> (NavigationController, Recording) {
+        let recording = Recording::default();
+        let driver = RecordingDriver {
+            commands: recording.commands.clone(),
+            colors: recording.colors.clone(),
+        };
+        let mut controller = NavigationController::new(
+            Box::new(driver),
+            collision_detector,
+            NavigationConfig::default(),
+        );
+        // leave user control
+        controller.set_user_command(
+            MoveCommand::default(),
+            Instant::now() - USER_COMMAND_TIMEOUT,
+        );
+        (controller, recording)
+    }
+
+    /// Holonomic base with first order motor response
+    pub(crate) struct SimulatedBase {
+        pub(crate) pose: Pose2d,
+        pub(crate) velocity: MoveCommand,
+        /// Speed in m/s and rad/s at full command
+        pub(crate) full_speed: f32,
+        /// Commands below this don't overcome motor friction
+        pub(crate) motor_deadband: f32,
+    }
+
+    impl SimulatedBase {
+        pub(crate) fn new(pose: Pose2d) -> Self {
+            Self {
+                pose,
+                velocity: MoveCommand::default(),
+                full_speed: 1.0,
+                motor_deadband: 0.0,
+            }
+        }
+
+        pub(crate) fn step(&mut self, command: &MoveCommand, dt: Duration) {
+            let dt = dt.as_secs_f32();
+            let deadband = |value: f32| {
+                if value.abs() < self.motor_deadband {
+                    0.0
+                } else {
+                    value
+                }
+            };
+            let command = MoveCommand::new(
+                deadband(command.forward()),
+                deadband(command.strafe()),
+                deadband(command.yaw()),
+            );
+            // motors reach commanded speed with a 100ms time constant
+            let response = (dt / 0.1).min(1.0);
+            self.velocity = MoveCommand::new(
+                self.velocity.forward() + (command.forward() - self.velocity.forward()) * response,
+                self.velocity.strafe() + (command.strafe() - self.velocity.strafe()) * response,
+                self.velocity.yaw() + (command.yaw() - self.velocity.yaw()) * response,
+            );
+            let translation = self.pose.rotation()
+                * na::Vector2::new(self.velocity.forward(), self.velocity.strafe())
+                * self.full_speed
+                * dt;
+            self.pose = Pose2d::new(
+                (
+                    self.pose.position().x + translation.x,
+                    self.pose.position().y + translation.y,
+                ),
+                self.pose.rotation().angle() + self.velocity.yaw() * self.full_speed * dt,
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn drives_towards_target() {
+        let (mut controller, recording) = recording_controller();
+        controller.set_target(Pose2d::new((1.0, 0.0), 0.0));
+        controller
+            .tick(Some(&Pose2d::new((0.0, 0.0), 0.0)))
+            .await
+            .unwrap();
+        let command = recording.commands.lock().unwrap().pop().unwrap();
+        assert!(command.left_front() > 0.0);
+        assert!(command.right_front() > 0.0);
+        assert!(controller.target().is_some());
+    }
+
+    #[tokio::test]
+    async fn arrives_within_tolerance() {
+        let (mut controller, recording) = recording_controller();
+        controller.set_target(Pose2d::new((1.0, 0.0), 0.0));
+        controller
+            .tick(Some(&Pose2d::new((0.98, 0.01), 2_f32.to_radians())))
+            .await
+            .unwrap();
+        assert!(controller.target().is_none());
+        assert!(controller.has_arrived());
+        let command = recording.commands.lock().unwrap().pop().unwrap();
+        assert_eq!(command.left_front(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn stops_without_pose() {
+        let (mut controller, recording) = recording_controller();
+        controller.set_target(Pose2d::new((1.0, 0.0), 0.0));
+        controller.tick(None).await.unwrap();
+        let command = recording.commands.lock().unwrap().pop().unwrap();
+        assert_eq!(command.left_front(), 0.0);
+        assert!(controller.target().is_some());
+        assert!(matches!(
+            controller.state(),
+            NavigationState::Navigating { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn user_command_preempts_target() {
+        let (mut controller, _recording) = recording_controller();
+        controller.set_target(Pose2d::new((1.0, 0.0), 0.0));
+        controller.issue_user_command(MoveCommand::new(0.5, 0.0, 0.0));
+        controller
+            .tick(Some(&Pose2d::new((0.0, 0.0), 0.0)))
+            .await
+            .unwrap();
+        assert!(controller.target().is_none());
+        assert_eq!(controller.state(), NavigationState::UserControl);
+
+        // user control ends once commands time out
+        controller.set_user_command(
+            MoveCommand::default(),
+            Instant::now() - USER_COMMAND_TIMEOUT,
+        );
+        controller.tick(None).await.unwrap();
+        assert_eq!(controller.state(), NavigationState::Idle);
+    }
+
+    /// Halves translation
+    struct HalfSpeedChecker;
+
+    impl crate::collision::CollisionChecker for HalfSpeedChecker {
+        fn check(
+            &self,
+            command: &MoveCommand,
+            _obstacles: &[na::Point2<f32>],
+        ) -> crate::collision::CollisionCheck {
+            crate::collision::CollisionCheck {
+                command: MoveCommand::new(
+                    command.forward() * 0.5,
+                    command.strafe() * 0.5,
+                    command.yaw(),
+                ),
+                reason: crate::collision::CollisionReason::SlowedDown { scale: 0.5 },
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn user_commands_pass_through_collision_checker() {
+        let detector = CollisionDetector::new(None, Box::new(HalfSpeedChecker));
+        let (mut controller, recording) = recording_controller_with(detector);
+        let speed_scale = controller.subscribe_speed_scale();
+        controller.issue_user_command(MoveCommand::new(0.8, 0.0, 0.0));
+        controller.tick(None).await.unwrap();
+
+        let sent = recording
+            .commands
+            .lock()
+            .unwrap()
+            .last()
+            .unwrap()
+            .left_front();
+        let expected = HolonomicWheelCommand::from_move_command(&MoveCommand::new(0.4, 0.0, 0.0));
+        assert_relative_eq!(sent, expected.left_front());
+        assert_eq!(*speed_scale.borrow(), 0.5);
+    }
+
+    #[tokio::test]
+    async fn follows_planned_path() {
+        let (mut controller, _recording) = recording_controller();
+        let map = crate::map::Map::new(na::Vector2::new(0.0, 4.0), na::Vector2::new(4.0, 0.0))
+            .with_obstacles(vec![crate::map::MapObstacle::new(
+                na::Vector2::new(1.9, 0.0),
+                na::Vector2::new(2.1, 3.0),
+            )]);
+        controller.set_planner(GlobalPlanner::new(&map, PlannerConfig::default()));
+        controller.set_target(Pose2d::new((3.5, 0.5), 0.0));
+        controller
+            .tick(Some(&Pose2d::new((0.5, 0.5), 0.0)))
+            .await
+            .unwrap();
+        assert!(!controller.path().is_empty());
+        assert!(controller.path()[0].y > 3.0);
+
+        // unreachable target
+        controller.set_target(Pose2d::new((2.0, 1.0), 0.0));
+        controller
+            .tick(Some(&Pose2d::new((0.5, 0.5), 0.0)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            controller.state(),
+            NavigationState::Failed {
+                reason: FailureReason::NoPath,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn fails_when_stuck() {
+        let (mut controller, _recording) = recording_controller();
+        controller.config.stuck_timeout_s = 0.0;
+        let mut state = controller.subscribe_state();
+        controller.set_target(Pose2d::new((1.0, 0.0), 0.0));
+        assert!(matches!(
+            state.try_recv().unwrap(),
+            NavigationState::Navigating { .. }
+        ));
+
+        let pose = Pose2d::new((0.0, 0.0), 0.0);
+        controller.tick(Some(&pose)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        controller.tick(Some(&pose)).await.unwrap();
+        assert!(controller.target().is_none());
+        assert!(matches!(
+            state.try_recv().unwrap(),
+            NavigationState::Failed {
+                reason: FailureReason::Stuck,
+                ..
+            }
+        ));
+        assert!(state.try_recv().is_err());
+        assert!(!controller.has_arrived());
+    }
+
+    #[test]
+    fn test_creation_with_into() {
+        let _pose = Pose2d::new((10.0, 10.0), 10.0);
+    }
+
+    /// these tests are kind of testing nalgebra.
+    /// I just used them to check that this behaves the way I expect
+    #[test]
+    fn rotation_angle_to() {
+        let pose = na::Rotation2::new(0_f32.to_radians());
+        let target = na::Rotation2::new(90_f32.to_radians());
+        let angle_to = pose.angle_to(&target);
+        assert_relative_eq!(angle_to, 90_f32.to_radians());
+    }
+
+    #[test]
+    fn rotation_angle_to_inverted() {
+        let pose = na::Rotation2::new(0_f32.to_radians());
+        let target = na::Rotation2::new(-90_f32.to_radians());
+        let angle_to = pose.angle_to(&target);
+        assert_relative_eq!(angle_to, -90_f32.to_radians());
+    }
+
+    #[test]
+    fn rotation_angle_to_wrap() {
+        let pose = na::Rotation2::new(-170_f32.to_radians());
+        let target = na::Rotation2::new(170_f32.to_radians());
+        let angle_to = pose.angle_to(&target);
+        assert_relative_eq!(angle_to, -20_f32.to_radians(), max_relative = 0.00001);
+    }
+}
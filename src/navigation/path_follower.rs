@@ -0,0 +1,363 @@
+use nalgebra as na;
+use serde::Deserialize;
+use std::time::Duration;
+
+use super::Pose2d;
+use crate::holonomic_controller::{MotionLimits, MoveCommand};
+
+fn default_lookahead() -> f32 {
+    0.4
+}
+
+fn default_cruise_speed() -> f32 {
+    0.5
+}
+
+fn default_min_speed() -> f32 {
+    0.05
+}
+
+fn default_goal_slowdown_distance() -> f32 {
+    0.6
+}
+
+fn default_curvature_gain() -> f32 {
+    0.8
+}
+
+fn default_heading_gain() -> f32 {
+    1.5
+}
+
+fn default_handover_distance() -> f32 {
+    0.25
+}
+
+fn default_max_acceleration() -> f32 {
+    1.0
+}
+
+fn default_max_yaw_acceleration() -> f32 {
+    2.0
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PathFollowerConfig {
+    /// Distance along the path to the point being tracked in meters
+    #[serde(default = "default_lookahead")]
+    pub lookahead: f32,
+    /// Translation speed on straight segments
+    #[serde(default = "default_cruise_speed")]
+    pub cruise_speed: f32,
+    /// Speed below which the follower won't slow down
+    #[serde(default = "default_min_speed")]
+    pub min_speed: f32,
+    /// Distance from the goal in meters at which the robot starts slowing down
+    #[serde(default = "default_goal_slowdown_distance")]
+    pub goal_slowdown_distance: f32,
+    /// How much to slow down per radian of turn within the lookahead
+    #[serde(default = "default_curvature_gain")]
+    pub curvature_gain: f32,
+    #[serde(default = "default_heading_gain")]
+    pub heading_gain: f32,
+    /// Remaining distance at which the pose controller takes over for the final approach
+    #[serde(default = "default_handover_distance")]
+    pub handover_distance: f32,
+    /// Largest change in translation command per second
+    #[serde(default = "default_max_acceleration")]
+    pub max_acceleration: f32,
+    /// Largest change in yaw command per second
+    #[serde(default = "default_max_yaw_acceleration")]
+    pub max_yaw_acceleration: f32,
+}
+
+impl Default for PathFollowerConfig {
+    fn default() -> Self {
+        Self {
+            lookahead: default_lookahead(),
+            cruise_speed: default_cruise_speed(),
+            min_speed: default_min_speed(),
+            goal_slowdown_distance: default_goal_slowdown_distance(),
+            curvature_gain: default_curvature_gain(),
+            heading_gain: default_heading_gain(),
+            handover_distance: default_handover_distance(),
+            max_acceleration: default_max_acceleration(),
+            max_yaw_acceleration: default_max_yaw_acceleration(),
+        }
+    }
+}
+
+/// Limits how quickly commands can change
+#[derive(Debug, Clone, Default)]
+pub struct AccelerationLimiter {
+    last: MoveCommand,
+}
+
+impl AccelerationLimiter {
+    pub fn limit(
+        &mut self,
+        command: &MoveCommand,
+        max_acceleration: f32,
+        max_yaw_acceleration: f32,
+        dt: Duration,
+    ) -> MoveCommand {
+        let dt = dt.as_secs_f32();
+        let step = |last: f32, next: f32, max: f32| {
+            let max_change = max * dt;
+            last + (next - last).clamp(-max_change, max_change)
+        };
+        self.last = MoveCommand::new(
+            step(self.last.forward(), command.forward(), max_acceleration),
+            step(self.last.strafe(), command.strafe(), max_acceleration),
+            step(self.last.yaw(), command.yaw(), max_yaw_acceleration),
+        );
+        self.last
+    }
+
+    /// Last command that was let through
+    pub fn last(&self) -> MoveCommand {
+        self.last
+    }
+
+    /// Forget the last command after the robot was stopped or driven by something else
+    pub fn reset(&mut self) {
+        self.last = MoveCommand::default();
+    }
+}
+
+/// Holonomic pure pursuit
+///
+/// Translation chases a point `lookahead` meters along the path
+/// while heading is controlled independently.
+pub struct PathFollower {
+    config: PathFollowerConfig,
+    limits: MotionLimits,
+}
+
+impl PathFollower {
+    pub fn new(config: PathFollowerConfig, limits: MotionLimits) -> Self {
+        Self { config, limits }
+    }
+
+    pub fn config(&self) -> &PathFollowerConfig {
+        &self.config
+    }
+
+    /// Command following `path` from the current pose
+    ///
+    /// The path starts at the next waypoint. Heading tracks the heading of the waypoint
+    /// at the end of the segment being followed.
+    pub fn update(&self, current: &Pose2d, path: &[Pose2d]) -> MoveCommand {
+        if path.is_empty() {
+            return MoveCommand::default();
+        }
+        let position = current.position();
+        let points: Vec<_> = path.iter().map(|pose| *pose.position()).collect();
+        let (segment, closest) = closest_point(&points, position);
+        // remaining path starting at the closest point
+        let ahead: Vec<_> = std::iter::once(closest)
+            .chain(points[segment + 1..].iter().copied())
+            .collect();
+
+        let carrot = carrot(&ahead, position, self.config.lookahead);
+        let remaining = na::distance(position, &closest) + polyline_length(&ahead);
+        let goal_factor =
+            (remaining / self.config.goal_slowdown_distance.max(f32::EPSILON)).min(1.0);
+        let turn = if segment == 0 && closest == points[0] {
+            // still heading to the first waypoint
+            let approach: Vec<_> = std::iter::once(*position)
+                .chain(ahead.iter().copied())
+                .collect();
+            turn_within(&approach, self.config.lookahead * 2.0)
+        } else {
+            turn_within(&ahead, self.config.lookahead * 2.0)
+        };
+        let curvature_factor = 1.0 / (1.0 + self.config.curvature_gain * turn);
+        let speed = (self.config.cruise_speed * goal_factor * curvature_factor)
+            .max(self.config.min_speed.min(self.config.cruise_speed));
+
+        let direction = carrot - position;
+        let translation = if direction.norm() > f32::EPSILON {
+            current.rotation().inverse() * direction.normalize() * speed
+        } else {
+            na::Vector2::zeros()
+        };
+        let heading = path[(segment + 1).min(path.len() - 1)].rotation();
+        let yaw = self.config.heading_gain * current.rotation().angle_to(heading);
+
+        self.limits
+            .saturate(&MoveCommand::new(translation.x, translation.y, yaw))
+    }
+}
+
+fn polyline_length(points: &[na::Point2<f32>]) -> f32 {
+    points
+        .windows(2)
+        .map(|leg| na::distance(&leg[0], &leg[1]))
+        .sum()
+}
+
+/// Index of the segment closest to a point and the closest point on it
+///
+/// Single point polylines return that point.
+fn closest_point(
+    points: &[na::Point2<f32>],
+    position: &na::Point2<f32>,
+) -> (usize, na::Point2<f32>) {
+    let mut best = (0, points[0]);
+    let mut best_distance = na::distance(position, &points[0]);
+    for (index, leg) in points.windows(2).enumerate() {
+        let direction = leg[1] - leg[0];
+        let length_squared = direction.norm_squared();
+        let t = if length_squared > 0.0 {
+            ((position - leg[0]).dot(&direction) / length_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let point = leg[0] + direction * t;
+        let distance = na::distance(position, &point);
+        if distance < best_distance {
+            best = (index, point);
+            best_distance = distance;
+        }
+    }
+    best
+}
+
+/// First point along the polyline that is `lookahead` away from the position
+///
+/// Falls back to the start when it's already further away and to the end when
+/// the whole polyline is within the lookahead.
+fn carrot(
+    points: &[na::Point2<f32>],
+    position: &na::Point2<f32>,
+    lookahead: f32,
+) -> na::Point2<f32> {
+    if na::distance(position, &points[0]) >= lookahead {
+        return points[0];
+    }
+    for leg in points.windows(2) {
+        if na::distance(position, &leg[1]) < lookahead {
+            continue;
+        }
+        // solve |leg[0] + t * direction - position| = lookahead for the exit point
+        let direction = leg[1] - leg[0];
+        let offset = leg[0] - position;
+        let a = direction.norm_squared();
+        let b = 2.0 * offset.dot(&direction);
+        let c = offset.norm_squared() - lookahead * lookahead;
+        let t = (-b + (b * b - 4.0 * a * c).max(0.0).sqrt()) / (2.0 * a);
+        return leg[0] + direction * t.clamp(0.0, 1.0);
+    }
+    *points.last().expect("Polyline can't be empty")
+}
+
+/// Total absolute turn in radians at vertices within `distance` along a polyline
+fn turn_within(points: &[na::Point2<f32>], distance: f32) -> f32 {
+    let mut travelled = 0.0;
+    let mut turn = 0.0;
+    for window in points.windows(3) {
+        travelled += na::distance(&window[0], &window[1]);
+        if travelled > distance {
+            break;
+        }
+        let incoming = window[1] - window[0];
+        let outgoing = window[2] - window[1];
+        if incoming.norm() > f32::EPSILON && outgoing.norm() > f32::EPSILON {
+            turn += incoming.angle(&outgoing);
+        }
+    }
+    turn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::SimulatedBase;
+    use super::*;
+
+    const DT: Duration = Duration::from_millis(50);
+
+    fn distance_to_segment(
+        point: &na::Point2<f32>,
+        a: &na::Point2<f32>,
+        b: &na::Point2<f32>,
+    ) -> f32 {
+        let ab = b - a;
+        let t = ((point - a).dot(&ab) / ab.norm_squared()).clamp(0.0, 1.0);
+        na::distance(point, &(a + ab * t))
+    }
+
+    #[test]
+    fn tracks_corner_path() {
+        let follower = PathFollower::new(PathFollowerConfig::default(), MotionLimits::default());
+        let mut limiter = AccelerationLimiter::default();
+        let mut base = SimulatedBase::new(Pose2d::new((0.0, 0.0), 0.0));
+        let mut path = vec![Pose2d::new((2.0, 0.0), 0.0), Pose2d::new((2.0, 2.0), 0.0)];
+        let polyline = [
+            na::Point2::new(0.0, 0.0),
+            na::Point2::new(2.0, 0.0),
+            na::Point2::new(2.0, 2.0),
+        ];
+
+        let mut max_deviation: f32 = 0.0;
+        for _ in 0..400 {
+            // drop passed waypoints like the navigation controller does
+            if path.len() > 1 && na::distance(base.pose.position(), path[0].position()) < 0.15 {
+                path.remove(0);
+            }
+            let command = follower.update(&base.pose, &path);
+            let command = limiter.limit(&command, 1.0, 2.0, DT);
+            base.step(&command, DT);
+            let deviation = polyline
+                .windows(2)
+                .map(|leg| distance_to_segment(base.pose.position(), &leg[0], &leg[1]))
+                .fold(f32::INFINITY, f32::min);
+            max_deviation = max_deviation.max(deviation);
+        }
+        assert!(max_deviation < 0.2, "deviation {}", max_deviation);
+        assert!(
+            na::distance(base.pose.position(), &na::Point2::new(2.0, 2.0)) < 0.1,
+            "{}",
+            base.pose
+        );
+        assert!(base.pose.rotation().angle().abs() < 2_f32.to_radians());
+    }
+
+    #[test]
+    fn slows_down_for_corners_and_goal() {
+        let follower = PathFollower::new(PathFollowerConfig::default(), MotionLimits::default());
+        let current = Pose2d::new((0.0, 0.0), 0.0);
+        let speed = |path: &[Pose2d]| {
+            let command = follower.update(&current, path);
+            command.forward().hypot(command.strafe())
+        };
+        let straight = speed(&[Pose2d::new((5.0, 0.0), 0.0)]);
+        let corner = speed(&[Pose2d::new((0.3, 0.0), 0.0), Pose2d::new((0.3, 5.0), 0.0)]);
+        let goal = speed(&[Pose2d::new((0.2, 0.0), 0.0)]);
+        assert!((straight - 0.5).abs() < 0.001);
+        assert!(corner < straight * 0.7);
+        assert!(goal < straight * 0.5);
+    }
+
+    #[test]
+    fn heading_is_independent_of_direction() {
+        let follower = PathFollower::new(PathFollowerConfig::default(), MotionLimits::default());
+        // strafe left while keeping the heading
+        let command = follower.update(
+            &Pose2d::new((0.0, 0.0), 0.0),
+            &[Pose2d::new((0.0, 5.0), 0.0)],
+        );
+        assert!(command.forward().abs() < 0.001);
+        assert!(command.strafe() > 0.0);
+        assert_eq!(command.yaw(), 0.0);
+    }
+
+    #[test]
+    fn acceleration_is_limited() {
+        let mut limiter = AccelerationLimiter::default();
+        let command = limiter.limit(&MoveCommand::new(0.5, -0.5, 1.0), 1.0, 2.0, DT);
+        assert!((command.forward() - 0.05).abs() < 0.001);
+        assert!((command.strafe() + 0.05).abs() < 0.001);
+        assert!((command.yaw() - 0.1).abs() < 0.001);
+    }
+}
@@ -0,0 +1,517 @@
+use anyhow::{anyhow, Result};
+use nalgebra as na;
+use serde::Deserialize;
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crate::map::Map;
+
+fn default_resolution() -> f32 {
+    0.05
+}
+
+fn default_robot_radius() -> f32 {
+    0.25
+}
+
+fn default_replan_interval_ms() -> u64 {
+    1000
+}
+
+fn default_waypoint_tolerance() -> f32 {
+    0.15
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PlannerConfig {
+    /// Map planned on
+    ///
+    /// Targets are driven to in a straight line when not set
+    #[serde(default)]
+    pub map: Option<PathBuf>,
+    /// Size of a grid cell in meters
+    #[serde(default = "default_resolution")]
+    pub resolution: f32,
+    /// Obstacles are inflated by this radius so the robot can be planned as a point
+    #[serde(default = "default_robot_radius")]
+    pub robot_radius: f32,
+    /// How often the path is checked against new obstacles
+    #[serde(default = "default_replan_interval_ms")]
+    pub replan_interval_ms: u64,
+    /// Distance at which an intermediate waypoint counts as passed
+    #[serde(default = "default_waypoint_tolerance")]
+    pub waypoint_tolerance: f32,
+}
+
+impl Default for PlannerConfig {
+    fn default() -> Self {
+        Self {
+            map: None,
+            resolution: default_resolution(),
+            robot_radius: default_robot_radius(),
+            replan_interval_ms: default_replan_interval_ms(),
+            waypoint_tolerance: default_waypoint_tolerance(),
+        }
+    }
+}
+
+type Cell = (usize, usize);
+
+#[derive(Debug, Clone)]
+pub struct OccupancyGrid {
+    origin: na::Point2<f32>,
+    resolution: f32,
+    width: usize,
+    height: usize,
+    occupied: Vec<bool>,
+}
+
+impl OccupancyGrid {
+    /// Empty grid covering the area between two corners
+    pub fn new(min: na::Point2<f32>, max: na::Point2<f32>, resolution: f32) -> Self {
+        let width = ((max.x - min.x) / resolution).ceil().max(1.0) as usize;
+        let height = ((max.y - min.y) / resolution).ceil().max(1.0) as usize;
+        Self {
+            origin: min,
+            resolution,
+            width,
+            height,
+            occupied: vec![false; width * height],
+        }
+    }
+
+    pub fn from_map(map: &Map, resolution: f32) -> Self {
+        let (min, max) = map.bounds();
+        let mut grid = Self::new(min, max, resolution);
+        for y in 0..grid.height {
+            for x in 0..grid.width {
+                let center = grid.cell_center((x, y));
+                if map
+                    .obstacles()
+                    .iter()
+                    .any(|obstacle| obstacle.contains(&center))
+                {
+                    grid.occupied[y * grid.width + x] = true;
+                }
+            }
+        }
+        grid
+    }
+
+    pub fn cell(&self, point: &na::Point2<f32>) -> Option<Cell> {
+        let x = ((point.x - self.origin.x) / self.resolution).floor();
+        let y = ((point.y - self.origin.y) / self.resolution).floor();
+        if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+            return None;
+        }
+        Some((x as usize, y as usize))
+    }
+
+    pub fn cell_center(&self, (x, y): Cell) -> na::Point2<f32> {
+        na::Point2::new(
+            self.origin.x + (x as f32 + 0.5) * self.resolution,
+            self.origin.y + (y as f32 + 0.5) * self.resolution,
+        )
+    }
+
+    /// Points outside of the grid count as occupied
+    pub fn is_occupied(&self, point: &na::Point2<f32>) -> bool {
+        self.cell(point)
+            .map(|cell| self.is_cell_occupied(cell))
+            .unwrap_or(true)
+    }
+
+    fn is_cell_occupied(&self, (x, y): Cell) -> bool {
+        self.occupied[y * self.width + x]
+    }
+
+    /// Mark everything within `radius` of a point as occupied
+    pub fn mark(&mut self, point: &na::Point2<f32>, radius: f32) {
+        let cells = (radius / self.resolution).ceil() as isize;
+        let Some((center_x, center_y)) = self.cell(point) else {
+            return;
+        };
+        for dy in -cells..=cells {
+            for dx in -cells..=cells {
+                let x = center_x as isize + dx;
+                let y = center_y as isize + dy;
+                if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+                    continue;
+                }
+                let cell = (x as usize, y as usize);
+                // cells are considered occupied if their center is in range
+                if na::distance(&self.cell_center(cell), point) <= radius + self.resolution / 2.0 {
+                    self.occupied[cell.1 * self.width + cell.0] = true;
+                }
+            }
+        }
+    }
+
+    /// Grow every obstacle by `radius`
+    pub fn inflate(&self, radius: f32) -> Self {
+        let mut inflated = self.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.is_cell_occupied((x, y)) {
+                    inflated.mark(&self.cell_center((x, y)), radius);
+                }
+            }
+        }
+        inflated
+    }
+
+    /// Whether a straight line between two points only crosses free cells
+    pub fn line_of_sight(&self, from: &na::Point2<f32>, to: &na::Point2<f32>) -> bool {
+        let distance = na::distance(from, to);
+        // sample at half the resolution so corners aren't cut
+        let steps = (distance / (self.resolution / 2.0)).ceil().max(1.0) as usize;
+        (0..=steps).all(|step| {
+            let point = from + (to - from) * (step as f32 / steps as f32);
+            !self.is_occupied(&point)
+        })
+    }
+
+    fn neighbours(&self, (x, y): Cell) -> impl Iterator<Item = Cell> + '_ {
+        (-1_isize..=1)
+            .flat_map(|dy| (-1_isize..=1).map(move |dx| (dx, dy)))
+            .filter(|&(dx, dy)| dx != 0 || dy != 0)
+            .filter_map(move |(dx, dy)| {
+                let x = x as isize + dx;
+                let y = y as isize + dy;
+                if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+                    None
+                } else {
+                    Some((x as usize, y as usize))
+                }
+            })
+            .filter(|cell| !self.is_cell_occupied(*cell))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OpenCell {
+    cost: f32,
+    index: usize,
+}
+
+impl Eq for OpenCell {}
+
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed to make the heap a min heap
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Any angle path between two points using Theta*
+///
+/// The returned path starts at `start` and ends at `goal`.
+/// The start cell is allowed to be occupied so that the robot can plan its way out
+/// of an inflated obstacle it ended up in.
+pub fn plan_path(
+    grid: &OccupancyGrid,
+    start: &na::Point2<f32>,
+    goal: &na::Point2<f32>,
+) -> Result<Vec<na::Point2<f32>>> {
+    let start_cell = grid
+        .cell(start)
+        .ok_or_else(|| anyhow!("Start is outside of the map"))?;
+    let goal_cell = grid
+        .cell(goal)
+        .ok_or_else(|| anyhow!("Goal is outside of the map"))?;
+    if grid.is_cell_occupied(goal_cell) {
+        return Err(anyhow!("Goal is occupied"));
+    }
+
+    let index = |(x, y): Cell| y * grid.width + x;
+    let cell = |index: usize| (index % grid.width, index / grid.width);
+    let index_of_start = index(start_cell);
+    let index_of_goal = index(goal_cell);
+    // plan between cell centers but connect the real start point
+    let position = |index: usize| {
+        if index == index_of_start {
+            *start
+        } else {
+            grid.cell_center(cell(index))
+        }
+    };
+
+    let mut cost = vec![f32::INFINITY; grid.occupied.len()];
+    let mut parent: Vec<usize> = (0..grid.occupied.len()).collect();
+    let mut closed = vec![false; grid.occupied.len()];
+    let mut open = BinaryHeap::new();
+
+    cost[index_of_start] = 0.0;
+    open.push(OpenCell {
+        cost: na::distance(start, goal),
+        index: index_of_start,
+    });
+
+    while let Some(OpenCell { index: current, .. }) = open.pop() {
+        if current == index_of_goal {
+            let mut path = vec![*goal];
+            let mut node = parent[current];
+            while node != index_of_start {
+                path.push(position(node));
+                node = parent[node];
+            }
+            path.push(*start);
+            path.reverse();
+            return Ok(path);
+        }
+        if closed[current] {
+            continue;
+        }
+        closed[current] = true;
+
+        for neighbour in grid.neighbours(cell(current)).map(index) {
+            if closed[neighbour] {
+                continue;
+            }
+            let neighbour_position = position(neighbour);
+            // connect to the grandparent directly when possible
+            let grandparent = parent[current];
+            let from = if grandparent != current
+                && grid.line_of_sight(&position(grandparent), &neighbour_position)
+            {
+                grandparent
+            } else {
+                current
+            };
+            let new_cost = cost[from] + na::distance(&position(from), &neighbour_position);
+            if new_cost < cost[neighbour] {
+                cost[neighbour] = new_cost;
+                parent[neighbour] = from;
+                open.push(OpenCell {
+                    cost: new_cost + na::distance(&neighbour_position, goal),
+                    index: neighbour,
+                });
+            }
+        }
+    }
+    Err(anyhow!("No path to goal"))
+}
+
+/// Remove waypoints that can be skipped in a straight line
+pub fn smooth_path(grid: &OccupancyGrid, path: &[na::Point2<f32>]) -> Vec<na::Point2<f32>> {
+    let Some(first) = path.first() else {
+        return vec![];
+    };
+    let mut smoothed = vec![*first];
+    let mut anchor = 0;
+    while anchor < path.len() - 1 {
+        let next = (anchor + 1..path.len())
+            .rev()
+            .find(|&candidate| grid.line_of_sight(&path[anchor], &path[candidate]))
+            .unwrap_or(anchor + 1);
+        smoothed.push(path[next]);
+        anchor = next;
+    }
+    smoothed
+}
+
+/// Plans paths around map obstacles and the latest lidar obstacles
+pub struct GlobalPlanner {
+    config: PlannerConfig,
+    /// Map obstacles already inflated
+    static_grid: OccupancyGrid,
+    last_check: Option<Instant>,
+}
+
+impl GlobalPlanner {
+    pub fn new(map: &Map, config: PlannerConfig) -> Self {
+        let static_grid =
+            OccupancyGrid::from_map(map, config.resolution).inflate(config.robot_radius);
+        Self {
+            config,
+            static_grid,
+            last_check: None,
+        }
+    }
+
+    /// Load the map configured in `config`
+    pub fn from_config(config: PlannerConfig) -> Result<Option<Self>> {
+        let Some(path) = &config.map else {
+            return Ok(None);
+        };
+        let map = Map::load_json(
+            path.to_str()
+                .ok_or_else(|| anyhow!("Failed to convert path"))?,
+        )?;
+        Ok(Some(Self::new(&map, config)))
+    }
+
+    pub fn config(&self) -> &PlannerConfig {
+        &self.config
+    }
+
+    /// Static grid with lidar obstacles inflated
+    ///
+    /// Obstacles close to the start and goal are left out. Otherwise a wall or dock
+    /// next to the goal or a return from the robot itself would make them unreachable.
+    fn grid_with_obstacles(
+        &self,
+        obstacles: &[na::Point2<f32>],
+        start: &na::Point2<f32>,
+        goal: &na::Point2<f32>,
+    ) -> OccupancyGrid {
+        let keep_clear = self.config.robot_radius + self.config.resolution;
+        let mut grid = self.static_grid.clone();
+        for obstacle in obstacles.iter().filter(|obstacle| {
+            na::distance(obstacle, start) > keep_clear && na::distance(obstacle, goal) > keep_clear
+        }) {
+            grid.mark(obstacle, self.config.robot_radius);
+        }
+        grid
+    }
+
+    /// Smoothed path from start to goal
+    pub fn plan(
+        &mut self,
+        start: &na::Point2<f32>,
+        goal: &na::Point2<f32>,
+        obstacles: &[na::Point2<f32>],
+    ) -> Result<Vec<na::Point2<f32>>> {
+        let grid = self.grid_with_obstacles(obstacles, start, goal);
+        let path = plan_path(&grid, start, goal)?;
+        self.last_check = Some(Instant::now());
+        Ok(smooth_path(&grid, &path))
+    }
+
+    /// Whether a replan interval passed since the last check
+    pub fn replan_check_due(&self, now: Instant) -> bool {
+        let interval = Duration::from_millis(self.config.replan_interval_ms);
+        self.last_check
+            .is_none_or(|last_check| now.duration_since(last_check) >= interval)
+    }
+
+    /// Whether the remaining path should be replanned because of new obstacles
+    ///
+    /// Only checks once per replan interval
+    pub fn should_replan(
+        &mut self,
+        position: &na::Point2<f32>,
+        path: &[na::Point2<f32>],
+        obstacles: &[na::Point2<f32>],
+        now: Instant,
+    ) -> bool {
+        if !self.replan_check_due(now) {
+            return false;
+        }
+        self.last_check = Some(now);
+        let goal = path.last().unwrap_or(position);
+        let grid = self.grid_with_obstacles(obstacles, position, goal);
+        std::iter::once(position)
+            .chain(path.iter())
+            .zip(path.iter())
+            .any(|(from, to)| !grid.line_of_sight(from, to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::MapObstacle;
+
+    /// 4x4m room with a wall in the middle leaving a gap at the top
+    fn room() -> Map {
+        Map::new(na::Vector2::new(0.0, 4.0), na::Vector2::new(4.0, 0.0)).with_obstacles(vec![
+            MapObstacle::new(na::Vector2::new(1.9, 0.0), na::Vector2::new(2.1, 3.0)),
+        ])
+    }
+
+    fn path_length(path: &[na::Point2<f32>]) -> f32 {
+        path.windows(2)
+            .map(|leg| na::distance(&leg[0], &leg[1]))
+            .sum()
+    }
+
+    #[test]
+    fn inflation_grows_obstacles() {
+        let grid = OccupancyGrid::from_map(&room(), 0.05);
+        let point = na::Point2::new(1.7, 1.0);
+        assert!(!grid.is_occupied(&point));
+        assert!(grid.inflate(0.25).is_occupied(&point));
+    }
+
+    #[test]
+    fn straight_path_in_open_space() {
+        let mut planner = GlobalPlanner::new(&room(), PlannerConfig::default());
+        let start = na::Point2::new(0.5, 0.5);
+        let goal = na::Point2::new(1.5, 2.5);
+        let path = planner.plan(&start, &goal, &[]).unwrap();
+        assert_eq!(path, vec![start, goal]);
+    }
+
+    #[test]
+    fn plans_around_wall() {
+        let mut planner = GlobalPlanner::new(&room(), PlannerConfig::default());
+        let start = na::Point2::new(0.5, 0.5);
+        let goal = na::Point2::new(3.5, 0.5);
+        let path = planner.plan(&start, &goal, &[]).unwrap();
+
+        let grid = OccupancyGrid::from_map(&room(), 0.05).inflate(0.25);
+        for leg in path.windows(2) {
+            assert!(grid.line_of_sight(&leg[0], &leg[1]));
+        }
+        // up and over the wall and down again
+        assert!(path.iter().any(|point| point.y > 3.0));
+        assert!(path.len() <= 6, "path not smoothed {:?}", path);
+        assert!(path_length(&path) < 7.0, "path too long {:?}", path);
+    }
+
+    #[test]
+    fn fails_without_route() {
+        let mut planner = GlobalPlanner::new(&room(), PlannerConfig::default());
+        let start = na::Point2::new(0.5, 0.5);
+        let goal = na::Point2::new(3.5, 0.5);
+        // block the gap
+        let obstacles: Vec<_> = (0..10)
+            .map(|i| na::Point2::new(2.0, 3.0 + i as f32 * 0.1))
+            .collect();
+        assert!(planner.plan(&start, &goal, &obstacles).is_err());
+        assert!(planner
+            .plan(&start, &na::Point2::new(2.0, 1.0), &[])
+            .is_err());
+    }
+
+    #[test]
+    fn replans_when_blocked() {
+        let mut planner = GlobalPlanner::new(&room(), PlannerConfig::default());
+        let start = na::Point2::new(0.5, 0.5);
+        let goal = na::Point2::new(1.5, 2.5);
+        let path = planner.plan(&start, &goal, &[]).unwrap();
+        let now = Instant::now() + Duration::from_secs(10);
+
+        let obstacle = [na::Point2::new(1.0, 1.5)];
+        assert!(planner.should_replan(&start, &path[1..], &obstacle, now));
+        // rate limited
+        assert!(!planner.should_replan(&start, &path[1..], &obstacle, now));
+        assert!(!planner.should_replan(&start, &path[1..], &[], now + Duration::from_secs(2)));
+
+        let new_path = planner.plan(&start, &goal, &obstacle).unwrap();
+        assert!(new_path.len() > 2);
+    }
+
+    #[test]
+    fn obstacles_next_to_start_and_goal_are_ignored() {
+        let mut planner = GlobalPlanner::new(&room(), PlannerConfig::default());
+        let start = na::Point2::new(0.5, 0.5);
+        let goal = na::Point2::new(1.0, 2.5);
+        // a dock just behind the goal and a self hit next to the robot
+        let obstacles = [na::Point2::new(1.0, 2.7), na::Point2::new(0.6, 0.5)];
+        let path = planner.plan(&start, &goal, &obstacles).unwrap();
+        assert_eq!(path.last(), Some(&goal));
+        assert!(!planner.should_replan(&start, &path[1..], &obstacles, Instant::now()));
+    }
+}
@@ -0,0 +1,246 @@
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::{PI, TAU},
+    fmt,
+};
+
+/// Wrap an angle in radians to [-π, π)
+pub fn normalize_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+/// Shortest signed rotation in radians from one angle to another
+pub fn angle_difference(from: f32, to: f32) -> f32 {
+    normalize_angle(to - from)
+}
+
+/// Position and heading in the plane
+///
+/// Serialized as `{x, y, theta}` with theta in radians.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PoseMessage", into = "PoseMessage")]
+pub struct Pose2d {
+    position: na::Point2<f32>,
+    rotation: na::Rotation2<f32>,
+}
+
+impl Pose2d {
+    pub fn from_na(position: na::Point2<f32>, rotation: na::Rotation2<f32>) -> Self {
+        Self { position, rotation }
+    }
+
+    pub fn new((x, y): (f32, f32), rotation: f32) -> Self {
+        Self {
+            position: na::Point2::new(x, y),
+            rotation: na::Rotation2::new(rotation),
+        }
+    }
+
+    pub fn identity() -> Self {
+        Self::from_na(na::Point2::origin(), na::Rotation2::identity())
+    }
+
+    pub fn position(&self) -> &na::Point2<f32> {
+        &self.position
+    }
+
+    pub fn rotation(&self) -> &na::Rotation2<f32> {
+        &self.rotation
+    }
+
+    pub fn x(&self) -> f32 {
+        self.position.x
+    }
+
+    pub fn y(&self) -> f32 {
+        self.position.y
+    }
+
+    /// Heading in radians
+    pub fn theta(&self) -> f32 {
+        self.rotation.angle()
+    }
+
+    /// Pose given relative to this one expressed in the frame this pose is in
+    pub fn compose(&self, other: &Pose2d) -> Pose2d {
+        Pose2d::from_na(
+            self.transform_point(&other.position),
+            self.rotation * other.rotation,
+        )
+    }
+
+    pub fn inverse(&self) -> Pose2d {
+        let rotation = self.rotation.inverse();
+        Pose2d::from_na(na::Point2::from(rotation * -self.position.coords), rotation)
+    }
+
+    /// Point from this pose's frame to the frame this pose is in
+    pub fn transform_point(&self, point: &na::Point2<f32>) -> na::Point2<f32> {
+        self.position + self.rotation * point.coords
+    }
+
+    /// Point from the frame this pose is in to this pose's frame
+    pub fn inverse_transform_point(&self, point: &na::Point2<f32>) -> na::Point2<f32> {
+        na::Point2::from(self.rotation.inverse() * (point - self.position))
+    }
+
+    /// This pose expressed in the frame of `reference`
+    pub fn relative_to(&self, reference: &Pose2d) -> Pose2d {
+        reference.inverse().compose(self)
+    }
+
+    pub fn distance(&self, other: &Pose2d) -> f32 {
+        na::distance(&self.position, &other.position)
+    }
+
+    /// Shortest signed rotation in radians to the heading of another pose
+    pub fn heading_error(&self, other: &Pose2d) -> f32 {
+        self.rotation.angle_to(&other.rotation)
+    }
+
+    /// Lerp position and slerp heading along the shorter arc
+    ///
+    /// `t` of 0 is this pose and 1 is `other`
+    pub fn interpolate(&self, other: &Pose2d, t: f32) -> Pose2d {
+        Pose2d::from_na(
+            self.position + (other.position - self.position) * t,
+            na::Rotation2::new(self.theta() + self.heading_error(other) * t),
+        )
+    }
+
+    /// Whether poses are within a distance in meters and angle in radians of each other
+    pub fn approx_eq(&self, other: &Pose2d, position_epsilon: f32, angle_epsilon: f32) -> bool {
+        self.distance(other) <= position_epsilon && self.heading_error(other).abs() <= angle_epsilon
+    }
+}
+
+impl Default for Pose2d {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+/// Pose as sent over zenoh
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct PoseMessage {
+    pub x: f32,
+    pub y: f32,
+    /// Heading in radians
+    pub theta: f32,
+}
+
+impl From<PoseMessage> for Pose2d {
+    fn from(message: PoseMessage) -> Self {
+        Pose2d::new((message.x, message.y), message.theta)
+    }
+}
+
+impl From<&Pose2d> for PoseMessage {
+    fn from(pose: &Pose2d) -> Self {
+        Self {
+            x: pose.x(),
+            y: pose.y(),
+            theta: pose.theta(),
+        }
+    }
+}
+
+impl From<Pose2d> for PoseMessage {
+    fn from(pose: Pose2d) -> Self {
+        PoseMessage::from(&pose)
+    }
+}
+
+impl fmt::Display for Pose2d {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{}, {}] -> {}",
+            self.position.x,
+            self.position.y,
+            self.rotation.angle().to_degrees()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use std::f32::consts::FRAC_PI_2;
+
+    const EPSILON: f32 = 1e-5;
+
+    #[test]
+    fn serializes_as_x_y_theta() {
+        let pose = Pose2d::new((1.0, -2.0), 0.5);
+        let json = serde_json::to_string(&pose).unwrap();
+        assert_eq!(json, r#"{"x":1.0,"y":-2.0,"theta":0.5}"#);
+        let parsed: Pose2d = serde_json::from_str(&json).unwrap();
+        assert!(parsed.approx_eq(&pose, EPSILON, EPSILON));
+
+        // message and pose are interchangeable on the wire
+        let message: PoseMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(message, PoseMessage::from(&pose));
+    }
+
+    #[test]
+    fn compose_and_inverse() {
+        let pose = Pose2d::new((1.0, 2.0), FRAC_PI_2);
+        assert!(pose
+            .compose(&pose.inverse())
+            .approx_eq(&Pose2d::identity(), EPSILON, EPSILON));
+        assert!(pose
+            .inverse()
+            .compose(&pose)
+            .approx_eq(&Pose2d::identity(), EPSILON, EPSILON));
+
+        // one meter ahead of a robot facing +y
+        let ahead = pose.compose(&Pose2d::new((1.0, 0.0), 0.0));
+        assert!(ahead.approx_eq(&Pose2d::new((1.0, 3.0), FRAC_PI_2), EPSILON, EPSILON));
+        assert!(ahead.relative_to(&pose).approx_eq(
+            &Pose2d::new((1.0, 0.0), 0.0),
+            EPSILON,
+            EPSILON
+        ));
+    }
+
+    #[test]
+    fn transforms_points() {
+        let pose = Pose2d::new((1.0, 2.0), FRAC_PI_2);
+        let world = pose.transform_point(&na::Point2::new(1.0, 0.0));
+        assert_relative_eq!(world, na::Point2::new(1.0, 3.0), epsilon = EPSILON);
+        let local = pose.inverse_transform_point(&world);
+        assert_relative_eq!(local, na::Point2::new(1.0, 0.0), epsilon = EPSILON);
+    }
+
+    #[test]
+    fn interpolates_heading_along_shorter_arc() {
+        let from = Pose2d::new((0.0, 0.0), 170_f32.to_radians());
+        let to = Pose2d::new((2.0, 0.0), -170_f32.to_radians());
+        let middle = from.interpolate(&to, 0.5);
+        assert_relative_eq!(middle.x(), 1.0, epsilon = EPSILON);
+        assert_relative_eq!(middle.theta().abs(), PI, epsilon = 1e-4);
+        assert!(from.interpolate(&to, 1.0).approx_eq(&to, EPSILON, 1e-4));
+    }
+
+    #[test]
+    fn normalizes_angles() {
+        assert_relative_eq!(
+            normalize_angle(3.0 * PI / 2.0),
+            -FRAC_PI_2,
+            epsilon = EPSILON
+        );
+        assert_relative_eq!(
+            normalize_angle(-3.0 * PI / 2.0),
+            FRAC_PI_2,
+            epsilon = EPSILON
+        );
+        assert_relative_eq!(
+            angle_difference(170_f32.to_radians(), -170_f32.to_radians()),
+            20_f32.to_radians(),
+            epsilon = EPSILON
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::Pose2d;
+use crate::holonomic_controller::{MotionLimits, MoveCommand};
+
+fn default_integral_limit() -> f32 {
+    0.15
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PidGains {
+    pub kp: f32,
+    #[serde(default)]
+    pub ki: f32,
+    #[serde(default)]
+    pub kd: f32,
+    /// Largest contribution of the integral term to the output
+    ///
+    /// Has a nonzero default so that setting only `ki` takes effect.
+    #[serde(default = "default_integral_limit")]
+    pub integral_limit: f32,
+}
+
+impl PidGains {
+    pub fn new(kp: f32, ki: f32, kd: f32, integral_limit: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            integral_limit,
+        }
+    }
+}
+
+fn default_translation_gains() -> PidGains {
+    PidGains::new(3.0, 0.5, 0.1, default_integral_limit())
+}
+
+fn default_yaw_gains() -> PidGains {
+    PidGains::new(1.5, 0.3, 0.05, default_integral_limit())
+}
+
+/// Gains of the pose controller
+///
+/// `x` and `y` act on the position error in the robot frame, `yaw` on the heading error.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PoseControllerConfig {
+    #[serde(default = "default_translation_gains")]
+    pub x: PidGains,
+    #[serde(default = "default_translation_gains")]
+    pub y: PidGains,
+    #[serde(default = "default_yaw_gains")]
+    pub yaw: PidGains,
+}
+
+impl Default for PoseControllerConfig {
+    fn default() -> Self {
+        Self {
+            x: default_translation_gains(),
+            y: default_translation_gains(),
+            yaw: default_yaw_gains(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Pid {
+    integral: f32,
+    previous_error: Option<f32>,
+}
+
+impl Pid {
+    /// Output is saturated to `limit`
+    fn update(&mut self, gains: &PidGains, error: f32, dt: f32, limit: f32) -> f32 {
+        // skip the derivative on the first update to avoid a kick when a new target is set
+        let derivative = match self.previous_error {
+            Some(previous_error) if dt > 0.0 => (error - previous_error) / dt,
+            _ => 0.0,
+        };
+        self.previous_error = Some(error);
+
+        let output = |integral: f32| gains.kp * error + gains.ki * integral + gains.kd * derivative;
+        if gains.ki != 0.0 {
+            let integral_limit = (gains.integral_limit / gains.ki).abs();
+            let integral = (self.integral + error * dt).clamp(-integral_limit, integral_limit);
+            // don't wind up while the output is saturated
+            if output(integral).abs() <= limit || integral.abs() < self.integral.abs() {
+                self.integral = integral;
+            }
+        } else {
+            self.integral = 0.0;
+        }
+        output(self.integral).clamp(-limit, limit)
+    }
+}
+
+/// PID controller driving the robot towards a target pose
+pub struct PoseController {
+    config: PoseControllerConfig,
+    limits: MotionLimits,
+    x: Pid,
+    y: Pid,
+    yaw: Pid,
+}
+
+impl PoseController {
+    pub fn new(config: PoseControllerConfig, limits: MotionLimits) -> Self {
+        Self {
+            config,
+            limits,
+            x: Pid::default(),
+            y: Pid::default(),
+            yaw: Pid::default(),
+        }
+    }
+
+    pub fn config(&self) -> &PoseControllerConfig {
+        &self.config
+    }
+
+    /// Change gains without resetting controller state
+    pub fn set_config(&mut self, config: PoseControllerConfig) {
+        self.config = config;
+    }
+
+    /// Clear integral and derivative state
+    ///
+    /// Should be called whenever the target changes
+    pub fn reset(&mut self) {
+        self.x = Pid::default();
+        self.y = Pid::default();
+        self.yaw = Pid::default();
+    }
+
+    pub fn update(&mut self, current: &Pose2d, target: &Pose2d, dt: Duration) -> MoveCommand {
+        let dt = dt.as_secs_f32();
+        let error = current.inverse_transform_point(target.position());
+        let yaw_error = current.heading_error(target);
+
+        let command = MoveCommand::new(
+            self.x
+                .update(&self.config.x, error.x, dt, self.limits.max_forward),
+            self.y
+                .update(&self.config.y, error.y, dt, self.limits.max_strafe),
+            self.yaw
+                .update(&self.config.yaw, yaw_error, dt, self.limits.max_yaw),
+        );
+        self.limits.saturate(&command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::SimulatedBase;
+    use super::*;
+    use nalgebra as na;
+
+    const DT: Duration = Duration::from_millis(50);
+
+    /// Run the controller against the base and return the largest overshoot along x
+    fn run(controller: &mut PoseController, base: &mut SimulatedBase, target: &Pose2d) -> f32 {
+        let mut overshoot: f32 = 0.0;
+        for _ in 0..200 {
+            let command = controller.update(&base.pose, target, DT);
+            base.step(&command, DT);
+            overshoot = overshoot.max(base.pose.position().x - target.position().x);
+        }
+        overshoot
+    }
+
+    #[test]
+    fn forward_step_response_settles() {
+        let mut controller =
+            PoseController::new(PoseControllerConfig::default(), MotionLimits::default());
+        let mut base = SimulatedBase::new(Pose2d::new((0.0, 0.0), 0.0));
+        let target = Pose2d::new((1.0, 0.0), 0.0);
+        let overshoot = run(&mut controller, &mut base, &target);
+        assert!(overshoot < 0.05, "overshoot {}", overshoot);
+        assert!(
+            na::distance(base.pose.position(), target.position()) < 0.01,
+            "{}",
+            base.pose
+        );
+    }
+
+    #[test]
+    fn combined_step_response_settles() {
+        let mut controller =
+            PoseController::new(PoseControllerConfig::default(), MotionLimits::default());
+        let mut base = SimulatedBase::new(Pose2d::new((0.0, 0.0), 0.0));
+        let target = Pose2d::new((0.5, -0.8), 90_f32.to_radians());
+        run(&mut controller, &mut base, &target);
+        assert!(
+            na::distance(base.pose.position(), target.position()) < 0.01,
+            "{}",
+            base.pose
+        );
+        assert!(base.pose.rotation().angle_to(target.rotation()).abs() < 1_f32.to_radians());
+    }
+
+    #[test]
+    fn integral_overcomes_motor_deadband() {
+        let mut base = SimulatedBase::new(Pose2d::new((0.0, 0.0), 0.0));
+        base.motor_deadband = 0.1;
+        let target = Pose2d::new((0.5, 0.0), 0.0);
+
+        let proportional_only = PoseControllerConfig {
+            x: PidGains::new(3.0, 0.0, 0.0, 0.0),
+            ..Default::default()
+        };
+        let mut controller = PoseController::new(proportional_only, MotionLimits::default());
+        run(&mut controller, &mut base, &target);
+        let proportional_error = target.position().x - base.pose.position().x;
+        assert!(proportional_error > 0.02);
+
+        let mut controller =
+            PoseController::new(PoseControllerConfig::default(), MotionLimits::default());
+        run(&mut controller, &mut base, &target);
+        let pid_error = target.position().x - base.pose.position().x;
+        assert!(pid_error.abs() < 0.01, "error {}", pid_error);
+    }
+
+    #[test]
+    fn integral_is_limited() {
+        let gains = PidGains::new(0.0, 1.0, 0.0, 0.2);
+        let mut pid = Pid::default();
+        for _ in 0..1000 {
+            pid.update(&gains, 10.0, DT.as_secs_f32(), 1.0);
+        }
+        assert_eq!(pid.update(&gains, 0.0, DT.as_secs_f32(), 1.0), 0.2);
+    }
+
+    #[test]
+    fn integral_acts_when_limit_is_not_configured() {
+        let gains: PidGains = serde_json::from_str(r#"{"kp": 0.0, "ki": 1.0}"#).unwrap();
+        assert!(gains.integral_limit > 0.0);
+        let mut pid = Pid::default();
+        for _ in 0..10 {
+            pid.update(&gains, 1.0, DT.as_secs_f32(), 1.0);
+        }
+        assert!(pid.update(&gains, 0.0, DT.as_secs_f32(), 1.0) > 0.0);
+    }
+
+    #[test]
+    fn output_respects_motion_limits() {
+        let limits = MotionLimits {
+            max_forward: 0.3,
+            max_strafe: 0.6,
+            max_yaw: 0.6,
+        };
+        let mut controller = PoseController::new(PoseControllerConfig::default(), limits);
+        let command = controller.update(
+            &Pose2d::new((0.0, 0.0), 0.0),
+            &Pose2d::new((10.0, 10.0), 3.0),
+            DT,
+        );
+        assert!(command.forward().abs() <= 0.3);
+        let wheel_speed = command.forward().abs() + command.strafe().abs() + command.yaw().abs();
+        assert!(wheel_speed <= 1.0 + f32::EPSILON);
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use super::PoseMessage;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    /// No progress towards the target within the stuck timeout
+    Stuck,
+    /// No pose estimate within the stuck timeout
+    NotLocalised,
+    /// Planner found no route to the target
+    NoPath,
+}
+
+/// Published on every change
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum NavigationState {
+    #[default]
+    Idle,
+    UserControl,
+    Navigating {
+        target: PoseMessage,
+    },
+    /// Driving towards the target was refused by the collision detector or local planner
+    Blocked {
+        target: PoseMessage,
+    },
+    Arrived {
+        target: PoseMessage,
+    },
+    Failed {
+        target: PoseMessage,
+        reason: FailureReason,
+    },
+}
+
+/// Detects when the robot stops making progress towards its target
+#[derive(Debug, Clone)]
+pub struct ProgressMonitor {
+    best_distance: f32,
+    best_heading_error: f32,
+    last_progress: Instant,
+    last_pose: Instant,
+}
+
+impl ProgressMonitor {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            best_distance: f32::INFINITY,
+            best_heading_error: f32::INFINITY,
+            last_progress: now,
+            last_pose: now,
+        }
+    }
+
+    /// Record the current distance and absolute heading error to the target
+    ///
+    /// Improvements smaller than `min_progress` meters or `min_heading_progress` radians
+    /// aren't counted so that creeping or jittering doesn't look like progress.
+    pub fn update(
+        &mut self,
+        distance: f32,
+        heading_error: f32,
+        min_progress: f32,
+        min_heading_progress: f32,
+        now: Instant,
+    ) {
+        self.last_pose = now;
+        if distance < self.best_distance - min_progress {
+            self.best_distance = distance;
+            self.last_progress = now;
+        }
+        if heading_error < self.best_heading_error - min_heading_progress {
+            self.best_heading_error = heading_error;
+            self.last_progress = now;
+        }
+    }
+
+    pub fn failure(&self, timeout: Duration, now: Instant) -> Option<FailureReason> {
+        if now.duration_since(self.last_pose) > timeout {
+            Some(FailureReason::NotLocalised)
+        } else if now.duration_since(self.last_progress) > timeout {
+            Some(FailureReason::Stuck)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn progress_keeps_monitor_happy() {
+        let start = Instant::now();
+        let mut monitor = ProgressMonitor::new(start);
+        for i in 0..20 {
+            let now = start + Duration::from_secs(i);
+            monitor.update(10.0 - i as f32 * 0.1, 0.0, 0.05, 0.05, now);
+            assert_eq!(monitor.failure(TIMEOUT, now), None);
+        }
+    }
+
+    #[test]
+    fn jitter_is_not_progress() {
+        let start = Instant::now();
+        let mut monitor = ProgressMonitor::new(start);
+        for i in 0..10 {
+            let now = start + Duration::from_secs(i);
+            let jitter = if i % 2 == 0 { 0.01 } else { -0.01 };
+            monitor.update(1.0 + jitter, 0.0, 0.05, 0.05, now);
+        }
+        assert_eq!(
+            monitor.failure(TIMEOUT, start + Duration::from_secs(9)),
+            Some(FailureReason::Stuck)
+        );
+    }
+
+    #[test]
+    fn missing_pose_is_reported() {
+        let start = Instant::now();
+        let mut monitor = ProgressMonitor::new(start);
+        monitor.update(1.0, 0.0, 0.05, 0.05, start);
+        assert_eq!(
+            monitor.failure(TIMEOUT, start + Duration::from_secs(6)),
+            Some(FailureReason::NotLocalised)
+        );
+    }
+
+    #[test]
+    fn state_format() {
+        let state = NavigationState::Failed {
+            target: PoseMessage {
+                x: 1.0,
+                y: 0.0,
+                theta: 0.0,
+            },
+            reason: FailureReason::Stuck,
+        };
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(
+            json,
+            r#"{"state":"failed","target":{"x":1.0,"y":0.0,"theta":0.0},"reason":"stuck"}"#
+        );
+    }
+}
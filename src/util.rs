@@ -5,6 +5,18 @@ use std::sync::{
 };
 use tokio::sync::Notify;
 
+/// Check that a user supplied name is safe to use as a file name
+pub fn validate_file_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!("Invalid name {:?}", name));
+    }
+    Ok(())
+}
+
 pub fn latest_value_channel<T>() -> (LatestSender<T>, LatestReceiver<T>) {
     let value = Arc::new(Mutex::new(None));
     let notify = Arc::new(Notify::new());